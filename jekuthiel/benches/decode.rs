@@ -0,0 +1,163 @@
+// decoding the two packets that come in the largest numbers, once slicing strings out of the
+// frame the way s2c does now and once copying every string into its own Vec like it used to
+#![feature(test)]
+
+extern crate bytes;
+extern crate jekuthiel;
+extern crate test;
+extern crate tokio_io;
+
+use bytes::{BufMut, Bytes, BytesMut, LittleEndian};
+use jekuthiel::BNetPCodec;
+use jekuthiel::packets::{c2s, PacketID};
+use jekuthiel::packets::string::Encoding;
+use test::Bencher;
+use tokio_io::codec::Decoder;
+
+// the most games the realm sends in one list
+const GAMES: u32 = 20;
+
+fn game_list() -> Bytes {
+    let mut body = BytesMut::with_capacity(4 + GAMES as usize * 200);
+    body.put_u32::<LittleEndian>(GAMES);
+    for i in 0..GAMES {
+        body.put_u32::<LittleEndian>(0x0000_0001);
+        body.put_u32::<LittleEndian>(0);
+        // sockaddr_in
+        body.put_u16::<LittleEndian>(2);
+        body.put_slice(&[0x17, 0xe0, 10, 0, 0, i as u8]);
+        body.put_slice(&[0; 8]);
+        body.put_u32::<LittleEndian>(0x10);
+        body.put_u32::<LittleEndian>(i * 10);
+        body.put_slice(format!("DotA v6.83d -apem #{}\0", i).as_bytes());
+        body.put_slice(b"\0");
+        // an encoded statstring is about this long, and never contains a null
+        body.put_slice(&[0x61; 110]);
+        body.put_slice(b"\0");
+    }
+    c2s::raw(PacketID::GETADVLISTEX.id(), &body)
+}
+
+fn chat_event() -> Bytes {
+    let mut body = BytesMut::with_capacity(24 + 128);
+    body.put_u32::<LittleEndian>(0x05);
+    body.put_slice(&[0; 20]);
+    body.put_slice(b"SomePlayer#2\0");
+    body.put_slice(b"anyone up for a game of dota? join my lobby, -apem, no noobs please\0");
+    c2s::raw(PacketID::CHATEVENT.id(), &body)
+}
+
+// a read buffer the way the socket fills it, with many frames back to back
+fn read_buffer(frame: &Bytes, count: usize) -> Bytes {
+    let mut buf = BytesMut::with_capacity(frame.len() * count);
+    for _ in 0..count {
+        buf.put_slice(frame);
+    }
+    buf.freeze()
+}
+
+fn decode_sliced(data: &Bytes) {
+    let mut codec = BNetPCodec::new(Encoding::Utf8);
+    let mut buf = BytesMut::from(&data[..]);
+    while let Some(packet) = codec.decode(&mut buf).unwrap() {
+        test::black_box(packet);
+    }
+}
+
+// framed the same way BNetPCodec does it, only the body is read differently
+fn decode_copied<T, F: Fn(&[u8]) -> T>(data: &Bytes, read: F) {
+    let mut buf = BytesMut::from(&data[..]);
+    while !buf.is_empty() {
+        let length = (buf[2] as usize) | ((buf[3] as usize) << 8);
+        let mut frame = buf.split_to(length);
+        frame.advance(4);
+        test::black_box(read(&frame.freeze()));
+    }
+}
+
+// what s2c did before strings were sliced out of the frame
+#[allow(dead_code)]
+mod copying {
+    use bytes::{Buf, LittleEndian};
+    use std::io::Cursor;
+
+    pub struct Item {
+        pub header: [u32; 8],
+        pub game_name: Vec<u8>,
+        pub game_password: Vec<u8>,
+        pub game_statstring: Vec<u8>
+    }
+
+    pub struct ChatEvent {
+        pub header: [u32; 6],
+        pub username: Vec<u8>,
+        pub text: Vec<u8>
+    }
+
+    fn read_cstring(buf: &mut Cursor<&[u8]>) -> Vec<u8> {
+        let length = buf.bytes().iter().position(|&c| c == 0).unwrap();
+        let string = buf.bytes()[..length].to_vec();
+        buf.advance(length + 1);
+        string
+    }
+
+    pub fn game_list(body: &[u8]) -> Vec<Item> {
+        let mut buf = Cursor::new(body);
+        let count = buf.get_u32::<LittleEndian>();
+        let mut games = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut header = [0u32; 8];
+            for x in header.iter_mut() {
+                *x = buf.get_u32::<LittleEndian>();
+            }
+            games.push(Item {
+                header,
+                game_name: read_cstring(&mut buf),
+                game_password: read_cstring(&mut buf),
+                game_statstring: read_cstring(&mut buf)
+            });
+        }
+        games
+    }
+
+    pub fn chat_event(body: &[u8]) -> ChatEvent {
+        let mut buf = Cursor::new(body);
+        let mut header = [0u32; 6];
+        for x in header.iter_mut() {
+            *x = buf.get_u32::<LittleEndian>();
+        }
+        ChatEvent {
+            header,
+            username: read_cstring(&mut buf),
+            text: read_cstring(&mut buf)
+        }
+    }
+}
+
+#[bench]
+fn get_adv_list_ex_sliced(b: &mut Bencher) {
+    let data = read_buffer(&game_list(), 10);
+    b.bytes = data.len() as u64;
+    b.iter(|| decode_sliced(&data));
+}
+
+#[bench]
+fn get_adv_list_ex_copied(b: &mut Bencher) {
+    let data = read_buffer(&game_list(), 10);
+    b.bytes = data.len() as u64;
+    b.iter(|| decode_copied(&data, copying::game_list));
+}
+
+#[bench]
+fn chat_event_sliced(b: &mut Bencher) {
+    let data = read_buffer(&chat_event(), 100);
+    b.bytes = data.len() as u64;
+    b.iter(|| decode_sliced(&data));
+}
+
+#[bench]
+fn chat_event_copied(b: &mut Bencher) {
+    let data = read_buffer(&chat_event(), 100);
+    b.bytes = data.len() as u64;
+    b.iter(|| decode_copied(&data, copying::chat_event));
+}
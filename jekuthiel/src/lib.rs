@@ -7,9 +7,13 @@ extern crate bytes;
 extern crate tokio_core;
 extern crate tokio_io;
 extern crate futures;
+extern crate crc;
+extern crate flate2;
 
 pub mod packets;
 pub mod bindings;
+pub mod replay;
+pub mod w3gs;
 
 use futures::*;
 use tokio_io::AsyncRead;
@@ -25,19 +29,60 @@ use packets::BNetIncomingPacket;
 use packets::BNetOutgoingPacket;
 use packets::s2c::PacketReader;
 use packets::PacketID;
+use packets::string::Encoding;
 
-use bytes::{BytesMut, Bytes, Buf, BufMut};
+use bytes::{BytesMut, Bytes, BufMut};
+
+const BNET_HEADER: u8 = 0xff;
+const BNET_HEADER_LENGTH: usize = 4;
 
 enum DecodeState {
     Header,
-    Body(PacketID, usize)
+    Body(u8, usize)
 }
 
-struct BNetPCodec {
-    state: DecodeState
+// strings come out as raw bytes, whatever reads them goes by the connection's encoding
+pub struct BNetPCodec {
+    state: DecodeState,
+    // text encoding used by the realm on the other end of this connection
+    encoding: Encoding
 }
 
-impl<R: Buf> PacketReader<R> for BNetPCodec {
+impl PacketReader for BNetPCodec {
+}
+
+impl BNetPCodec {
+    pub fn new(encoding: Encoding) -> BNetPCodec {
+        BNetPCodec {
+            state: DecodeState::Header,
+            encoding
+        }
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    fn read_body(&self, id: u8, body: Bytes) -> io::Result<BNetIncomingPacket> {
+        let mut buf = io::Cursor::new(body);
+
+        let packet = match PacketID::from_id(id) {
+            Some(PacketID::NULL) => BNetIncomingPacket::Null,
+            Some(PacketID::GETADVLISTEX) => BNetIncomingPacket::GetAdvListEx(Self::read_get_adv_list_ex(&mut buf)?),
+            Some(PacketID::ENTERCHAT) => BNetIncomingPacket::EnterChat(Self::read_enter_chat(&mut buf)?),
+            Some(PacketID::CHATEVENT) => BNetIncomingPacket::ChatEvent(Self::read_chat_event(&mut buf)?),
+            Some(PacketID::STARTADVEX3) => BNetIncomingPacket::StartAdvEx3(Self::read_start_adv_ex3(&mut buf)?),
+            Some(PacketID::PING) => BNetIncomingPacket::Ping(Self::read_ping(&mut buf)?),
+            Some(PacketID::AUTHINFO) => BNetIncomingPacket::AuthInfo(Self::read_auth_info(&mut buf)?),
+            Some(PacketID::AUTHCHECK) => BNetIncomingPacket::AuthCheck(Self::read_auth_check(&mut buf)?),
+            Some(PacketID::AUTHACCOUNTLOGON) => BNetIncomingPacket::AuthAccountLogon(Self::read_auth_account_logon(&mut buf)?),
+            Some(PacketID::AUTHACCOUNTLOGONPROOF) => BNetIncomingPacket::AuthAccountLogonProof(Self::read_auth_account_logon_proof(&mut buf)?),
+            // either an id we don't know, or a client-only id we should never receive
+            _ => BNetIncomingPacket::Unknown { id, body: buf.into_inner() }
+        };
+
+        Ok(packet)
+    }
 }
 
 impl Encoder for BNetPCodec {
@@ -57,14 +102,22 @@ impl Decoder for BNetPCodec {
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         if let DecodeState::Header = self.state {
-            if src.len() < 4 {
+            if src.len() < BNET_HEADER_LENGTH {
                 return Ok(None);
             }
 
+            if src[0] != BNET_HEADER {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid BNCS header"));
+            }
+
             // deref first to allow reborrow
             let mut buf = io::Cursor::new(&mut *src);
             let (id, length) = self.read_header(&mut buf);
 
+            if length < BNET_HEADER_LENGTH {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid BNCS packet length"));
+            }
+
             self.state = DecodeState::Body(id, length);
         }
 
@@ -72,11 +125,15 @@ impl Decoder for BNetPCodec {
             if src.len() < length {
                 return Ok(None);
             }
-        
-            let src = src.split_to(length);
+
+            let mut frame = src.split_to(length);
+            frame.advance(BNET_HEADER_LENGTH);
+            self.state = DecodeState::Header;
+
+            return self.read_body(id, frame.freeze()).map(Some);
         }
 
-        Ok(Some(BNetIncomingPacket{lol: 0u8}))
+        Ok(None)
     }
 }
 
@@ -94,4 +151,115 @@ pub fn test() {
     });
 
     core.run(a).unwrap();
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use packets::c2s;
+
+    fn decode(data: &[u8]) -> io::Result<Option<BNetIncomingPacket>> {
+        BNetPCodec::new(Encoding::Utf8).decode(&mut BytesMut::from(data))
+    }
+
+    // the smallest valid body for every packet the realm sends us
+    fn bodies() -> Vec<(PacketID, Vec<u8>)> {
+        vec![
+            (PacketID::NULL, vec![]),
+            (PacketID::GETADVLISTEX, vec![0; 8]),
+            (PacketID::ENTERCHAT, vec![0; 3]),
+            (PacketID::CHATEVENT, vec![0; 26]),
+            (PacketID::STARTADVEX3, vec![0; 4]),
+            (PacketID::PING, vec![0; 4]),
+            (PacketID::AUTHINFO, vec![0; 150]),
+            (PacketID::AUTHCHECK, vec![0; 5]),
+            (PacketID::AUTHACCOUNTLOGON, vec![0; 68]),
+            (PacketID::AUTHACCOUNTLOGONPROOF, vec![0; 25])
+        ]
+    }
+
+    #[test]
+    fn decodes_every_server_packet() {
+        for (id, body) in bodies() {
+            let packet = decode(&c2s::raw(id.id(), &body)).unwrap().unwrap();
+            assert_eq!(packet.id(), id.id());
+            if let BNetIncomingPacket::Unknown { .. } = packet {
+                panic!("{:?} decoded as unknown", id);
+            }
+        }
+    }
+
+    #[test]
+    fn client_only_ids_are_kept() {
+        for &id in [PacketID::STOPADV, PacketID::JOINCHANNEL, PacketID::CHATCOMMAND, PacketID::NETGAMEPORT].iter() {
+            match decode(&c2s::raw(id.id(), b"body")).unwrap().unwrap() {
+                BNetIncomingPacket::Unknown { id: raw_id, ref body } => {
+                    assert_eq!(raw_id, id.id());
+                    assert_eq!(&body[..], b"body");
+                }
+                _ => panic!("{:?} should be unknown", id)
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_packets_are_preserved() {
+        let frame = c2s::raw(0x77, &[1, 2, 3, 0, 4]);
+
+        match decode(&frame).unwrap().unwrap() {
+            BNetIncomingPacket::Unknown { id, body } => {
+                assert_eq!(id, 0x77);
+                assert_eq!(&body[..], &[1, 2, 3, 0, 4]);
+                // forwarding it gives back the exact frame
+                assert_eq!(c2s::raw(id, &body), frame);
+            }
+            _ => panic!("0x77 should be unknown")
+        }
+    }
+
+    #[test]
+    fn waits_for_the_whole_frame() {
+        let frame = c2s::raw(PacketID::PING.id(), &[1, 2, 3, 4]);
+        let mut codec = BNetPCodec::new(Encoding::Utf8);
+        let mut buf = BytesMut::from(&frame[..6]);
+
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(&frame[6..]);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().id(), PacketID::PING.id());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let kind = |data: &[u8]| decode(data).err().map(|e| e.kind());
+
+        assert_eq!(kind(&[0xf7, 0x25, 8, 0, 0, 0, 0, 0]), Some(io::ErrorKind::InvalidData));
+        for length in 0..4 {
+            assert_eq!(kind(&[0xff, 0x25, length, 0]), Some(io::ErrorKind::InvalidData));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_bodies() {
+        for (id, body) in bodies() {
+            if body.is_empty() || id == PacketID::ENTERCHAT {
+                continue;
+            }
+            // strings may run to the end of the frame, so only cut into the fixed size fields
+            let cut = match id {
+                PacketID::CHATEVENT => 23,
+                PacketID::AUTHINFO => 147,
+                PacketID::AUTHCHECK => 3,
+                PacketID::AUTHACCOUNTLOGONPROOF => 23,
+                _ => body.len() - 1
+            };
+            assert!(decode(&c2s::raw(id.id(), &body[..cut])).is_err(), "{:?}", id);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_game_lists() {
+        // claims two games but carries none
+        let body = [2, 0, 0, 0];
+        assert!(decode(&c2s::raw(PacketID::GETADVLISTEX.id(), &body)).is_err());
+    }
+}
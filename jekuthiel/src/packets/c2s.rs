@@ -2,6 +2,9 @@
 
 use bytes::*;
 use super::*;
+use super::string::BnetString;
+
+use std::cmp;
 
 type E = LittleEndian;
 
@@ -21,23 +24,102 @@ fn recalc_length(buf: &mut BytesMut) {
     buf[3] = (length >> 8) as u8;
 }
 
-fn null() -> Bytes {
+// re-frames a body under an arbitrary id, used for forwarding packets we don't understand
+pub fn raw(id: u8, body: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(BNET_HEADER_LENGTH as usize + body.len());
+    buf.put(BNET_HEADER);
+    buf.put(id);
+    buf.put_u16::<E>(BNET_HEADER_LENGTH + body.len() as u16);
+    buf.put(body);
+    buf.freeze()
+}
+
+pub fn null() -> Bytes {
     new_packet(PacketID::NULL, 0).freeze()
 }
 
-fn stop_adv() -> Bytes {
+pub fn stop_adv() -> Bytes {
     new_packet(PacketID::STOPADV, 0).freeze()
 }
 
-const GETADVLISTEX_DATA: [u8; 15] = [255, 3, 0, 0, 255, 3, 0, 0, 0, 0, 0, 1, 0, 0, 0];
-fn get_adv_list_ex(game_name: &[u8]) -> Bytes {
+// defaults match what the WC3 client sends when browsing custom games
+const GETADVLISTEX_GAME_TYPE_ALL: u16 = 0x03ff;
+const GETADVLISTEX_VIEWING_FILTER_ALL: u32 = 0x03ff;
+// the realm never lists more games than this in one reply
+pub const GETADVLISTEX_MAX_GAMES: u32 = 20;
+
+#[derive(Clone, Debug)]
+pub struct GameListQuery {
+    game_type: u16,
+    sub_game_type: u16,
+    viewing_filter: u32,
+    count: u32,
+    game_name: Option<BnetString>
+}
+
+impl Default for GameListQuery {
+    fn default() -> Self {
+        GameListQuery {
+            game_type: GETADVLISTEX_GAME_TYPE_ALL,
+            sub_game_type: 0,
+            viewing_filter: GETADVLISTEX_VIEWING_FILTER_ALL,
+            count: GETADVLISTEX_MAX_GAMES,
+            game_name: None
+        }
+    }
+}
+
+impl GameListQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn game_type(mut self, game_type: u16) -> Self {
+        self.game_type = game_type;
+        self
+    }
+
+    pub fn sub_game_type(mut self, sub_game_type: u16) -> Self {
+        self.sub_game_type = sub_game_type;
+        self
+    }
+
+    pub fn viewing_filter(mut self, viewing_filter: u32) -> Self {
+        self.viewing_filter = viewing_filter;
+        self
+    }
+
+    pub fn count(mut self, count: u32) -> Self {
+        self.count = count;
+        self
+    }
+
+    // the most games a reply to this query can hold
+    pub fn max_games(&self) -> u32 {
+        cmp::min(self.count, GETADVLISTEX_MAX_GAMES)
+    }
+
+    // looks up a single game by name, the realm ignores the filters in that case
+    pub fn game_name(mut self, game_name: BnetString) -> Self {
+        self.game_name = Some(game_name);
+        self
+    }
+}
+
+pub fn get_adv_list_ex(query: &GameListQuery) -> Bytes {
+    let game_name = query.game_name.as_ref().map(|x| x.as_bytes()).unwrap_or(&[]);
     // allocate 3 additional bytes for null-terminators
-    let capacity = GETADVLISTEX_DATA.len() + game_name.len() + 3;
+    let capacity = 2 + 2 + 4 + 4 + 4 + game_name.len() + 3;
     let mut buf = new_packet(PacketID::GETADVLISTEX, capacity);
-    buf.put(Bytes::from_static(&GETADVLISTEX_DATA));
+    buf.put_u16::<E>(query.game_type);
+    buf.put_u16::<E>(query.sub_game_type);
+    buf.put_u32::<E>(query.viewing_filter);
+    // reserved
+    buf.put_u32::<E>(0);
+    buf.put_u32::<E>(query.count);
     buf.put(game_name);
 
-    // put in null terminators
+    // put in null terminators, password and statstring are left empty
     for _ in 0..3 {
         buf.put(0u8)
     }
@@ -45,7 +127,7 @@ fn get_adv_list_ex(game_name: &[u8]) -> Bytes {
     buf.freeze()
 }
 
-fn enter_chat() -> Bytes {
+pub fn enter_chat() -> Bytes {
     let mut buf = new_packet(PacketID::ENTERCHAT, 2);
     buf.put(0u8);
     buf.put(0u8);
@@ -58,23 +140,23 @@ pub enum JoinChannelFlag {
     ForcedJoin = 0x02,
 } 
 
-fn join_channel(flag: JoinChannelFlag, channel: &[u8]) -> Bytes {
+pub fn join_channel(flag: JoinChannelFlag, channel: &BnetString) -> Bytes {
     let mut buf = new_packet(PacketID::JOINCHANNEL, 4 + channel.len() + 1);
     buf.put_u32::<E>(flag as u32);
-    buf.put(channel);
+    buf.put(channel.as_bytes());
     buf.put(0u8);
     buf.freeze()
 }
 
-fn chat_command(message: &[u8]) -> Bytes {
+pub fn chat_command(message: &BnetString) -> Bytes {
     let mut buf = new_packet(PacketID::CHATCOMMAND, message.len() + 1);
-    buf.put(message);
+    buf.put(message.as_bytes());
     buf.put(0u8);
     buf.freeze()
 }
 
 #[derive(Copy, Clone)]
-enum StartAdvEx3GameState {
+pub enum StartAdvEx3GameState {
     Private = 0x01,
     Full = 0x02,
     NotEmpty = 0x04,
@@ -82,14 +164,14 @@ enum StartAdvEx3GameState {
     Replay = 0x80
 }
 
-fn start_adv_ex3(state: &[StartAdvEx3GameState], 
+pub fn start_adv_ex3(state: &[StartAdvEx3GameState], 
                 since_creation: u32, 
                 game_type: u16, 
                 sub_game_type: u16,
                 version: u32, 
                 ladder_type: u32,
-                game_name: &[u8],
-                game_password: &[u8],
+                game_name: &BnetString,
+                game_password: &BnetString,
                 game_statstring: &[u8]) -> Bytes {
     let state = state.iter().fold(0, |acc, &x| acc | x as u32);
     let mut buf = new_packet(PacketID::STARTADVEX3, 4 + 4 + 2 + 2 + 4 + 4 + game_name.len() + game_password.len() + game_statstring.len() + 3);
@@ -100,9 +182,9 @@ fn start_adv_ex3(state: &[StartAdvEx3GameState],
     buf.put_u16::<E>(sub_game_type);
     buf.put_u32::<E>(version);
     buf.put_u32::<E>(ladder_type);
-    buf.put(game_name);
+    buf.put(game_name.as_bytes());
     buf.put(0u8);
-    buf.put(game_password);
+    buf.put(game_password.as_bytes());
     buf.put(0u8);
     buf.put(game_statstring);
     buf.put(0u8);
@@ -110,13 +192,13 @@ fn start_adv_ex3(state: &[StartAdvEx3GameState],
     buf.freeze()
 }
 
-fn ping(value: u32) -> Bytes {
+pub fn ping(value: u32) -> Bytes {
     let mut buf = new_packet(PacketID::PING, 4);
     buf.put_u32::<E>(value);
     buf.freeze()
 }
 
-fn net_game_port(port: u16) -> Bytes {
+pub fn net_game_port(port: u16) -> Bytes {
     let mut buf = new_packet(PacketID::NETGAMEPORT, 2);
     buf.put_u16::<E>(port);
     buf.freeze()
@@ -142,7 +224,7 @@ const PRODUCT_ID: [u8; 4] = [80, 88, 51, 87]; // W3XP
 const LANGUAGE: [u8; 4] = [83, 85, 110, 101]; // enUS
 const LOCAL_IP: [u8; 4] = [127, 0, 0, 1];
 const TIMEZONE_BIAS: [u8; 4] = [60, 0, 0, 0];
-fn auth_info(version: u8, locale_id: u32, country: &[u8], country_abbr: &[u8]) -> Bytes {
+pub fn auth_info(version: u8, locale_id: u32, country: &[u8], country_abbr: &[u8]) -> Bytes {
     let mut buf = new_packet(PacketID::AUTHINFO, 4 * 9 + country.len() + country_abbr.len() + 2);
     let version: [u8; 4] = [version, 0, 0, 0];
 
@@ -163,13 +245,13 @@ fn auth_info(version: u8, locale_id: u32, country: &[u8], country_abbr: &[u8]) -
     buf.freeze()
 }
 
-fn auth_check(client_token: u32, 
+pub fn auth_check(client_token: u32, 
               exe_version: u32, 
               exe_hash: u32, 
               roc_key: &[u8], 
               tft_key: &[u8], 
               exe_info: &[u8],
-              owner_name: &[u8]) -> Bytes {
+              owner_name: &BnetString) -> Bytes {
     let mut buf = new_packet(PacketID::AUTHCHECK, 4 * 5 + roc_key.len() + tft_key.len() + exe_info.len() + owner_name.len() + 2);
     buf.put_u32::<E>(client_token);
     buf.put_u32::<E>(exe_version);
@@ -182,20 +264,20 @@ fn auth_check(client_token: u32,
     buf.put(tft_key);
     buf.put(exe_info);
     buf.put(0u8);
-    buf.put(owner_name);
+    buf.put(owner_name.as_bytes());
     buf.put(0u8);
     buf.freeze()
 }
 
-fn account_logon(client_key: u8, username: &[u8]) -> Bytes {
+pub fn account_logon(client_key: u8, username: &BnetString) -> Bytes {
     let mut buf = new_packet(PacketID::AUTHACCOUNTLOGON, 1 + username.len() + 1);
     buf.put(client_key);
-    buf.put(username);
+    buf.put(username.as_bytes());
     buf.put(0u8);
     buf.freeze()
 }
 
-fn account_logon_proof(proof: &[u8]) -> Bytes {
+pub fn account_logon_proof(proof: &[u8]) -> Bytes {
     let mut buf = new_packet(PacketID::AUTHACCOUNTLOGONPROOF, proof.len());
     buf.put(proof);
     buf.freeze()
@@ -3,12 +3,13 @@
 
 pub mod c2s;
 pub mod s2c;
+pub mod statstring;
+pub mod string;
 
 use bytes::Bytes;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PacketID {
-    UNKNOWN                = -1,
     NULL                   = 0,   // 0x0
     STOPADV                = 2,   // 0x2
     GETADVLISTEX           = 9,   // 0x9
@@ -26,30 +27,102 @@ pub enum PacketID {
 }
 
 impl PacketID {
-    pub fn from_id(id: u8) -> PacketID {
+    // returns None for ids we don't know about, callers should keep the raw id around
+    pub fn from_id(id: u8) -> Option<PacketID> {
         match id {
-            0 => PacketID::NULL,
-            2 => PacketID::STOPADV,
-            9 => PacketID::GETADVLISTEX,
-            12 => PacketID::ENTERCHAT,
-            14 => PacketID::CHATCOMMAND,
-            15 => PacketID::CHATEVENT,
-            28 => PacketID::STARTADVEX3,
-            37 => PacketID::PING,
-            69 => PacketID::NETGAMEPORT,
-            80 => PacketID::AUTHINFO,
-            81 => PacketID::AUTHCHECK,
-            83 => PacketID::AUTHACCOUNTLOGON,
-            84 => PacketID::AUTHACCOUNTLOGONPROOF,
-            _ => PacketID::UNKNOWN
+            0 => Some(PacketID::NULL),
+            2 => Some(PacketID::STOPADV),
+            9 => Some(PacketID::GETADVLISTEX),
+            10 => Some(PacketID::ENTERCHAT),
+            12 => Some(PacketID::JOINCHANNEL),
+            14 => Some(PacketID::CHATCOMMAND),
+            15 => Some(PacketID::CHATEVENT),
+            28 => Some(PacketID::STARTADVEX3),
+            37 => Some(PacketID::PING),
+            69 => Some(PacketID::NETGAMEPORT),
+            80 => Some(PacketID::AUTHINFO),
+            81 => Some(PacketID::AUTHCHECK),
+            83 => Some(PacketID::AUTHACCOUNTLOGON),
+            84 => Some(PacketID::AUTHACCOUNTLOGONPROOF),
+            _ => None
         }
     }
+
+    pub fn id(self) -> u8 {
+        self as u8
+    }
 }
 
-pub struct BNetIncomingPacket {
-    pub lol: u8
+pub enum BNetIncomingPacket {
+    Null,
+    GetAdvListEx(s2c::GetAdvListEx),
+    EnterChat(s2c::EnterChat),
+    ChatEvent(s2c::ChatEvent),
+    StartAdvEx3(s2c::StartAdvEx3),
+    Ping(s2c::Ping),
+    AuthInfo(s2c::AuthInfo),
+    AuthCheck(s2c::AuthCheck),
+    AuthAccountLogon(s2c::AuthAccountLogon),
+    AuthAccountLogonProof(s2c::AuthAccountLogonProof),
+    // anything we can't parse yet, kept as-is so it can be logged or forwarded
+    Unknown {
+        id: u8,
+        body: Bytes
+    }
+}
+
+impl BNetIncomingPacket {
+    pub fn id(&self) -> u8 {
+        match *self {
+            BNetIncomingPacket::Null => PacketID::NULL.id(),
+            BNetIncomingPacket::GetAdvListEx(_) => PacketID::GETADVLISTEX.id(),
+            BNetIncomingPacket::EnterChat(_) => PacketID::ENTERCHAT.id(),
+            BNetIncomingPacket::ChatEvent(_) => PacketID::CHATEVENT.id(),
+            BNetIncomingPacket::StartAdvEx3(_) => PacketID::STARTADVEX3.id(),
+            BNetIncomingPacket::Ping(_) => PacketID::PING.id(),
+            BNetIncomingPacket::AuthInfo(_) => PacketID::AUTHINFO.id(),
+            BNetIncomingPacket::AuthCheck(_) => PacketID::AUTHCHECK.id(),
+            BNetIncomingPacket::AuthAccountLogon(_) => PacketID::AUTHACCOUNTLOGON.id(),
+            BNetIncomingPacket::AuthAccountLogonProof(_) => PacketID::AUTHACCOUNTLOGONPROOF.id(),
+            BNetIncomingPacket::Unknown { id, .. } => id
+        }
+    }
 }
 
 pub struct BNetOutgoingPacket {
     pub data: Bytes
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [PacketID; 14] = [
+        PacketID::NULL, PacketID::STOPADV, PacketID::GETADVLISTEX, PacketID::ENTERCHAT,
+        PacketID::JOINCHANNEL, PacketID::CHATCOMMAND, PacketID::CHATEVENT, PacketID::STARTADVEX3,
+        PacketID::PING, PacketID::NETGAMEPORT, PacketID::AUTHINFO, PacketID::AUTHCHECK,
+        PacketID::AUTHACCOUNTLOGON, PacketID::AUTHACCOUNTLOGONPROOF
+    ];
+
+    #[test]
+    fn packet_ids_round_trip() {
+        for &id in ALL.iter() {
+            assert_eq!(PacketID::from_id(id.id()), Some(id));
+        }
+    }
+
+    #[test]
+    fn enter_chat_is_0x0a() {
+        assert_eq!(PacketID::from_id(0x0A), Some(PacketID::ENTERCHAT));
+        assert_eq!(PacketID::from_id(0x0C), Some(PacketID::JOINCHANNEL));
+    }
+
+    #[test]
+    fn undefined_ids_are_none() {
+        for id in 0..256u32 {
+            let id = id as u8;
+            if !ALL.iter().any(|x| x.id() == id) {
+                assert_eq!(PacketID::from_id(id), None);
+            }
+        }
+    }
+}
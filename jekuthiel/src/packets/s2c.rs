@@ -1,24 +1,34 @@
 #![allow(dead_code)]
 
 use bytes::*;
-use super::*;
+use std::cmp;
+use std::io::{self, Cursor};
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use super::statstring::AdvertisedStat;
+use super::string::BnetString;
 
 pub struct GetAdvListEx {
-    count: u32,
-    status: GetAdvListExStatus
+    pub count: u32,
+    pub status: GetAdvListExStatus
 }
 
 pub struct GetAdvListExItem {
-    game_settings: u32,
-    language_id: u32,
-    address_family: u16,
-    address_port: u16,
-    host_ip: u32,
-    game_status: u32,
-    elapsed_time: u32,
-    game_name: Vec<u8>,
-    game_password: Vec<u8>,
-    game_statstring: Vec<u8>,
+    pub game_settings: u32,
+    pub language_id: u32,
+    pub address_family: u16,
+    pub address: SocketAddrV4,
+    pub game_status: u32,
+    pub elapsed_time: u32,
+    pub game_name: BnetString,
+    pub game_password: BnetString,
+    pub game_statstring: Bytes,
+}
+
+impl GetAdvListExItem {
+    pub fn stat(&self) -> Option<AdvertisedStat> {
+        AdvertisedStat::decode(&self.game_statstring)
+    }
 }
 
 pub enum GetAdvListExStatus {
@@ -27,9 +37,9 @@ pub enum GetAdvListExStatus {
 }
 
 pub struct EnterChat {
-    unique_name: Vec<u8>,
-    statstring: Vec<u8>,
-    account_name: Vec<u8>
+    unique_name: BnetString,
+    statstring: Bytes,
+    account_name: BnetString
 }
 
 #[derive(Copy, Clone)]
@@ -83,11 +93,11 @@ pub struct ChatEvent {
     event_id: ChatEventID,
     user_flags: u32,
     ping: u32,
-    username: Vec<u8>,
-    text: Vec<u8>
+    username: BnetString,
+    text: BnetString
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum StartAdvEx3Status {
     Ok = 0x00,
     Failed = 0x01,
@@ -105,7 +115,7 @@ impl StartAdvEx3Status {
 }
 
 pub struct StartAdvEx3 {
-    status: StartAdvEx3Status
+    pub status: StartAdvEx3Status
 }
 
 pub struct Ping {
@@ -117,14 +127,14 @@ pub struct AuthInfo {
     server_token: u32,
     udp_value: u32,
     mpq_filetime: u64,
-    mpq_filename: Vec<u8>,
-    value_string: Vec<u8>,
+    mpq_filename: Bytes,
+    value_string: Bytes,
     server_signature: [u8; 128]
 }
 
 pub struct AuthCheck {
     status: u32,
-    info: Vec<u8>
+    info: BnetString
 }
 
 pub struct AuthAccountLogon {
@@ -136,62 +146,100 @@ pub struct AuthAccountLogon {
 pub struct AuthAccountLogonProof {
     status: u32,
     proof: [u8; 20],
-    info: Vec<u8>
+    info: BnetString
 }
 
 type E = LittleEndian;
 
-pub trait PacketReader<R: Buf> {
-    fn read_header(&self, buf: &mut R) -> (PacketID, usize) {
+// packet bodies are always read out of a single frame, so strings can be sliced out of it
+// instead of copied
+type R = Cursor<Bytes>;
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "truncated BNCS packet")
+}
+
+// the realm decides how long a frame is, so make sure the fields we're about to read fit in it
+fn need(buf: &R, length: usize) -> io::Result<()> {
+    if buf.remaining() < length {
+        return Err(truncated());
+    }
+    Ok(())
+}
+
+// fixed size part of a game list entry, everything up to the strings
+const GETADVLISTEX_ITEM_LENGTH: usize = 32;
+
+pub trait PacketReader {
+    fn read_header<H: Buf>(&self, buf: &mut H) -> (u8, usize) {
         // discard protocol id
         buf.get_u8();
-        let id = PacketID::from_id(buf.get_u8());
+        let id = buf.get_u8();
         let length = buf.get_u16::<E>() as usize;
 
         (id, length)
     }
 
-    fn read_cstring(buf: &mut R) -> Vec<u8> {
-        let null_pos = buf.iter().position(|c| c == 0).unwrap();
-        let mut slice = Vec::with_capacity(null_pos);
-        buf.copy_to_slice(&mut slice);
+    fn read_cstring(buf: &mut R) -> Bytes {
+        let start = buf.position() as usize;
+        let remaining = buf.remaining();
+        // an unterminated string runs to the end of the frame
+        let length = buf.bytes().iter().position(|&c| c == 0).unwrap_or(remaining);
+        let slice = buf.get_ref().slice(start, start + length);
         // skip null byte
-        buf.advance(1);
+        buf.advance(::std::cmp::min(length + 1, remaining));
         slice
     }
 
+    fn read_string(buf: &mut R) -> BnetString {
+        BnetString::from_bytes_unchecked(Self::read_cstring(buf))
+    }
+
+    // sockaddr_in as the realm sends it, the family is little endian but port and address
+    // are in network byte order
+    fn read_sockaddr(buf: &mut R) -> io::Result<(u16, SocketAddrV4)> {
+        need(buf, 16)?;
+        let family = buf.get_u16::<E>();
+        let port = buf.get_u16::<BigEndian>();
+        let mut ip = [0u8; 4];
+        buf.copy_to_slice(&mut ip);
+        // sin_zero
+        buf.advance(8);
+
+        Ok((family, SocketAddrV4::new(Ipv4Addr::from(ip), port)))
+    }
+
     fn read_null(_: &mut R) {}
 
-    fn read_get_adv_list_ex(buf: &mut R) -> GetAdvListEx {
+    fn read_get_adv_list_ex(buf: &mut R) -> io::Result<GetAdvListEx> {
+        need(buf, 4)?;
         let count = buf.get_u32::<E>();
 
         if count == 0 {
-            return GetAdvListEx { 
+            need(buf, 4)?;
+            return Ok(GetAdvListEx { 
                 count : 0u32, 
                 status : GetAdvListExStatus::EMPTY(buf.get_u32::<E>())
-            }
+            })
         } else {
-            let mut games = Vec::with_capacity(count as usize);
+            // the count is only a claim, don't reserve more than the frame could hold
+            let mut games = Vec::with_capacity(cmp::min(count as usize, buf.remaining() / GETADVLISTEX_ITEM_LENGTH));
             for _ in 0..count {
+                need(buf, GETADVLISTEX_ITEM_LENGTH)?;
                 let game_settings = buf.get_u32::<E>();
                 let language_id = buf.get_u32::<E>();
-                let address_family = buf.get_u16::<E>();
-                let address_port = buf.get_u16::<E>();
-                let host_ip = buf.get_u32::<E>();
-                let _sin_zero = buf.get_u32::<E>();
-                let _sin_zero = buf.get_u32::<E>();
+                let (address_family, address) = Self::read_sockaddr(buf)?;
                 let game_status = buf.get_u32::<E>();
                 let elapsed_time = buf.get_u32::<E>();
-                let game_name = Self::read_cstring(buf);
-                let game_password = Self::read_cstring(buf);
+                let game_name = Self::read_string(buf);
+                let game_password = Self::read_string(buf);
                 let game_statstring = Self::read_cstring(buf);
 
                 games.push(GetAdvListExItem {
                     game_settings,
                     language_id,
                     address_family,
-                    address_port,
-                    host_ip,
+                    address,
                     game_status,
                     elapsed_time,
                     game_name,
@@ -200,57 +248,61 @@ pub trait PacketReader<R: Buf> {
                 });
             }
 
-            return GetAdvListEx {
+            return Ok(GetAdvListEx {
                 count : count,
                 status : GetAdvListExStatus::OK(games)
-            }
+            })
         }
     }
 
-    fn read_enter_chat(buf: &mut R) -> EnterChat {
-        let unique_name = Self::read_cstring(buf);
+    fn read_enter_chat(buf: &mut R) -> io::Result<EnterChat> {
+        let unique_name = Self::read_string(buf);
         let statstring = Self::read_cstring(buf);
-        let account_name = Self::read_cstring(buf);
+        let account_name = Self::read_string(buf);
 
-        EnterChat {
+        Ok(EnterChat {
             unique_name,
             statstring,
             account_name
-        }
+        })
     }
 
-    fn read_chat_event(buf: &mut R) -> ChatEvent {
+    fn read_chat_event(buf: &mut R) -> io::Result<ChatEvent> {
+        need(buf, 24)?;
         let event_id = ChatEventID::from_id(buf.get_u32::<LittleEndian>());
         let user_flags = buf.get_u32::<LittleEndian>();
         let ping = buf.get_u32::<LittleEndian>();
         let _ip = buf.get_u32::<LittleEndian>();
         let _acc = buf.get_u32::<LittleEndian>();
         let _auth = buf.get_u32::<LittleEndian>();
-        let username = Self::read_cstring(buf);
-        let text = Self::read_cstring(buf);
+        let username = Self::read_string(buf);
+        let text = Self::read_string(buf);
 
-        ChatEvent {
+        Ok(ChatEvent {
             event_id,
             user_flags,
             ping,
             username,
             text
-        }
+        })
     }
 
-    fn read_start_adv_ex3(buf: &mut R) -> StartAdvEx3 {
-        StartAdvEx3 {
+    fn read_start_adv_ex3(buf: &mut R) -> io::Result<StartAdvEx3> {
+        need(buf, 4)?;
+        Ok(StartAdvEx3 {
             status : StartAdvEx3Status::from_id(buf.get_u32::<LittleEndian>())
-        }
+        })
     }
 
-    fn read_ping(buf: &mut R) -> Ping {
-        Ping {
+    fn read_ping(buf: &mut R) -> io::Result<Ping> {
+        need(buf, 4)?;
+        Ok(Ping {
             value : buf.get_u32::<LittleEndian>()
-        }
+        })
     }
 
-    fn read_auth_info(buf: &mut R) -> AuthInfo {
+    fn read_auth_info(buf: &mut R) -> io::Result<AuthInfo> {
+        need(buf, 20)?;
         let logon_type = buf.get_u32::<LittleEndian>();
         let server_token = buf.get_u32::<LittleEndian>();
         let udp_value = buf.get_u32::<LittleEndian>();
@@ -258,9 +310,10 @@ pub trait PacketReader<R: Buf> {
         let mpq_filename = Self::read_cstring(buf);
         let value_string = Self::read_cstring(buf);
         let mut server_signature = [0u8; 128];
+        need(buf, 128)?;
         buf.take(128).copy_to_slice(&mut server_signature);
 
-        AuthInfo {
+        Ok(AuthInfo {
             logon_type,
             server_token,
             udp_value,
@@ -268,17 +321,19 @@ pub trait PacketReader<R: Buf> {
             mpq_filename,
             value_string,
             server_signature
-        }
+        })
     }
 
-    fn read_auth_check(buf: &mut R) -> AuthCheck {
-        AuthCheck {
+    fn read_auth_check(buf: &mut R) -> io::Result<AuthCheck> {
+        need(buf, 4)?;
+        Ok(AuthCheck {
             status: buf.get_u32::<LittleEndian>(),
-            info: Self::read_cstring(buf)
-        }
+            info: Self::read_string(buf)
+        })
     }
 
-    fn read_auth_account_logon(buf: &mut R) -> AuthAccountLogon {
+    fn read_auth_account_logon(buf: &mut R) -> io::Result<AuthAccountLogon> {
+        need(buf, 68)?;
         let status = buf.get_u32::<LittleEndian>();
         let mut salt = [0u8; 32];
         let mut server_key = [0u8; 32];
@@ -286,23 +341,24 @@ pub trait PacketReader<R: Buf> {
         buf.take(32).copy_to_slice(&mut salt);
         buf.take(32).copy_to_slice(&mut server_key);
 
-        AuthAccountLogon {
+        Ok(AuthAccountLogon {
             status,
             salt,
             server_key
-        }
+        })
     }
 
-    fn read_auth_account_logon_proof(buf: &mut R) -> AuthAccountLogonProof {
+    fn read_auth_account_logon_proof(buf: &mut R) -> io::Result<AuthAccountLogonProof> {
+        need(buf, 24)?;
         let status = buf.get_u32::<LittleEndian>();
         let mut proof = [0u8; 20];
         buf.take(20).copy_to_slice(&mut proof);
-        let info = Self::read_cstring(buf);
+        let info = Self::read_string(buf);
 
-        AuthAccountLogonProof {
+        Ok(AuthAccountLogonProof {
             status,
             proof,
             info
-        }
+        })
     }
 }
\ No newline at end of file
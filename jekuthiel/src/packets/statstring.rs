@@ -0,0 +1,388 @@
+// reference: https://bnetdocs.org/packet/268/sid-startadvex3 and GHost++'s statstring handling
+#![allow(dead_code)]
+
+use bytes::*;
+
+use std::io::Cursor;
+
+type E = LittleEndian;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameSpeed {
+    Slow = 0x00,
+    Normal = 0x01,
+    Fast = 0x02
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameVisibility {
+    HideTerrain = 0x0100,
+    MapExplored = 0x0200,
+    AlwaysVisible = 0x0400,
+    Default = 0x0800
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameObservers {
+    None = 0x0000_0000,
+    OnDefeat = 0x0000_2000,
+    Full = 0x0000_3000,
+    Referees = 0x4000_0000
+}
+
+const FLAG_TEAMS_TOGETHER: u32 = 0x0000_4000;
+const FLAG_LOCK_TEAMS: u32 = 0x0006_0000;
+const FLAG_SHARED_UNITS: u32 = 0x0100_0000;
+const FLAG_RANDOM_HERO: u32 = 0x0200_0000;
+const FLAG_RANDOM_RACES: u32 = 0x0400_0000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameFlags {
+    pub speed: GameSpeed,
+    pub visibility: GameVisibility,
+    pub observers: GameObservers,
+    pub teams_together: bool,
+    pub lock_teams: bool,
+    pub shared_units: bool,
+    pub random_hero: bool,
+    pub random_races: bool
+}
+
+impl Default for GameFlags {
+    fn default() -> Self {
+        GameFlags {
+            speed: GameSpeed::Fast,
+            visibility: GameVisibility::Default,
+            observers: GameObservers::None,
+            teams_together: true,
+            lock_teams: true,
+            shared_units: false,
+            random_hero: false,
+            random_races: false
+        }
+    }
+}
+
+impl GameFlags {
+    pub fn from_u32(flags: u32) -> Self {
+        let speed = match flags & 0x0f {
+            0x00 => GameSpeed::Slow,
+            0x01 => GameSpeed::Normal,
+            _ => GameSpeed::Fast
+        };
+
+        let visibility = match flags & 0x0f00 {
+            0x0100 => GameVisibility::HideTerrain,
+            0x0200 => GameVisibility::MapExplored,
+            0x0400 => GameVisibility::AlwaysVisible,
+            _ => GameVisibility::Default
+        };
+
+        let observers = if flags & GameObservers::Referees as u32 != 0 {
+            GameObservers::Referees
+        } else {
+            match flags & 0x3000 {
+                0x3000 => GameObservers::Full,
+                0x2000 => GameObservers::OnDefeat,
+                _ => GameObservers::None
+            }
+        };
+
+        GameFlags {
+            speed,
+            visibility,
+            observers,
+            teams_together: flags & FLAG_TEAMS_TOGETHER != 0,
+            lock_teams: flags & FLAG_LOCK_TEAMS == FLAG_LOCK_TEAMS,
+            shared_units: flags & FLAG_SHARED_UNITS != 0,
+            random_hero: flags & FLAG_RANDOM_HERO != 0,
+            random_races: flags & FLAG_RANDOM_RACES != 0
+        }
+    }
+
+    pub fn to_u32(&self) -> u32 {
+        let mut flags = self.speed as u32 | self.visibility as u32 | self.observers as u32;
+
+        if self.teams_together {
+            flags |= FLAG_TEAMS_TOGETHER;
+        }
+        if self.lock_teams {
+            flags |= FLAG_LOCK_TEAMS;
+        }
+        if self.shared_units {
+            flags |= FLAG_SHARED_UNITS;
+        }
+        if self.random_hero {
+            flags |= FLAG_RANDOM_HERO;
+        }
+        if self.random_races {
+            flags |= FLAG_RANDOM_RACES;
+        }
+
+        flags
+    }
+}
+
+// the decoded contents of a statstring, describing the map a game is played on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameStat {
+    pub flags: GameFlags,
+    pub map_width: u16,
+    pub map_height: u16,
+    pub map_crc: u32,
+    pub map_path: Bytes,
+    pub host_name: Bytes,
+    // absent before 1.23
+    pub map_sha1: Option<[u8; 20]>
+}
+
+impl GameStat {
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(4 + 1 + 2 + 2 + 4 + self.map_path.len() + 1 + self.host_name.len() + 1 + 1 + 20);
+        buf.put_u32::<E>(self.flags.to_u32());
+        buf.put(0u8);
+        buf.put_u16::<E>(self.map_width);
+        buf.put_u16::<E>(self.map_height);
+        buf.put_u32::<E>(self.map_crc);
+        buf.put(&self.map_path);
+        buf.put(0u8);
+        buf.put(&self.host_name);
+        buf.put(0u8);
+        buf.put(0u8);
+        if let Some(ref sha1) = self.map_sha1 {
+            buf.put_slice(sha1);
+        }
+
+        encode_odd(&buf)
+    }
+
+    pub fn decode(encoded: &[u8]) -> Option<GameStat> {
+        let data = decode_odd(encoded);
+        let mut buf = Cursor::new(data.freeze());
+
+        if buf.remaining() < 4 + 1 + 2 + 2 + 4 {
+            return None;
+        }
+
+        let flags = GameFlags::from_u32(buf.get_u32::<E>());
+        buf.get_u8();
+        let map_width = buf.get_u16::<E>();
+        let map_height = buf.get_u16::<E>();
+        let map_crc = buf.get_u32::<E>();
+        let map_path = read_cstring(&mut buf)?;
+        let host_name = read_cstring(&mut buf)?;
+
+        if buf.has_remaining() {
+            buf.get_u8();
+        }
+
+        let map_sha1 = if buf.remaining() >= 20 {
+            let mut sha1 = [0u8; 20];
+            buf.copy_to_slice(&mut sha1);
+            Some(sha1)
+        } else {
+            None
+        };
+
+        Some(GameStat {
+            flags,
+            map_width,
+            map_height,
+            map_crc,
+            map_path,
+            host_name,
+            map_sha1
+        })
+    }
+}
+
+// the full statstring field as sent in STARTADVEX3 and received in GETADVLISTEX:
+// a free slot count, the host counter as reversed hex and then the encoded GameStat
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AdvertisedStat {
+    pub free_slots: u8,
+    pub host_counter: u32,
+    pub stat: GameStat
+}
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+impl AdvertisedStat {
+    pub fn encode(&self) -> Bytes {
+        let stat = self.stat.encode();
+        let mut buf = BytesMut::with_capacity(1 + 8 + stat.len());
+
+        // free slots is stored as a hex digit, for whatever reason
+        buf.put(HEX_DIGITS[(self.free_slots & 0x0f) as usize]);
+        // host counter is written as 8 hex digits in reverse order
+        for i in 0..8 {
+            buf.put(HEX_DIGITS[((self.host_counter >> (i * 4)) & 0x0f) as usize]);
+        }
+        buf.put(stat);
+
+        buf.freeze()
+    }
+
+    pub fn decode(data: &[u8]) -> Option<AdvertisedStat> {
+        if data.len() < 9 {
+            return None;
+        }
+
+        let free_slots = hex_value(data[0])?;
+        let mut host_counter = 0u32;
+        for (i, &c) in data[1..9].iter().enumerate() {
+            host_counter |= (hex_value(c)? as u32) << (i * 4);
+        }
+
+        Some(AdvertisedStat {
+            free_slots,
+            host_counter,
+            stat: GameStat::decode(&data[9..])?
+        })
+    }
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'...b'9' => Some(c - b'0'),
+        b'a'...b'f' => Some(c - b'a' + 10),
+        b'A'...b'F' => Some(c - b'A' + 10),
+        _ => None
+    }
+}
+
+fn read_cstring(buf: &mut Cursor<Bytes>) -> Option<Bytes> {
+    let start = buf.position() as usize;
+    let length = buf.bytes().iter().position(|&c| c == 0)?;
+    let slice = buf.get_ref().slice(start, start + length);
+    buf.advance(length + 1);
+    Some(slice)
+}
+
+// every 7 bytes are prefixed with a mask byte, even bytes are incremented and get their bit
+// in the mask cleared so that the result never contains a null
+pub fn encode_odd(data: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(data.len() + data.len() / 7 + 1);
+
+    for chunk in data.chunks(7) {
+        let mut mask = 1u8;
+        for (i, &c) in chunk.iter().enumerate() {
+            if c % 2 != 0 {
+                mask |= 1 << (i + 1);
+            }
+        }
+
+        buf.put(mask);
+        for &c in chunk {
+            buf.put(if c % 2 == 0 { c + 1 } else { c });
+        }
+    }
+
+    buf.freeze()
+}
+
+pub fn decode_odd(data: &[u8]) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(data.len());
+
+    for chunk in data.chunks(8) {
+        let mask = chunk[0];
+        for (i, &c) in chunk[1..].iter().enumerate() {
+            buf.put(if mask & (1 << (i + 1)) == 0 { c.wrapping_sub(1) } else { c });
+        }
+    }
+
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat(map_sha1: Option<[u8; 20]>) -> GameStat {
+        GameStat {
+            flags: GameFlags::default(),
+            map_width: 116,
+            map_height: 84,
+            map_crc: 0x1122_3344,
+            map_path: Bytes::from(&b"Maps\\Download\\DotA v6.83d.w3x"[..]),
+            host_name: Bytes::from(&b"tabeal"[..]),
+            map_sha1
+        }
+    }
+
+    #[test]
+    fn odd_encoding_known_bytes() {
+        assert_eq!(&encode_odd(&[0x02, 0x03, 0x00, 0xff])[..], &[0x15, 0x03, 0x03, 0x01, 0xff]);
+        assert_eq!(&decode_odd(&[0x15, 0x03, 0x03, 0x01, 0xff])[..], &[0x02, 0x03, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn odd_encoding_round_trip() {
+        // crosses a few 7 byte chunks and ends on a partial one
+        let data: Vec<u8> = (0..30u8).map(|x| x.wrapping_mul(37)).collect();
+        let encoded = encode_odd(&data);
+
+        assert_eq!(encoded.len(), data.len() + 5);
+        assert!(!encoded.contains(&0));
+        assert_eq!(&decode_odd(&encoded)[..], &data[..]);
+    }
+
+    #[test]
+    fn default_flags() {
+        assert_eq!(GameFlags::default().to_u32(), 0x0006_4802);
+        assert_eq!(GameFlags::from_u32(0x0006_4802), GameFlags::default());
+    }
+
+    #[test]
+    fn flags_round_trip() {
+        let flags = GameFlags {
+            speed: GameSpeed::Normal,
+            visibility: GameVisibility::MapExplored,
+            observers: GameObservers::Referees,
+            teams_together: false,
+            lock_teams: false,
+            shared_units: true,
+            random_hero: true,
+            random_races: true
+        };
+        assert_eq!(GameFlags::from_u32(flags.to_u32()), flags);
+    }
+
+    #[test]
+    fn stat_round_trip() {
+        for &sha1 in [None, Some([0xab; 20]), Some([0; 20])].iter() {
+            let stat = stat(sha1);
+            let encoded = stat.encode();
+
+            assert!(!encoded.contains(&0));
+            assert_eq!(GameStat::decode(&encoded), Some(stat));
+        }
+    }
+
+    #[test]
+    fn stat_rejects_truncated() {
+        let encoded = stat(None).encode();
+        assert_eq!(GameStat::decode(&encoded[..8]), None);
+        // cut off before the host name is terminated
+        assert_eq!(GameStat::decode(&encode_odd(&decode_odd(&encoded)[..30])), None);
+    }
+
+    #[test]
+    fn advertised_stat_known_prefix() {
+        let advertised = AdvertisedStat {
+            free_slots: 9,
+            host_counter: 0x1234_5678,
+            stat: stat(Some([1; 20]))
+        };
+        let encoded = advertised.encode();
+
+        assert_eq!(&encoded[..9], b"987654321");
+        assert_eq!(AdvertisedStat::decode(&encoded), Some(advertised));
+    }
+
+    #[test]
+    fn advertised_stat_rejects_garbage() {
+        assert_eq!(AdvertisedStat::decode(b"short"), None);
+        assert_eq!(AdvertisedStat::decode(b"x12345678\x01\x01\x01\x01\x01\x01\x01\x01"), None);
+    }
+}
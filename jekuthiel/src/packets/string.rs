@@ -0,0 +1,219 @@
+// strings on the wire are plain null-terminated byte sequences, the encoding depends on
+// the product and the server: WC3 speaks UTF-8, older products and some PvPGN setups Windows-1252.
+// game connections and replays are always UTF-8, only realm connections need to be configured
+#![allow(dead_code)]
+
+use bytes::Bytes;
+
+use std::borrow::Cow;
+use std::error;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Windows1252
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Utf8
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StringError {
+    // position of the offending null byte
+    EmbeddedNul(usize),
+    // character that has no representation in the target encoding
+    Unrepresentable(char)
+}
+
+impl fmt::Display for StringError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StringError::EmbeddedNul(pos) => write!(f, "embedded null byte at position {}", pos),
+            StringError::Unrepresentable(c) => write!(f, "character {:?} can't be represented in the target encoding", c)
+        }
+    }
+}
+
+impl error::Error for StringError {
+    fn description(&self) -> &str {
+        match *self {
+            StringError::EmbeddedNul(_) => "embedded null byte",
+            StringError::Unrepresentable(_) => "unrepresentable character"
+        }
+    }
+}
+
+// 0x80..0x9F in Windows-1252, the rest of the upper half maps straight onto Latin-1.
+// the five undefined bytes map to the matching C1 control characters so decoding stays lossless
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+fn decode_windows_1252(c: u8) -> char {
+    match c {
+        0x80...0x9F => WINDOWS_1252_HIGH[(c - 0x80) as usize],
+        _ => c as char
+    }
+}
+
+fn encode_windows_1252(c: char) -> Option<u8> {
+    match c as u32 {
+        0x00...0x7F | 0xA0...0xFF => Some(c as u8),
+        _ => WINDOWS_1252_HIGH.iter().position(|&x| x == c).map(|pos| 0x80 + pos as u8)
+    }
+}
+
+// a string as it goes over the wire, guaranteed to not contain any null bytes
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub struct BnetString {
+    bytes: Bytes
+}
+
+impl BnetString {
+    pub fn from_bytes<B: Into<Bytes>>(bytes: B) -> Result<BnetString, StringError> {
+        let bytes = bytes.into();
+
+        match bytes.iter().position(|&c| c == 0) {
+            Some(pos) => Err(StringError::EmbeddedNul(pos)),
+            None => Ok(BnetString { bytes })
+        }
+    }
+
+    // only for bytes that were split on a null terminator already
+    pub(crate) fn from_bytes_unchecked(bytes: Bytes) -> BnetString {
+        BnetString { bytes }
+    }
+
+    pub fn encode(text: &str, encoding: Encoding) -> Result<BnetString, StringError> {
+        if let Some(pos) = text.bytes().position(|c| c == 0) {
+            return Err(StringError::EmbeddedNul(pos));
+        }
+
+        let bytes = match encoding {
+            Encoding::Utf8 => Bytes::from(text),
+            Encoding::Windows1252 => {
+                let mut bytes = Vec::with_capacity(text.len());
+                for c in text.chars() {
+                    bytes.push(encode_windows_1252(c).ok_or(StringError::Unrepresentable(c))?);
+                }
+                Bytes::from(bytes)
+            }
+        };
+
+        Ok(BnetString { bytes })
+    }
+
+    pub fn decode(&self, encoding: Encoding) -> Cow<str> {
+        match encoding {
+            Encoding::Utf8 => String::from_utf8_lossy(&self.bytes),
+            Encoding::Windows1252 => {
+                if self.bytes.is_ascii() {
+                    // ascii is valid utf-8 as-is
+                    String::from_utf8_lossy(&self.bytes)
+                } else {
+                    Cow::Owned(self.bytes.iter().map(|&c| decode_windows_1252(c)).collect())
+                }
+            }
+        }
+    }
+
+    pub fn display(&self, encoding: Encoding) -> Display {
+        Display {
+            string: self,
+            encoding
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl AsRef<[u8]> for BnetString {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+pub struct Display<'a> {
+    string: &'a BnetString,
+    encoding: Encoding
+}
+
+impl<'a> fmt::Display for Display<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.string.decode(self.encoding))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_round_trip() {
+        let string = BnetString::encode("Grüße, 世界", Encoding::Utf8).unwrap();
+        assert_eq!(string.as_bytes(), "Grüße, 世界".as_bytes());
+        assert_eq!(string.decode(Encoding::Utf8), "Grüße, 世界");
+    }
+
+    #[test]
+    fn windows_1252_round_trip() {
+        let string = BnetString::encode("Grüße €5 – “ok”", Encoding::Windows1252).unwrap();
+        assert_eq!(string.as_bytes(), b"Gr\xfc\xdfe \x805 \x96 \x93ok\x94");
+        assert_eq!(string.decode(Encoding::Windows1252), "Grüße €5 – “ok”");
+    }
+
+    #[test]
+    fn windows_1252_is_lossless() {
+        let bytes: Vec<u8> = (1..256u32).map(|x| x as u8).collect();
+        let string = BnetString::from_bytes(bytes.clone()).unwrap();
+        let text = string.decode(Encoding::Windows1252).into_owned();
+
+        assert_eq!(BnetString::encode(&text, Encoding::Windows1252).unwrap().as_bytes(), &bytes[..]);
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let string = BnetString::from_bytes(&b"a\xffb"[..]).unwrap();
+        assert_eq!(string.decode(Encoding::Utf8), "a\u{FFFD}b");
+        assert_eq!(string.decode(Encoding::Windows1252), "aÿb");
+    }
+
+    #[test]
+    fn rejects_embedded_nul() {
+        assert_eq!(BnetString::encode("ab\0c", Encoding::Utf8), Err(StringError::EmbeddedNul(2)));
+        assert_eq!(BnetString::encode("\0", Encoding::Windows1252), Err(StringError::EmbeddedNul(0)));
+        assert_eq!(BnetString::from_bytes(&b"abc\0"[..]), Err(StringError::EmbeddedNul(3)));
+    }
+
+    #[test]
+    fn rejects_unrepresentable() {
+        assert_eq!(BnetString::encode("世界", Encoding::Windows1252), Err(StringError::Unrepresentable('世')));
+    }
+
+    #[test]
+    fn display() {
+        let string = BnetString::from_bytes(&b"caf\xe9"[..]).unwrap();
+        assert_eq!(string.display(Encoding::Windows1252).to_string(), "café");
+    }
+}
@@ -0,0 +1,316 @@
+// the player actions inside time slots, as the client sends them in OUTGOING_ACTION
+// reference: http://w3g.deepnode.de/files/w3g_actions.txt, for 1.14b and later
+#![allow(dead_code)]
+
+use bytes::*;
+
+use std::io::{self, Cursor};
+
+use w3gs::packets::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObjectId(pub u32, pub u32);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32
+}
+
+// ChangeSelection modes
+pub const SELECTION_ADD: u8 = 0x01;
+pub const SELECTION_REMOVE: u8 = 0x02;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Pause,
+    Resume,
+    SetSpeed(u8),
+    SaveGame(Bytes),
+    // 0x10 to 0x14, an order for the selected units; the ones with a target carry its position
+    Ability {
+        id: u8,
+        flags: u16,
+        order: u32,
+        target: Option<Point>
+    },
+    ChangeSelection {
+        mode: u8,
+        units: Vec<ObjectId>
+    },
+    AssignGroup {
+        group: u8,
+        units: Vec<ObjectId>
+    },
+    SelectGroup(u8),
+    // sent by the client itself after every selection change
+    SelectSubgroup {
+        item: u32,
+        object: ObjectId
+    },
+    PreSubselection,
+    SelectGroundItem(ObjectId),
+    CancelRevival(ObjectId),
+    DequeueUnit {
+        slot: u8,
+        item: u32
+    },
+    AllyOptions {
+        slot: u8,
+        flags: u32
+    },
+    TransferResources {
+        slot: u8,
+        gold: u32,
+        lumber: u32
+    },
+    // chat the map's triggers listen to
+    TriggerChat(Bytes),
+    Escape,
+    MinimapPing(Point),
+    // game cache syncs, which is how W3MMD maps report their stats
+    SyncStored {
+        filename: Bytes,
+        mission: Bytes,
+        key: Bytes,
+        value: Option<u32>
+    },
+    // an action we know the size of but don't model, cheats included
+    Other {
+        id: u8,
+        data: Bytes
+    },
+    // an action we don't know the size of, along with everything after it in the block
+    Unknown {
+        id: u8,
+        data: Bytes
+    }
+}
+
+// payload length of the actions that end up as Other
+fn other_length(id: u8) -> Option<usize> {
+    match id {
+        0x04 | 0x05 => Some(0),
+        0x07 => Some(4),
+        0x1B => Some(9),
+        0x20 | 0x22...0x26 | 0x29...0x2C | 0x2F...0x32 => Some(0),
+        0x27 | 0x28 | 0x2D => Some(5),
+        0x2E => Some(4),
+        0x21 => Some(8),
+        0x62 => Some(12),
+        0x66 | 0x67 => Some(0),
+        0x69 | 0x6A => Some(16),
+        0x75 => Some(1),
+        _ => None
+    }
+}
+
+fn read_point(buf: &mut Cursor<Bytes>) -> io::Result<Point> {
+    Ok(Point {
+        x: f32::from_bits(read_u32(buf)?),
+        y: f32::from_bits(read_u32(buf)?)
+    })
+}
+
+fn read_object(buf: &mut Cursor<Bytes>) -> io::Result<ObjectId> {
+    Ok(ObjectId(read_u32(buf)?, read_u32(buf)?))
+}
+
+fn read_units(buf: &mut Cursor<Bytes>) -> io::Result<Vec<ObjectId>> {
+    let count = read_u16(buf)?;
+    (0..count).map(|_| read_object(buf)).collect()
+}
+
+impl Action {
+    fn read(id: u8, buf: &mut Cursor<Bytes>) -> io::Result<Action> {
+        let action = match id {
+            0x01 => Action::Pause,
+            0x02 => Action::Resume,
+            0x03 => Action::SetSpeed(read_u8(buf)?),
+            0x06 => Action::SaveGame(read_cstring(buf)?),
+            0x10...0x14 => {
+                let flags = read_u16(buf)?;
+                let order = read_u32(buf)?;
+                let _unknown = read_bytes(buf, 8)?;
+
+                let target = if id >= 0x11 { Some(read_point(buf)?) } else { None };
+                // the target object, the item and for 0x14 a second order and target
+                let rest = match id {
+                    0x12 => 8,
+                    0x13 => 16,
+                    0x14 => 4 + 9 + 8,
+                    _ => 0
+                };
+                read_bytes(buf, rest)?;
+
+                Action::Ability { id, flags, order, target }
+            }
+            0x16 => Action::ChangeSelection {
+                mode: read_u8(buf)?,
+                units: read_units(buf)?
+            },
+            0x17 => Action::AssignGroup {
+                group: read_u8(buf)?,
+                units: read_units(buf)?
+            },
+            0x18 => {
+                let group = read_u8(buf)?;
+                let _unknown = read_u8(buf)?;
+                Action::SelectGroup(group)
+            }
+            0x19 => Action::SelectSubgroup {
+                item: read_u32(buf)?,
+                object: read_object(buf)?
+            },
+            0x1A => Action::PreSubselection,
+            0x1C => {
+                let _unknown = read_u8(buf)?;
+                Action::SelectGroundItem(read_object(buf)?)
+            }
+            0x1D => Action::CancelRevival(read_object(buf)?),
+            0x1E => Action::DequeueUnit {
+                slot: read_u8(buf)?,
+                item: read_u32(buf)?
+            },
+            0x50 => Action::AllyOptions {
+                slot: read_u8(buf)?,
+                flags: read_u32(buf)?
+            },
+            0x51 => Action::TransferResources {
+                slot: read_u8(buf)?,
+                gold: read_u32(buf)?,
+                lumber: read_u32(buf)?
+            },
+            0x60 => {
+                let _unknown = read_bytes(buf, 8)?;
+                Action::TriggerChat(read_cstring(buf)?)
+            }
+            0x61 => Action::Escape,
+            0x68 => {
+                let point = read_point(buf)?;
+                let _unknown = read_u32(buf)?;
+                Action::MinimapPing(point)
+            }
+            0x6B...0x6D | 0x70 => Action::SyncStored {
+                filename: read_cstring(buf)?,
+                mission: read_cstring(buf)?,
+                key: read_cstring(buf)?,
+                value: if id == 0x70 { None } else { Some(read_u32(buf)?) }
+            },
+            _ => match other_length(id) {
+                Some(length) => Action::Other { id, data: read_bytes(buf, length)? },
+                None => Action::Unknown { id, data: read_rest(buf) }
+            }
+        };
+
+        Ok(action)
+    }
+
+    // everything in the data of one PlayerAction; decoding stops at the first action it can't
+    // make sense of
+    pub fn parse(data: Bytes) -> io::Result<Vec<Action>> {
+        let mut buf = Cursor::new(data);
+        let mut actions = Vec::new();
+
+        while buf.has_remaining() {
+            let id = read_u8(&mut buf)?;
+            actions.push(Action::read(id, &mut buf)?);
+        }
+
+        Ok(actions)
+    }
+
+    // whether the action was the player doing something, as opposed to the client sending
+    // things on its own (subgroup selections, game cache syncs) or the player only deselecting
+    pub fn counts_for_apm(&self) -> bool {
+        match *self {
+            Action::Ability { .. } |
+            Action::AssignGroup { .. } |
+            Action::SelectGroup(_) |
+            Action::SelectGroundItem(_) |
+            Action::CancelRevival(_) |
+            Action::DequeueUnit { .. } |
+            Action::TransferResources { .. } |
+            Action::Escape => true,
+            Action::ChangeSelection { mode, .. } => mode == SELECTION_ADD,
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &[u8]) -> Vec<Action> {
+        Action::parse(Bytes::from(data)).unwrap()
+    }
+
+    // payload lengths from w3g_actions.txt, with empty strings and unit lists
+    const SIZES: [(u8, usize); 38] = [
+        (0x01, 0), (0x02, 0), (0x03, 1), (0x04, 0), (0x05, 0), (0x06, 1), (0x07, 4),
+        (0x10, 14), (0x11, 22), (0x12, 30), (0x13, 38), (0x14, 43),
+        (0x16, 3), (0x17, 3), (0x18, 2), (0x19, 12), (0x1A, 0), (0x1B, 9), (0x1C, 9), (0x1D, 8), (0x1E, 5),
+        (0x20, 0), (0x21, 8), (0x27, 5), (0x2E, 4), (0x32, 0),
+        (0x50, 5), (0x51, 9), (0x60, 9), (0x61, 0), (0x62, 12), (0x66, 0), (0x68, 12), (0x69, 16),
+        (0x6B, 7), (0x6C, 7), (0x70, 3), (0x75, 1)
+    ];
+
+    #[test]
+    fn action_sizes() {
+        for &(id, length) in SIZES.iter() {
+            // a pause right behind it only shows up if exactly the payload was consumed
+            let mut data = vec![id];
+            data.extend(vec![0; length]);
+            data.push(0x01);
+
+            let actions = parse(&data);
+            assert_eq!(actions.len(), 2, "action 0x{:02X}", id);
+            assert_eq!(actions[1], Action::Pause, "action 0x{:02X}", id);
+            if let Action::Unknown { .. } = actions[0] {
+                panic!("action 0x{:02X} has no known size", id);
+            }
+
+            assert!(Action::parse(Bytes::from(&data[..length])).is_err() || length == 0, "truncated action 0x{:02X}", id);
+        }
+    }
+
+    #[test]
+    fn decodes_actions() {
+        let mut data = vec![0x12, 0x40, 0x00, 0x03, 0x00, 0x0D, 0x00];
+        data.extend(vec![0xff; 8]);
+        data.extend_from_slice(&[0x00, 0x00, 0x80, 0x3F, 0x00, 0x00, 0x00, 0xC0]);
+        data.extend(vec![0; 8]);
+        data.extend_from_slice(&[0x16, SELECTION_ADD, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00]);
+        data.extend_from_slice(b"\x60\0\0\0\0\0\0\0\0-ap\0");
+
+        assert_eq!(parse(&data), vec![
+            Action::Ability { id: 0x12, flags: 0x40, order: 0x000D_0003, target: Some(Point { x: 1.0, y: -2.0 }) },
+            Action::ChangeSelection { mode: SELECTION_ADD, units: vec![ObjectId(1, 2)] },
+            Action::TriggerChat(Bytes::from(&b"-ap"[..]))
+        ]);
+    }
+
+    #[test]
+    fn unknown_actions_take_the_rest() {
+        assert_eq!(parse(&[0x01, 0xEE, 0x01, 0x02]), vec![
+            Action::Pause,
+            Action::Unknown { id: 0xEE, data: Bytes::from(&[0x01, 0x02][..]) }
+        ]);
+    }
+
+    #[test]
+    fn apm_counting() {
+        let counts = |data: &[u8]| parse(data).iter().filter(|x| x.counts_for_apm()).count();
+
+        // select, deselect, subgroup, ability without a target, escape
+        let mut data = vec![0x16, SELECTION_ADD, 0x00, 0x00, 0x16, SELECTION_REMOVE, 0x00, 0x00];
+        data.extend(vec![0x19; 13]);
+        data.extend(vec![0x10; 15]);
+        data.push(0x61);
+        assert_eq!(counts(&data), 3);
+
+        // pauses, speed changes and game cache syncs never count
+        assert_eq!(counts(&[0x01, 0x02, 0x03, 0x02, 0x70, 0, 0, 0]), 0);
+    }
+}
@@ -0,0 +1,441 @@
+// WC3 replays (.w3g): a header followed by zlib compressed 8K blocks, which together hold the
+// game settings and then a stream of records
+// reference: http://w3g.deepnode.de/files/w3g_format.txt and GHost++'s replay.cpp
+#![allow(dead_code)]
+
+pub mod actions;
+pub mod parser;
+pub mod writer;
+
+use bytes::*;
+use crc::crc32;
+
+use std::io::{self, Cursor};
+
+use packets::statstring::GameStat;
+use packets::string::BnetString;
+use w3gs::packets::*;
+use w3gs::slots::SlotTable;
+
+pub use self::actions::Action;
+pub use self::parser::Replay;
+pub use self::writer::ReplayWriter;
+// replays hold what went over the game connections, text included
+pub use w3gs::ENCODING;
+
+pub const REPLAY_MAGIC: &[u8; 28] = b"Warcraft III recorded game\x1A\0";
+pub const HEADER_LENGTH: usize = 0x44;
+pub const HEADER_VERSION: u32 = 1;
+pub const BLOCK_HEADER_LENGTH: usize = 8;
+pub const BLOCK_SIZE: usize = 8192;
+
+// the flags in the header of a game that wasn't single player
+pub const FLAG_MULTIPLAYER: u16 = 0x8000;
+
+// always at the start of the decompressed data
+const DATA_MAGIC: u32 = 0x0000_0110;
+
+const RECORD_HOST: u8 = 0x00;
+const RECORD_PLAYER: u8 = 0x16;
+const RECORD_LEAVE: u8 = 0x17;
+const RECORD_GAME_START: u8 = 0x19;
+const RECORD_START_1: u8 = 0x1A;
+const RECORD_START_2: u8 = 0x1B;
+const RECORD_START_3: u8 = 0x1C;
+const RECORD_TIME_SLOT_2: u8 = 0x1E;
+const RECORD_TIME_SLOT: u8 = 0x1F;
+const RECORD_CHAT: u8 = 0x20;
+const RECORD_CHECKSUM: u8 = 0x22;
+const RECORD_UNKNOWN_23: u8 = 0x23;
+const RECORD_FORCED_END: u8 = 0x2F;
+
+// leave reasons, whether it was another player's connection or the one saving the replay
+pub const LEAVE_REMOTE: u32 = 0x01;
+pub const LEAVE_LOCAL: u32 = 0x0C;
+
+// chat flag of messages sent before the game started, which have no chat mode
+pub const CHAT_FLAG_DELAYED: u8 = 0x10;
+pub const CHAT_FLAG_NORMAL: u8 = 0x20;
+
+// the game type for custom games
+pub const GAME_TYPE_CUSTOM: u32 = 0x09;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReplayHeader {
+    // of the whole file, header included
+    pub file_size: u32,
+    pub decompressed_size: u32,
+    pub blocks: u32,
+    pub product: u32,
+    // the minor version, 26 for 1.26
+    pub version: u32,
+    pub build: u16,
+    pub flags: u16,
+    // milliseconds of game time
+    pub duration: u32
+}
+
+impl ReplayHeader {
+    pub fn write(&self, buf: &mut BytesMut) {
+        let start = buf.len();
+        buf.reserve(HEADER_LENGTH);
+        buf.put_slice(REPLAY_MAGIC);
+        buf.put_u32::<E>(HEADER_LENGTH as u32);
+        buf.put_u32::<E>(self.file_size);
+        buf.put_u32::<E>(HEADER_VERSION);
+        buf.put_u32::<E>(self.decompressed_size);
+        buf.put_u32::<E>(self.blocks);
+        buf.put_u32::<E>(self.product);
+        buf.put_u32::<E>(self.version);
+        buf.put_u16::<E>(self.build);
+        buf.put_u16::<E>(self.flags);
+        buf.put_u32::<E>(self.duration);
+
+        // crc of the header with this field still zero
+        buf.put_u32::<E>(0);
+        let crc = crc32::checksum_ieee(&buf[start..]);
+        let end = buf.len();
+        E::write_u32(&mut buf[end - 4..], crc);
+    }
+
+    pub fn read(buf: &mut Cursor<Bytes>) -> io::Result<Self> {
+        if &read_bytes(buf, REPLAY_MAGIC.len())?[..] != &REPLAY_MAGIC[..] {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a WC3 replay"));
+        }
+
+        let header_length = read_u32(buf)?;
+        let file_size = read_u32(buf)?;
+        let header_version = read_u32(buf)?;
+        if header_length as usize != HEADER_LENGTH || header_version != HEADER_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported replay header version"));
+        }
+
+        let header = ReplayHeader {
+            file_size,
+            decompressed_size: read_u32(buf)?,
+            blocks: read_u32(buf)?,
+            product: read_u32(buf)?,
+            version: read_u32(buf)?,
+            build: read_u16(buf)?,
+            flags: read_u16(buf)?,
+            duration: read_u32(buf)?
+        };
+        let _crc = read_u32(buf)?;
+
+        Ok(header)
+    }
+}
+
+// each half is the xor of the two halves of a crc, one over the block header and one over
+// the compressed data
+pub fn block_checksum(header: &[u8], data: &[u8]) -> u32 {
+    let fold = |crc: u32| (crc ^ (crc >> 16)) & 0xffff;
+    fold(crc32::checksum_ieee(header)) | fold(crc32::checksum_ieee(data)) << 16
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplayPlayer {
+    pub pid: u8,
+    pub name: BnetString,
+    // one zero byte in custom games, runtime and race in ladder games
+    pub extra: Bytes
+}
+
+impl ReplayPlayer {
+    pub fn custom(pid: u8, name: BnetString) -> ReplayPlayer {
+        ReplayPlayer {
+            pid,
+            name,
+            extra: Bytes::from_static(&[0])
+        }
+    }
+
+    fn write(&self, buf: &mut BytesMut, record: u8) {
+        buf.reserve(2);
+        buf.put(record);
+        buf.put(self.pid);
+        write_cstring(buf, self.name.as_bytes());
+        buf.reserve(1 + self.extra.len());
+        buf.put(self.extra.len() as u8);
+        buf.put(&self.extra);
+    }
+
+    fn read(buf: &mut Cursor<Bytes>) -> io::Result<Self> {
+        let pid = read_u8(buf)?;
+        let name = read_string(buf)?;
+        let length = read_u8(buf)? as usize;
+
+        Ok(ReplayPlayer {
+            pid,
+            name,
+            extra: read_bytes(buf, length)?
+        })
+    }
+}
+
+// everything in front of the records: who's playing on what
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplayGame {
+    // whoever saved the replay
+    pub host: ReplayPlayer,
+    pub game_name: BnetString,
+    pub stat: GameStat,
+    pub game_type: u32,
+    pub language: u32,
+    pub players: Vec<ReplayPlayer>,
+    pub slots: SlotTable
+}
+
+impl ReplayGame {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(4);
+        buf.put_u32::<E>(DATA_MAGIC);
+        self.host.write(buf, RECORD_HOST);
+        write_cstring(buf, self.game_name.as_bytes());
+        buf.reserve(1);
+        buf.put(0u8);
+        write_cstring(buf, &self.stat.encode());
+
+        buf.reserve(4 * 3);
+        buf.put_u32::<E>(self.slots.slots.len() as u32);
+        buf.put_u32::<E>(self.game_type);
+        buf.put_u32::<E>(self.language);
+
+        for player in &self.players {
+            player.write(buf, RECORD_PLAYER);
+            buf.reserve(4);
+            // unknown, always 0
+            buf.put_u32::<E>(0);
+        }
+
+        let slots = self.slots.encode();
+        buf.reserve(1 + 2 + slots.len());
+        buf.put(RECORD_GAME_START);
+        buf.put_u16::<E>(slots.len() as u16);
+        buf.put(slots);
+    }
+
+    pub fn read(buf: &mut Cursor<Bytes>) -> io::Result<Self> {
+        if read_u32(buf)? != DATA_MAGIC || read_u8(buf)? != RECORD_HOST {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "replay data doesn't start with the host"));
+        }
+
+        let host = ReplayPlayer::read(buf)?;
+        let game_name = read_string(buf)?;
+        let _unknown = read_u8(buf)?;
+        let stat = GameStat::decode(&read_cstring(buf)?)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad statstring in replay"))?;
+
+        let _slot_count = read_u32(buf)?;
+        let game_type = read_u32(buf)?;
+        let language = read_u32(buf)?;
+
+        let mut players = Vec::new();
+        loop {
+            match read_u8(buf)? {
+                RECORD_PLAYER => {
+                    players.push(ReplayPlayer::read(buf)?);
+                    let _unknown = read_u32(buf)?;
+                }
+                RECORD_GAME_START => break,
+                id => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected replay record 0x{:02X} in the player list", id)))
+            }
+        }
+
+        let length = read_u16(buf)? as usize;
+        let slots = SlotTable::decode(read_bytes(buf, length)?)?;
+
+        Ok(ReplayGame {
+            host,
+            game_name,
+            stat,
+            game_type,
+            language,
+            players,
+            slots
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReplayRecord {
+    // reason is 0x01 if the player's connection closed and 0x0C if it was the one saving the
+    // replay, result is the PLAYERLEAVE reason
+    Leave {
+        reason: u32,
+        pid: u8,
+        result: u32
+    },
+    // the three records between loading and the first time slot
+    Start(u8),
+    TimeSlot {
+        interval: u16,
+        actions: Vec<PlayerAction>
+    },
+    // INCOMING_ACTION2, actions that didn't fit into the time slot that follows
+    TimeSlot2 {
+        interval: u16,
+        actions: Vec<PlayerAction>
+    },
+    // mode is None for messages sent before the game started
+    Chat {
+        pid: u8,
+        mode: Option<u32>,
+        message: BnetString
+    },
+    Checksum(u32),
+    // records with a known length we don't model: 0x23, which nobody knows the meaning of, and
+    // 0x2F, the countdown of a game that's about to end on its own
+    Other {
+        record: u8,
+        data: Bytes
+    }
+}
+
+fn read_time_slot(buf: &mut Cursor<Bytes>) -> io::Result<(u16, Vec<PlayerAction>)> {
+    let length = read_u16(buf)? as usize;
+    if length < 2 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "replay time slot too short"));
+    }
+
+    let interval = read_u16(buf)?;
+    let mut data = Cursor::new(read_bytes(buf, length - 2)?);
+    let mut actions = Vec::new();
+    while data.has_remaining() {
+        let pid = read_u8(&mut data)?;
+        let length = read_u16(&mut data)? as usize;
+        actions.push(PlayerAction {
+            pid,
+            data: read_bytes(&mut data, length)?
+        });
+    }
+
+    Ok((interval, actions))
+}
+
+fn write_time_slot(buf: &mut BytesMut, record: u8, interval: u16, actions: &[PlayerAction]) {
+    let length = 2 + actions.iter().map(|x| 3 + x.data.len()).sum::<usize>();
+    buf.reserve(1 + 2 + length);
+    buf.put(record);
+    buf.put_u16::<E>(length as u16);
+    buf.put_u16::<E>(interval);
+    for action in actions {
+        buf.put(action.pid);
+        buf.put_u16::<E>(action.data.len() as u16);
+        buf.put(&action.data);
+    }
+}
+
+impl ReplayRecord {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(16);
+
+        match *self {
+            ReplayRecord::Leave { reason, pid, result } => {
+                buf.put(RECORD_LEAVE);
+                buf.put_u32::<E>(reason);
+                buf.put(pid);
+                buf.put_u32::<E>(result);
+                // unknown, always 1
+                buf.put_u32::<E>(1);
+            }
+            ReplayRecord::Start(record) => {
+                buf.put(record);
+                buf.put_u32::<E>(1);
+            }
+            ReplayRecord::TimeSlot { interval, ref actions } => write_time_slot(buf, RECORD_TIME_SLOT, interval, actions),
+            ReplayRecord::TimeSlot2 { interval, ref actions } => write_time_slot(buf, RECORD_TIME_SLOT_2, interval, actions),
+            ReplayRecord::Chat { pid, mode, ref message } => {
+                let length = match mode {
+                    Some(_) => 1 + 4 + message.len() + 1,
+                    None => 1 + message.len() + 1
+                };
+                buf.put(RECORD_CHAT);
+                buf.put(pid);
+                buf.put_u16::<E>(length as u16);
+                match mode {
+                    Some(mode) => {
+                        buf.put(CHAT_FLAG_NORMAL);
+                        buf.put_u32::<E>(mode);
+                    }
+                    None => buf.put(CHAT_FLAG_DELAYED)
+                }
+                write_cstring(buf, message.as_bytes());
+            }
+            ReplayRecord::Checksum(checksum) => {
+                buf.put(RECORD_CHECKSUM);
+                buf.put(4u8);
+                buf.put_u32::<E>(checksum);
+            }
+            ReplayRecord::Other { record, ref data } => {
+                buf.reserve(1 + data.len());
+                buf.put(record);
+                buf.put(data);
+            }
+        }
+    }
+
+    // the record that starts with the given id, which has already been read
+    pub fn read(record: u8, buf: &mut Cursor<Bytes>) -> io::Result<Self> {
+        let record = match record {
+            RECORD_LEAVE => {
+                let reason = read_u32(buf)?;
+                let pid = read_u8(buf)?;
+                let result = read_u32(buf)?;
+                let _unknown = read_u32(buf)?;
+                ReplayRecord::Leave { reason, pid, result }
+            }
+            RECORD_START_1 | RECORD_START_2 | RECORD_START_3 => {
+                let _unknown = read_u32(buf)?;
+                ReplayRecord::Start(record)
+            }
+            RECORD_TIME_SLOT => {
+                let (interval, actions) = read_time_slot(buf)?;
+                ReplayRecord::TimeSlot { interval, actions }
+            }
+            RECORD_TIME_SLOT_2 => {
+                let (interval, actions) = read_time_slot(buf)?;
+                ReplayRecord::TimeSlot2 { interval, actions }
+            }
+            RECORD_CHAT => {
+                let pid = read_u8(buf)?;
+                let length = read_u16(buf)? as usize;
+                let mut data = Cursor::new(read_bytes(buf, length)?);
+                let mode = match read_u8(&mut data)? {
+                    CHAT_FLAG_DELAYED => None,
+                    _ => Some(read_u32(&mut data)?)
+                };
+
+                ReplayRecord::Chat {
+                    pid,
+                    mode,
+                    message: read_string(&mut data)?
+                }
+            }
+            RECORD_CHECKSUM => match read_u8(buf)? {
+                4 => ReplayRecord::Checksum(read_u32(buf)?),
+                length => {
+                    let data = read_bytes(buf, length as usize)?;
+                    let mut full = BytesMut::with_capacity(1 + data.len());
+                    full.put(length);
+                    full.put(data);
+                    ReplayRecord::Other { record, data: full.freeze() }
+                }
+            },
+            RECORD_UNKNOWN_23 => ReplayRecord::Other { record, data: read_bytes(buf, 10)? },
+            RECORD_FORCED_END => ReplayRecord::Other { record, data: read_bytes(buf, 8)? },
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown replay record 0x{:02X}", record)))
+        };
+
+        Ok(record)
+    }
+
+    // the three start records all look the same
+    pub fn start_records() -> Vec<ReplayRecord> {
+        vec![
+            ReplayRecord::Start(RECORD_START_1),
+            ReplayRecord::Start(RECORD_START_2),
+            ReplayRecord::Start(RECORD_START_3)
+        ]
+    }
+}
@@ -0,0 +1,349 @@
+// reads a whole replay back, plus the few things people usually want to know about one
+
+use bytes::*;
+use flate2::read::ZlibDecoder;
+
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+
+use super::*;
+use super::actions::Action;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub game: ReplayGame,
+    pub records: Vec<ReplayRecord>,
+    // false if the records stop at one we couldn't read, everything before it is still there
+    pub complete: bool
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChatMessage {
+    // milliseconds of game time
+    pub time: u32,
+    pub pid: u8,
+    pub mode: Option<u32>,
+    pub message: BnetString
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlayerStats {
+    pub pid: u8,
+    // the ones that count for APM
+    pub actions: u32,
+    // milliseconds of game time the player was there for
+    pub time: u32,
+    pub left: bool
+}
+
+impl PlayerStats {
+    pub fn apm(&self) -> f64 {
+        match self.time {
+            0 => 0.0,
+            time => self.actions as f64 * 60_000.0 / time as f64
+        }
+    }
+}
+
+// the sizes in the header aren't trusted, the data grows one block at a time and no block
+// gets to be bigger than it says it is
+fn decompress(header: &ReplayHeader, buf: &mut Cursor<Bytes>) -> io::Result<Bytes> {
+    let mut data = Vec::new();
+
+    for _ in 0..header.blocks {
+        let compressed = read_u16(buf)? as usize;
+        let decompressed = read_u16(buf)?;
+        let _checksum = read_u32(buf)?;
+
+        let block = read_bytes(buf, compressed)?;
+        ZlibDecoder::new(&block[..]).take(decompressed as u64).read_to_end(&mut data)?;
+    }
+
+    // the last block is padded
+    if data.len() < header.decompressed_size as usize {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "replay data shorter than the header says"));
+    }
+    data.truncate(header.decompressed_size as usize);
+
+    Ok(Bytes::from(data))
+}
+
+impl Replay {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Replay> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        Replay::parse(Bytes::from(data))
+    }
+
+    pub fn parse(data: Bytes) -> io::Result<Replay> {
+        let mut buf = Cursor::new(data);
+        let header = ReplayHeader::read(&mut buf)?;
+
+        let mut buf = Cursor::new(decompress(&header, &mut buf)?);
+        let game = ReplayGame::read(&mut buf)?;
+
+        let mut records = Vec::new();
+        let mut complete = true;
+        while buf.has_remaining() {
+            let id = read_u8(&mut buf)?;
+            // padding some writers leave in
+            if id == 0 {
+                break;
+            }
+
+            // without knowing how long a record is there's no way to get to the next one
+            match ReplayRecord::read(id, &mut buf) {
+                Ok(record) => records.push(record),
+                Err(_) => {
+                    complete = false;
+                    break;
+                }
+            }
+        }
+
+        Ok(Replay {
+            header,
+            game,
+            records,
+            complete
+        })
+    }
+
+    // milliseconds of game time
+    pub fn duration(&self) -> u32 {
+        self.header.duration
+    }
+
+    // everyone who was in the game, the one who saved the replay first
+    pub fn players(&self) -> Vec<&ReplayPlayer> {
+        Some(&self.game.host).into_iter().chain(self.game.players.iter()).collect()
+    }
+
+    pub fn player_name(&self, pid: u8) -> Option<&BnetString> {
+        self.players().into_iter().find(|x| x.pid == pid).map(|x| &x.name)
+    }
+
+    // the records that carry actions, each with the game time it happened at
+    pub fn time_slots(&self) -> Vec<(u32, &[PlayerAction])> {
+        let mut time = 0;
+        let mut slots = Vec::new();
+
+        for record in &self.records {
+            match *record {
+                ReplayRecord::TimeSlot { interval, ref actions } |
+                ReplayRecord::TimeSlot2 { interval, ref actions } => {
+                    time += interval as u32;
+                    slots.push((time, &actions[..]));
+                }
+                _ => {}
+            }
+        }
+
+        slots
+    }
+
+    pub fn chat(&self) -> Vec<ChatMessage> {
+        let mut time = 0;
+        let mut messages = Vec::new();
+
+        for record in &self.records {
+            match *record {
+                ReplayRecord::TimeSlot { interval, .. } | ReplayRecord::TimeSlot2 { interval, .. } => time += interval as u32,
+                ReplayRecord::Chat { pid, mode, ref message } => messages.push(ChatMessage {
+                    time,
+                    pid,
+                    mode,
+                    message: message.clone()
+                }),
+                _ => {}
+            }
+        }
+
+        messages
+    }
+
+    // a player action that fails to decode doesn't count at all
+    pub fn player_stats(&self) -> Vec<PlayerStats> {
+        let mut stats: Vec<PlayerStats> = self.players().iter()
+            .map(|x| PlayerStats {
+                pid: x.pid,
+                actions: 0,
+                time: self.duration(),
+                left: false
+            })
+            .collect();
+
+        let mut time = 0;
+        for record in &self.records {
+            match *record {
+                ReplayRecord::TimeSlot { interval, ref actions } | ReplayRecord::TimeSlot2 { interval, ref actions } => {
+                    time += interval as u32;
+
+                    for action in actions {
+                        let count = match Action::parse(action.data.clone()) {
+                            Ok(decoded) => decoded.iter().filter(|x| x.counts_for_apm()).count(),
+                            Err(_) => 0
+                        };
+
+                        if let Some(player) = stats.iter_mut().find(|x| x.pid == action.pid) {
+                            player.actions += count as u32;
+                        }
+                    }
+                }
+                ReplayRecord::Leave { pid, .. } => {
+                    if let Some(player) = stats.iter_mut().find(|x| x.pid == pid) {
+                        player.time = time;
+                        player.left = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use packets::statstring::{GameFlags, GameStat};
+    use packets::string::Encoding;
+    use w3gs::slots::SlotTable;
+
+    fn name(text: &str) -> BnetString {
+        BnetString::encode(text, Encoding::Utf8).unwrap()
+    }
+
+    fn game() -> ReplayGame {
+        let mut slots = SlotTable::melee(2);
+        slots.occupy(2).unwrap();
+        slots.occupy(3).unwrap();
+
+        ReplayGame {
+            host: ReplayPlayer::custom(1, name("tabeal")),
+            game_name: name("test game"),
+            stat: GameStat {
+                flags: GameFlags::default(),
+                map_width: 116,
+                map_height: 84,
+                map_crc: 0x1122_3344,
+                map_path: Bytes::from(&b"Maps\\Download\\test.w3x"[..]),
+                host_name: Bytes::from(&b"tabeal"[..]),
+                map_sha1: Some([7; 20])
+            },
+            game_type: GAME_TYPE_CUSTOM,
+            language: 0,
+            players: vec![ReplayPlayer::custom(2, name("first")), ReplayPlayer::custom(3, name("second"))],
+            slots
+        }
+    }
+
+    // an ability without a target, which counts for APM
+    fn ability(pid: u8) -> PlayerAction {
+        let mut data = vec![0x10];
+        data.extend(vec![0; 14]);
+        PlayerAction { pid, data: Bytes::from(data) }
+    }
+
+    fn records() -> Vec<ReplayRecord> {
+        let mut records = ReplayRecord::start_records();
+        records.push(ReplayRecord::Chat { pid: 2, mode: None, message: name("glhf") });
+        for _ in 0..600 {
+            records.push(ReplayRecord::TimeSlot { interval: 100, actions: vec![ability(2), ability(3), ability(2)] });
+        }
+        records.push(ReplayRecord::Chat { pid: 3, mode: Some(0), message: name("gg") });
+        records.push(ReplayRecord::Leave { reason: LEAVE_REMOTE, pid: 3, result: 0x07 });
+        for _ in 0..600 {
+            // actions that didn't fit come first, without an interval of their own
+            records.push(ReplayRecord::TimeSlot2 { interval: 0, actions: vec![ability(2)] });
+            records.push(ReplayRecord::TimeSlot { interval: 100, actions: vec![] });
+        }
+        records.push(ReplayRecord::Checksum(0xDEAD_BEEF));
+        records.push(ReplayRecord::Leave { reason: LEAVE_LOCAL, pid: 1, result: 0x07 });
+        records
+    }
+
+    fn write(records: &[ReplayRecord]) -> Bytes {
+        let mut writer = ReplayWriter::new(PRODUCT_TFT, 26, 6059, &game());
+        for record in records {
+            writer.write(record);
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let records = records();
+        let replay = Replay::parse(write(&records)).unwrap();
+
+        assert_eq!(replay.header.product, PRODUCT_TFT);
+        assert_eq!((replay.header.version, replay.header.build), (26, 6059));
+        assert_eq!(replay.header.flags, FLAG_MULTIPLAYER);
+        // more than one block's worth
+        assert!(replay.header.blocks > 1);
+        assert_eq!(replay.duration(), 120_000);
+        assert_eq!(replay.game, game());
+        assert_eq!(replay.records, records);
+        assert!(replay.complete);
+    }
+
+    #[test]
+    fn chat_and_stats() {
+        let replay = Replay::parse(write(&records())).unwrap();
+
+        assert_eq!(replay.player_name(3), Some(&name("second")));
+        assert_eq!(replay.player_name(9), None);
+
+        let chat = replay.chat();
+        assert_eq!(chat.len(), 2);
+        assert_eq!((chat[0].time, chat[0].mode), (0, None));
+        assert_eq!((chat[1].time, chat[1].pid, chat[1].mode), (60_000, 3, Some(0)));
+
+        let stats = replay.player_stats();
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats[0], PlayerStats { pid: 1, actions: 0, time: 120_000, left: true });
+        assert_eq!(stats[1], PlayerStats { pid: 2, actions: 1800, time: 120_000, left: false });
+        assert_eq!(stats[2], PlayerStats { pid: 3, actions: 600, time: 60_000, left: true });
+        assert_eq!(stats[1].apm(), 900.0);
+        assert_eq!(stats[2].apm(), 600.0);
+    }
+
+    #[test]
+    fn stops_at_unknown_records() {
+        let mut records = ReplayRecord::start_records();
+        records.push(ReplayRecord::TimeSlot { interval: 100, actions: vec![ability(2)] });
+        records.push(ReplayRecord::Other { record: 0x99, data: Bytes::from(&[1, 2, 3][..]) });
+        records.push(ReplayRecord::TimeSlot { interval: 100, actions: vec![ability(3)] });
+
+        let replay = Replay::parse(write(&records)).unwrap();
+        assert!(!replay.complete);
+        assert_eq!(replay.records, &records[..4]);
+    }
+
+    #[test]
+    fn rejects_bad_replays() {
+        let data = write(&records());
+        assert!(Replay::parse(data.slice_to(HEADER_LENGTH - 1)).is_err());
+        assert!(Replay::parse(data.slice_to(data.len() - 1)).is_err());
+
+        let mut magic = BytesMut::from(&data[..]);
+        magic[0] = b'w';
+        assert!(Replay::parse(magic.freeze()).is_err());
+    }
+
+    #[test]
+    fn decompressed_size_is_not_trusted() {
+        let data = write(&records());
+        let mut header = ReplayHeader::read(&mut Cursor::new(data.clone())).unwrap();
+
+        // claims gigabytes, which only fails once the blocks don't hold that much
+        header.decompressed_size = 0xFFFF_FFF0;
+        let mut bogus = BytesMut::new();
+        header.write(&mut bogus);
+        bogus.extend_from_slice(&data[HEADER_LENGTH..]);
+        assert_eq!(Replay::parse(bogus.freeze()).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,297 @@
+// builds a replay as the game goes on, blocks are compressed as soon as they fill up so there's
+// little left to do once it's over
+
+use bytes::*;
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+
+use std::io::{self, Write};
+
+use super::*;
+
+pub struct ReplayWriter {
+    product: u32,
+    version: u32,
+    build: u16,
+    // what doesn't fill a block yet
+    data: BytesMut,
+    // the finished blocks, headers included
+    blocks: BytesMut,
+    block_count: u32,
+    // everything written so far, before compression
+    size: usize,
+    duration: u32
+}
+
+// pads the block to the full size, every block in the file is that long once inflated
+fn compress_block(data: &[u8], blocks: &mut BytesMut) -> io::Result<()> {
+    let mut block = data.to_vec();
+    block.resize(BLOCK_SIZE, 0);
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&block)?;
+    let compressed = encoder.finish()?;
+
+    let mut header = [0u8; BLOCK_HEADER_LENGTH];
+    E::write_u16(&mut header[0..2], compressed.len() as u16);
+    E::write_u16(&mut header[2..4], BLOCK_SIZE as u16);
+    let checksum = block_checksum(&header, &compressed);
+    E::write_u32(&mut header[4..8], checksum);
+
+    blocks.reserve(BLOCK_HEADER_LENGTH + compressed.len());
+    blocks.put_slice(&header);
+    blocks.put_slice(&compressed);
+    Ok(())
+}
+
+impl ReplayWriter {
+    pub fn new(product: u32, version: u32, build: u16, game: &ReplayGame) -> ReplayWriter {
+        let mut writer = ReplayWriter {
+            product,
+            version,
+            build,
+            data: BytesMut::with_capacity(BLOCK_SIZE),
+            blocks: BytesMut::new(),
+            block_count: 0,
+            size: 0,
+            duration: 0
+        };
+
+        let mut data = BytesMut::new();
+        game.write(&mut data);
+        writer.push(&data);
+        writer
+    }
+
+    pub fn duration(&self) -> u32 {
+        self.duration
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+        self.size += data.len();
+
+        while self.data.len() >= BLOCK_SIZE {
+            let block = self.data.split_to(BLOCK_SIZE);
+            // zlib into memory doesn't fail
+            compress_block(&block, &mut self.blocks).unwrap();
+            self.block_count += 1;
+        }
+    }
+
+    pub fn write(&mut self, record: &ReplayRecord) {
+        if let ReplayRecord::TimeSlot { interval, .. } = *record {
+            self.duration += interval as u32;
+        }
+
+        let mut data = BytesMut::new();
+        record.write(&mut data);
+        self.push(&data);
+    }
+
+    // the complete file, the last block gets padded with zeroes to the full block size
+    pub fn finish(mut self) -> io::Result<Bytes> {
+        if !self.data.is_empty() {
+            compress_block(&self.data, &mut self.blocks)?;
+            self.block_count += 1;
+        }
+
+        let header = ReplayHeader {
+            file_size: (HEADER_LENGTH + self.blocks.len()) as u32,
+            decompressed_size: self.size as u32,
+            blocks: self.block_count,
+            product: self.product,
+            version: self.version,
+            build: self.build,
+            flags: FLAG_MULTIPLAYER,
+            duration: self.duration
+        };
+
+        let mut file = BytesMut::with_capacity(HEADER_LENGTH + self.blocks.len());
+        header.write(&mut file);
+        file.put(self.blocks);
+        Ok(file.freeze())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use packets::statstring::{GameFlags, GameStat};
+    use packets::string::Encoding;
+    use w3gs::slots::SlotTable;
+
+    use std::io::Read;
+
+    fn name(text: &str) -> BnetString {
+        BnetString::encode(text, Encoding::Utf8).unwrap()
+    }
+
+    fn game() -> ReplayGame {
+        let mut slots = SlotTable::melee(2);
+        slots.occupy(2).unwrap();
+
+        ReplayGame {
+            host: ReplayPlayer::custom(1, name("tabeal")),
+            game_name: name("test game"),
+            stat: GameStat {
+                flags: GameFlags::default(),
+                map_width: 116,
+                map_height: 84,
+                map_crc: 0x1122_3344,
+                map_path: Bytes::from(&b"Maps\\Download\\test.w3x"[..]),
+                host_name: Bytes::from(&b"tabeal"[..]),
+                map_sha1: Some([7; 20])
+            },
+            game_type: GAME_TYPE_CUSTOM,
+            language: 0,
+            players: vec![ReplayPlayer::custom(2, name("first"))],
+            slots
+        }
+    }
+
+    fn game_data() -> Vec<u8> {
+        let mut data = BytesMut::new();
+        game().write(&mut data);
+        data.to_vec()
+    }
+
+    fn write(records: &[ReplayRecord]) -> Bytes {
+        let mut writer = ReplayWriter::new(0x5733_5850, 26, 6059, &game());
+        for record in records {
+            writer.write(record);
+        }
+        writer.finish().unwrap()
+    }
+
+    // checks the header crc and every block's checksum, then inflates the blocks
+    fn unpack(file: &Bytes) -> (ReplayHeader, Vec<u8>) {
+        let header = ReplayHeader::read(&mut Cursor::new(file.clone())).unwrap();
+        assert_eq!(header.file_size as usize, file.len());
+
+        let mut zeroed = file[..HEADER_LENGTH].to_vec();
+        zeroed[HEADER_LENGTH - 4..].copy_from_slice(&[0; 4]);
+        assert_eq!(E::read_u32(&file[HEADER_LENGTH - 4..HEADER_LENGTH]), crc32::checksum_ieee(&zeroed));
+
+        let mut data = Vec::new();
+        let mut offset = HEADER_LENGTH;
+        for _ in 0..header.blocks {
+            let mut block_header = file[offset..offset + BLOCK_HEADER_LENGTH].to_vec();
+            let length = E::read_u16(&block_header[0..2]) as usize;
+            assert_eq!(E::read_u16(&block_header[2..4]) as usize, BLOCK_SIZE);
+            let checksum = E::read_u32(&block_header[4..8]);
+            block_header[4..8].copy_from_slice(&[0; 4]);
+
+            let compressed = &file[offset + BLOCK_HEADER_LENGTH..offset + BLOCK_HEADER_LENGTH + length];
+            assert_eq!(block_checksum(&block_header, compressed), checksum);
+
+            let mut block = Vec::new();
+            ZlibDecoder::new(compressed).read_to_end(&mut block).unwrap();
+            assert_eq!(block.len(), BLOCK_SIZE);
+            data.extend(block);
+            offset += BLOCK_HEADER_LENGTH + length;
+        }
+        assert_eq!(offset, file.len());
+
+        // whatever is left of the last block is padding
+        assert!(data[header.decompressed_size as usize..].iter().all(|&x| x == 0));
+        data.truncate(header.decompressed_size as usize);
+        (header, data)
+    }
+
+    #[test]
+    fn header() {
+        let records = vec![
+            ReplayRecord::TimeSlot { interval: 100, actions: vec![] },
+            ReplayRecord::TimeSlot2 { interval: 50, actions: vec![] },
+            ReplayRecord::TimeSlot { interval: 250, actions: vec![] }
+        ];
+        let (header, data) = unpack(&write(&records));
+
+        assert_eq!(header.product, 0x5733_5850);
+        assert_eq!(header.version, 26);
+        assert_eq!(header.build, 6059);
+        assert_eq!(header.flags, FLAG_MULTIPLAYER);
+        // only time slots move the clock
+        assert_eq!(header.duration, 350);
+        assert_eq!(header.blocks, 1);
+        assert_eq!(header.decompressed_size as usize, data.len());
+    }
+
+    #[test]
+    fn game_data_first() {
+        let (_, data) = unpack(&write(&[]));
+
+        assert_eq!(data, game_data());
+        assert_eq!(&data[..4], &[0x10, 0x01, 0x00, 0x00]);
+        assert_eq!(&data[4..15], &b"\x00\x01tabeal\x00\x01\x00"[..]);
+
+        // the slot table closes it off, behind the game start record and its length
+        let slots = game().slots.encode();
+        let start = data.len() - slots.len() - 3;
+        assert_eq!(data[start], RECORD_GAME_START);
+        assert_eq!(E::read_u16(&data[start + 1..start + 3]) as usize, slots.len());
+        assert_eq!(&data[start + 3..], &slots[..]);
+    }
+
+    #[test]
+    fn records() {
+        let records = vec![
+            ReplayRecord::Start(RECORD_START_1),
+            ReplayRecord::Chat { pid: 2, mode: None, message: name("hi") },
+            ReplayRecord::TimeSlot { interval: 100, actions: vec![PlayerAction { pid: 2, data: Bytes::from_static(&[0x01]) }] },
+            ReplayRecord::TimeSlot2 { interval: 0, actions: vec![PlayerAction { pid: 2, data: Bytes::from_static(&[0x02, 0x03]) }] },
+            ReplayRecord::Chat { pid: 2, mode: Some(0), message: name("gg") },
+            ReplayRecord::Checksum(0xDEAD_BEEF),
+            ReplayRecord::Leave { reason: 0x0C, pid: 1, result: 0x07 }
+        ];
+        let (_, data) = unpack(&write(&records));
+
+        let expected: &[&[u8]] = &[
+            &[0x1A, 0x01, 0x00, 0x00, 0x00],
+            &[0x20, 0x02, 0x04, 0x00, 0x10, b'h', b'i', 0x00],
+            &[0x1F, 0x06, 0x00, 0x64, 0x00, 0x02, 0x01, 0x00, 0x01],
+            &[0x1E, 0x07, 0x00, 0x00, 0x00, 0x02, 0x02, 0x00, 0x02, 0x03],
+            &[0x20, 0x02, 0x08, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00, b'g', b'g', 0x00],
+            &[0x22, 0x04, 0xEF, 0xBE, 0xAD, 0xDE],
+            &[0x17, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x07, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]
+        ];
+        let game = game_data();
+        assert_eq!(&data[..game.len()], &game[..]);
+        assert_eq!(&data[game.len()..], &expected.concat()[..]);
+    }
+
+    #[test]
+    fn several_blocks() {
+        let actions = vec![PlayerAction { pid: 2, data: Bytes::from(vec![0x10; 15]) }];
+        let records: Vec<_> = (0..2000).map(|_| ReplayRecord::TimeSlot { interval: 100, actions: actions.clone() }).collect();
+        let (header, data) = unpack(&write(&records));
+
+        let game = game_data();
+        let length = game.len() + 2000 * (1 + 2 + 2 + 3 + 15);
+        assert_eq!(data.len(), length);
+        assert_eq!(header.blocks as usize, (length + BLOCK_SIZE - 1) / BLOCK_SIZE);
+        assert_eq!(header.duration, 200_000);
+
+        // a record cut in half by a block boundary reads on in the next one
+        let mut expected = BytesMut::new();
+        records[0].write(&mut expected);
+        for (i, record) in data[game.len()..].chunks(expected.len()).enumerate() {
+            assert_eq!(record, &expected[..], "record {}", i);
+        }
+    }
+
+    #[test]
+    fn blocks_are_compressed_as_they_fill() {
+        let mut writer = ReplayWriter::new(0x5733_5850, 26, 6059, &game());
+        let record = ReplayRecord::TimeSlot { interval: 100, actions: vec![PlayerAction { pid: 2, data: Bytes::from(vec![0x10; 15]) }] };
+        while writer.block_count < 2 {
+            writer.write(&record);
+        }
+
+        assert!(writer.data.len() < BLOCK_SIZE);
+        assert_eq!(writer.size, 2 * BLOCK_SIZE + writer.data.len());
+        assert_eq!(unpack(&writer.finish().unwrap()).0.blocks, 3);
+    }
+}
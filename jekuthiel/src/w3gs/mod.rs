@@ -0,0 +1,615 @@
+// reference: https://bnetdocs.org/packet/index (W3GS section) and GHost++'s gameprotocol.cpp
+#![allow(dead_code)]
+
+pub mod packets;
+pub mod slots;
+
+use bytes::*;
+use tokio_core::net::UdpCodec;
+use tokio_io::codec::{Encoder, Decoder};
+
+use std::io::{self, Cursor};
+use std::net::SocketAddr;
+
+use self::packets::*;
+use packets::string::Encoding;
+
+pub const W3GS_HEADER: u8 = 0xf7;
+pub const W3GS_HEADER_LENGTH: usize = 4;
+
+// WC3 clients speak nothing but UTF-8, unlike realm connections there's no other encoding to pick
+pub const ENCODING: Encoding = Encoding::Utf8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum W3GSPacketID {
+    PING_FROM_HOST         = 1,   // 0x1
+    SLOTINFOJOIN           = 4,   // 0x4
+    REJECTJOIN             = 5,   // 0x5
+    PLAYERINFO             = 6,   // 0x6
+    PLAYERLEAVE_OTHERS     = 7,   // 0x7
+    GAMELOADED_OTHERS      = 8,   // 0x8
+    SLOTINFO               = 9,   // 0x9
+    COUNTDOWN_START        = 10,  // 0xA
+    COUNTDOWN_END          = 11,  // 0xB
+    INCOMING_ACTION        = 12,  // 0xC
+    CHAT_FROM_HOST         = 15,  // 0xF
+    START_LAG              = 16,  // 0x10
+    STOP_LAG               = 17,  // 0x11
+    REQJOIN                = 30,  // 0x1E
+    LEAVEGAME              = 33,  // 0x21
+    GAMELOADED_SELF        = 35,  // 0x23
+    OUTGOING_ACTION        = 38,  // 0x26
+    OUTGOING_KEEPALIVE     = 39,  // 0x27
+    CHAT_TO_HOST           = 40,  // 0x28
+    DROPREQ                = 41,  // 0x29
+    SEARCHGAME             = 47,  // 0x2F
+    GAMEINFO               = 48,  // 0x30
+    CREATEGAME             = 49,  // 0x31
+    REFRESHGAME            = 50,  // 0x32
+    DECREATEGAME           = 51,  // 0x33
+    MAPCHECK               = 61,  // 0x3D
+    STARTDOWNLOAD          = 63,  // 0x3F
+    MAPSIZE                = 66,  // 0x42
+    MAPPART                = 67,  // 0x43
+    MAPPARTOK              = 68,  // 0x44
+    PONG_TO_HOST           = 70,  // 0x46
+    INCOMING_ACTION2       = 72,  // 0x48
+}
+
+impl W3GSPacketID {
+    pub fn from_id(id: u8) -> Option<W3GSPacketID> {
+        match id {
+            1 => Some(W3GSPacketID::PING_FROM_HOST),
+            4 => Some(W3GSPacketID::SLOTINFOJOIN),
+            5 => Some(W3GSPacketID::REJECTJOIN),
+            6 => Some(W3GSPacketID::PLAYERINFO),
+            7 => Some(W3GSPacketID::PLAYERLEAVE_OTHERS),
+            8 => Some(W3GSPacketID::GAMELOADED_OTHERS),
+            9 => Some(W3GSPacketID::SLOTINFO),
+            10 => Some(W3GSPacketID::COUNTDOWN_START),
+            11 => Some(W3GSPacketID::COUNTDOWN_END),
+            12 => Some(W3GSPacketID::INCOMING_ACTION),
+            15 => Some(W3GSPacketID::CHAT_FROM_HOST),
+            16 => Some(W3GSPacketID::START_LAG),
+            17 => Some(W3GSPacketID::STOP_LAG),
+            30 => Some(W3GSPacketID::REQJOIN),
+            33 => Some(W3GSPacketID::LEAVEGAME),
+            35 => Some(W3GSPacketID::GAMELOADED_SELF),
+            38 => Some(W3GSPacketID::OUTGOING_ACTION),
+            39 => Some(W3GSPacketID::OUTGOING_KEEPALIVE),
+            40 => Some(W3GSPacketID::CHAT_TO_HOST),
+            41 => Some(W3GSPacketID::DROPREQ),
+            47 => Some(W3GSPacketID::SEARCHGAME),
+            48 => Some(W3GSPacketID::GAMEINFO),
+            49 => Some(W3GSPacketID::CREATEGAME),
+            50 => Some(W3GSPacketID::REFRESHGAME),
+            51 => Some(W3GSPacketID::DECREATEGAME),
+            61 => Some(W3GSPacketID::MAPCHECK),
+            63 => Some(W3GSPacketID::STARTDOWNLOAD),
+            66 => Some(W3GSPacketID::MAPSIZE),
+            67 => Some(W3GSPacketID::MAPPART),
+            68 => Some(W3GSPacketID::MAPPARTOK),
+            70 => Some(W3GSPacketID::PONG_TO_HOST),
+            72 => Some(W3GSPacketID::INCOMING_ACTION2),
+            _ => None
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum W3GSPacket {
+    PingFromHost(u32),
+    SlotInfoJoin(SlotInfoJoin),
+    RejectJoin(u32),
+    PlayerInfo(PlayerInfo),
+    PlayerLeaveOthers(PlayerLeave),
+    GameLoadedOthers(u8),
+    SlotInfo(Bytes),
+    CountdownStart,
+    CountdownEnd,
+    IncomingAction(IncomingAction),
+    ChatFromHost(Chat),
+    StartLag(Vec<LagPlayer>),
+    StopLag(LagPlayer),
+    ReqJoin(ReqJoin),
+    LeaveGame(u32),
+    GameLoadedSelf,
+    OutgoingAction(OutgoingAction),
+    OutgoingKeepalive(OutgoingKeepalive),
+    ChatToHost(Chat),
+    // a player asks to drop everyone we're waiting for
+    DropReq,
+    SearchGame(SearchGame),
+    GameInfo(GameInfo),
+    CreateGame(CreateGame),
+    RefreshGame(RefreshGame),
+    DecreateGame(u32),
+    MapCheck(MapCheck),
+    StartDownload(StartDownload),
+    MapSize(MapSize),
+    MapPart(MapPart),
+    MapPartOk(MapPartOk),
+    PongToHost(u32),
+    // the part of an action batch that didn't fit into INCOMING_ACTION, sent right before it
+    IncomingAction2(IncomingAction),
+    // anything we can't parse yet, kept as-is so it can be logged or forwarded
+    Unknown {
+        id: u8,
+        body: Bytes
+    }
+}
+
+impl W3GSPacket {
+    pub fn id(&self) -> u8 {
+        let id = match *self {
+            W3GSPacket::PingFromHost(_) => W3GSPacketID::PING_FROM_HOST,
+            W3GSPacket::SlotInfoJoin(_) => W3GSPacketID::SLOTINFOJOIN,
+            W3GSPacket::RejectJoin(_) => W3GSPacketID::REJECTJOIN,
+            W3GSPacket::PlayerInfo(_) => W3GSPacketID::PLAYERINFO,
+            W3GSPacket::PlayerLeaveOthers(_) => W3GSPacketID::PLAYERLEAVE_OTHERS,
+            W3GSPacket::GameLoadedOthers(_) => W3GSPacketID::GAMELOADED_OTHERS,
+            W3GSPacket::SlotInfo(_) => W3GSPacketID::SLOTINFO,
+            W3GSPacket::CountdownStart => W3GSPacketID::COUNTDOWN_START,
+            W3GSPacket::CountdownEnd => W3GSPacketID::COUNTDOWN_END,
+            W3GSPacket::IncomingAction(_) => W3GSPacketID::INCOMING_ACTION,
+            W3GSPacket::ChatFromHost(_) => W3GSPacketID::CHAT_FROM_HOST,
+            W3GSPacket::StartLag(_) => W3GSPacketID::START_LAG,
+            W3GSPacket::StopLag(_) => W3GSPacketID::STOP_LAG,
+            W3GSPacket::ReqJoin(_) => W3GSPacketID::REQJOIN,
+            W3GSPacket::LeaveGame(_) => W3GSPacketID::LEAVEGAME,
+            W3GSPacket::GameLoadedSelf => W3GSPacketID::GAMELOADED_SELF,
+            W3GSPacket::OutgoingAction(_) => W3GSPacketID::OUTGOING_ACTION,
+            W3GSPacket::OutgoingKeepalive(_) => W3GSPacketID::OUTGOING_KEEPALIVE,
+            W3GSPacket::ChatToHost(_) => W3GSPacketID::CHAT_TO_HOST,
+            W3GSPacket::DropReq => W3GSPacketID::DROPREQ,
+            W3GSPacket::SearchGame(_) => W3GSPacketID::SEARCHGAME,
+            W3GSPacket::GameInfo(_) => W3GSPacketID::GAMEINFO,
+            W3GSPacket::CreateGame(_) => W3GSPacketID::CREATEGAME,
+            W3GSPacket::RefreshGame(_) => W3GSPacketID::REFRESHGAME,
+            W3GSPacket::DecreateGame(_) => W3GSPacketID::DECREATEGAME,
+            W3GSPacket::MapCheck(_) => W3GSPacketID::MAPCHECK,
+            W3GSPacket::StartDownload(_) => W3GSPacketID::STARTDOWNLOAD,
+            W3GSPacket::MapSize(_) => W3GSPacketID::MAPSIZE,
+            W3GSPacket::MapPart(_) => W3GSPacketID::MAPPART,
+            W3GSPacket::MapPartOk(_) => W3GSPacketID::MAPPARTOK,
+            W3GSPacket::PongToHost(_) => W3GSPacketID::PONG_TO_HOST,
+            W3GSPacket::IncomingAction2(_) => W3GSPacketID::INCOMING_ACTION2,
+            W3GSPacket::Unknown { id, .. } => return id
+        };
+
+        id.id()
+    }
+
+    fn write_body(&self, buf: &mut BytesMut) {
+        // enough for the fixed size packets, the rest reserve what they need themselves
+        buf.reserve(8);
+
+        match *self {
+            W3GSPacket::PingFromHost(value) | W3GSPacket::PongToHost(value) => buf.put_u32::<E>(value),
+            W3GSPacket::SlotInfoJoin(ref x) => x.write(buf),
+            W3GSPacket::RejectJoin(reason) | W3GSPacket::LeaveGame(reason) => buf.put_u32::<E>(reason),
+            W3GSPacket::PlayerInfo(ref x) => x.write(buf),
+            W3GSPacket::PlayerLeaveOthers(ref x) => x.write(buf),
+            W3GSPacket::GameLoadedOthers(pid) => buf.put(pid),
+            W3GSPacket::SlotInfo(ref slot_info) => {
+                // can't wrap once encode_into is happy with the length of the whole packet
+                buf.reserve(2 + slot_info.len());
+                buf.put_u16::<E>(slot_info.len() as u16);
+                buf.put(slot_info);
+            }
+            W3GSPacket::CountdownStart | W3GSPacket::CountdownEnd | W3GSPacket::GameLoadedSelf | W3GSPacket::DropReq => {}
+            W3GSPacket::IncomingAction(ref x) | W3GSPacket::IncomingAction2(ref x) => x.write(buf),
+            W3GSPacket::ChatFromHost(ref x) | W3GSPacket::ChatToHost(ref x) => x.write(buf),
+            W3GSPacket::StartLag(ref x) => write_start_lag(buf, x),
+            W3GSPacket::StopLag(ref x) => x.write(buf),
+            W3GSPacket::ReqJoin(ref x) => x.write(buf),
+            W3GSPacket::OutgoingAction(ref x) => x.write(buf),
+            W3GSPacket::OutgoingKeepalive(ref x) => x.write(buf),
+            W3GSPacket::SearchGame(ref x) => x.write(buf),
+            W3GSPacket::GameInfo(ref x) => x.write(buf),
+            W3GSPacket::CreateGame(ref x) => x.write(buf),
+            W3GSPacket::RefreshGame(ref x) => x.write(buf),
+            W3GSPacket::DecreateGame(host_counter) => buf.put_u32::<E>(host_counter),
+            W3GSPacket::MapCheck(ref x) => x.write(buf),
+            W3GSPacket::StartDownload(ref x) => x.write(buf),
+            W3GSPacket::MapSize(ref x) => x.write(buf),
+            W3GSPacket::MapPart(ref x) => x.write(buf),
+            W3GSPacket::MapPartOk(ref x) => x.write(buf),
+            W3GSPacket::Unknown { ref body, .. } => {
+                buf.reserve(body.len());
+                buf.put(body);
+            }
+        }
+    }
+
+    pub fn encode(&self) -> io::Result<Bytes> {
+        let mut buf = BytesMut::with_capacity(64);
+        self.encode_into(&mut buf)?;
+        Ok(buf.freeze())
+    }
+
+    // packets too long for the length field leave the buffer as it was
+    pub fn encode_into(&self, buf: &mut BytesMut) -> io::Result<()> {
+        let start = buf.len();
+        buf.reserve(W3GS_HEADER_LENGTH);
+        buf.put(W3GS_HEADER);
+        buf.put(self.id());
+        buf.put_u16::<E>(0);
+
+        self.write_body(buf);
+
+        // length is only known once the body is written
+        let length = buf.len() - start;
+        if length > u16::MAX as usize {
+            buf.truncate(start);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "W3GS packet too long"));
+        }
+        buf[start + 2] = (length & 0x00ff) as u8;
+        buf[start + 3] = (length >> 8) as u8;
+        Ok(())
+    }
+
+    // a single packet making up all of the data, the way they arrive over UDP
+    pub fn from_datagram(data: &[u8]) -> io::Result<W3GSPacket> {
+        if data.len() < W3GS_HEADER_LENGTH || data[0] != W3GS_HEADER {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid W3GS header"));
+        }
+
+        let length = (data[2] as usize) | ((data[3] as usize) << 8);
+        if length < W3GS_HEADER_LENGTH || length > data.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid W3GS packet length"));
+        }
+
+        W3GSPacket::decode(data[1], Bytes::from(&data[W3GS_HEADER_LENGTH..length]))
+    }
+
+    pub fn decode(id: u8, body: Bytes) -> io::Result<W3GSPacket> {
+        let mut buf = Cursor::new(body.clone());
+        let buf = &mut buf;
+
+        let packet = match W3GSPacketID::from_id(id) {
+            Some(W3GSPacketID::PING_FROM_HOST) => W3GSPacket::PingFromHost(read_u32(buf)?),
+            Some(W3GSPacketID::SLOTINFOJOIN) => W3GSPacket::SlotInfoJoin(SlotInfoJoin::read(buf)?),
+            Some(W3GSPacketID::REJECTJOIN) => W3GSPacket::RejectJoin(read_u32(buf)?),
+            Some(W3GSPacketID::PLAYERINFO) => W3GSPacket::PlayerInfo(PlayerInfo::read(buf)?),
+            Some(W3GSPacketID::PLAYERLEAVE_OTHERS) => W3GSPacket::PlayerLeaveOthers(PlayerLeave::read(buf)?),
+            Some(W3GSPacketID::GAMELOADED_OTHERS) => W3GSPacket::GameLoadedOthers(read_u8(buf)?),
+            Some(W3GSPacketID::SLOTINFO) => {
+                let length = read_u16(buf)? as usize;
+                W3GSPacket::SlotInfo(read_bytes(buf, length)?)
+            }
+            Some(W3GSPacketID::COUNTDOWN_START) => W3GSPacket::CountdownStart,
+            Some(W3GSPacketID::COUNTDOWN_END) => W3GSPacket::CountdownEnd,
+            Some(W3GSPacketID::INCOMING_ACTION) => W3GSPacket::IncomingAction(IncomingAction::read(buf)?),
+            Some(W3GSPacketID::CHAT_FROM_HOST) => W3GSPacket::ChatFromHost(Chat::read(buf)?),
+            Some(W3GSPacketID::START_LAG) => W3GSPacket::StartLag(read_start_lag(buf)?),
+            Some(W3GSPacketID::STOP_LAG) => W3GSPacket::StopLag(LagPlayer::read(buf)?),
+            Some(W3GSPacketID::REQJOIN) => W3GSPacket::ReqJoin(ReqJoin::read(buf)?),
+            Some(W3GSPacketID::LEAVEGAME) => W3GSPacket::LeaveGame(read_u32(buf)?),
+            Some(W3GSPacketID::GAMELOADED_SELF) => W3GSPacket::GameLoadedSelf,
+            Some(W3GSPacketID::OUTGOING_ACTION) => W3GSPacket::OutgoingAction(OutgoingAction::read(buf)?),
+            Some(W3GSPacketID::OUTGOING_KEEPALIVE) => W3GSPacket::OutgoingKeepalive(OutgoingKeepalive::read(buf)?),
+            Some(W3GSPacketID::CHAT_TO_HOST) => W3GSPacket::ChatToHost(Chat::read(buf)?),
+            Some(W3GSPacketID::DROPREQ) => W3GSPacket::DropReq,
+            Some(W3GSPacketID::SEARCHGAME) => W3GSPacket::SearchGame(SearchGame::read(buf)?),
+            Some(W3GSPacketID::GAMEINFO) => W3GSPacket::GameInfo(GameInfo::read(buf)?),
+            Some(W3GSPacketID::CREATEGAME) => W3GSPacket::CreateGame(CreateGame::read(buf)?),
+            Some(W3GSPacketID::REFRESHGAME) => W3GSPacket::RefreshGame(RefreshGame::read(buf)?),
+            Some(W3GSPacketID::DECREATEGAME) => W3GSPacket::DecreateGame(read_u32(buf)?),
+            Some(W3GSPacketID::MAPCHECK) => W3GSPacket::MapCheck(MapCheck::read(buf)?),
+            Some(W3GSPacketID::STARTDOWNLOAD) => W3GSPacket::StartDownload(StartDownload::read(buf)?),
+            Some(W3GSPacketID::MAPSIZE) => W3GSPacket::MapSize(MapSize::read(buf)?),
+            Some(W3GSPacketID::MAPPART) => W3GSPacket::MapPart(MapPart::read(buf)?),
+            Some(W3GSPacketID::MAPPARTOK) => W3GSPacket::MapPartOk(MapPartOk::read(buf)?),
+            Some(W3GSPacketID::PONG_TO_HOST) => W3GSPacket::PongToHost(read_u32(buf)?),
+            Some(W3GSPacketID::INCOMING_ACTION2) => W3GSPacket::IncomingAction2(IncomingAction::read(buf)?),
+            None => W3GSPacket::Unknown { id, body }
+        };
+
+        Ok(packet)
+    }
+}
+
+enum DecodeState {
+    Header,
+    Body(u8, usize)
+}
+
+// same framing as BNetPCodec, with a different header byte and typed packets both ways
+pub struct W3GSCodec {
+    state: DecodeState
+}
+
+impl W3GSCodec {
+    pub fn new() -> W3GSCodec {
+        W3GSCodec {
+            state: DecodeState::Header
+        }
+    }
+}
+
+impl Encoder for W3GSCodec {
+    type Item = W3GSPacket;
+    type Error = io::Error;
+
+    fn encode(&mut self, item: W3GSPacket, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        item.encode_into(dst)
+    }
+}
+
+impl Decoder for W3GSCodec {
+    type Item = W3GSPacket;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let DecodeState::Header = self.state {
+            if src.len() < W3GS_HEADER_LENGTH {
+                return Ok(None);
+            }
+
+            if src[0] != W3GS_HEADER {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid W3GS header"));
+            }
+
+            let id = src[1];
+            let length = (src[2] as usize) | ((src[3] as usize) << 8);
+
+            if length < W3GS_HEADER_LENGTH {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid W3GS packet length"));
+            }
+
+            self.state = DecodeState::Body(id, length);
+        }
+
+        if let DecodeState::Body(id, length) = self.state {
+            if src.len() < length {
+                return Ok(None);
+            }
+
+            let mut frame = src.split_to(length);
+            frame.advance(W3GS_HEADER_LENGTH);
+            self.state = DecodeState::Header;
+
+            return W3GSPacket::decode(id, frame.freeze()).map(Some);
+        }
+
+        Ok(None)
+    }
+}
+
+// one packet per datagram, for finding games on the LAN. anyone on the network can send us
+// anything, so datagrams that don't parse come out as None instead of ending the stream
+pub struct W3GSUdpCodec;
+
+impl UdpCodec for W3GSUdpCodec {
+    type In = (SocketAddr, Option<W3GSPacket>);
+    type Out = (SocketAddr, W3GSPacket);
+
+    fn decode(&mut self, src: &SocketAddr, buf: &[u8]) -> io::Result<Self::In> {
+        Ok((*src, W3GSPacket::from_datagram(buf).ok()))
+    }
+
+    fn encode(&mut self, (address, packet): Self::Out, buf: &mut Vec<u8>) -> SocketAddr {
+        // nothing that long fits in a datagram, the empty one that goes out instead is ignored
+        if let Ok(data) = packet.encode() {
+            buf.extend_from_slice(&data);
+        }
+        address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use packets::string::{BnetString, Encoding};
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    fn string(text: &str) -> BnetString {
+        BnetString::encode(text, Encoding::Utf8).unwrap()
+    }
+
+    fn address(port: u16) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 20), port)
+    }
+
+    fn chat(command: ChatCommand) -> Chat {
+        Chat {
+            to_pids: vec![2, 3],
+            from_pid: 1,
+            command
+        }
+    }
+
+    fn map_part() -> MapPart {
+        MapPart {
+            to_pid: 2,
+            from_pid: 1,
+            position: 1442,
+            data: Bytes::from(&b"some map data"[..])
+        }
+    }
+
+    // one of every packet we know
+    fn packets() -> Vec<W3GSPacket> {
+        let actions = IncomingAction {
+            send_interval: 100,
+            actions: vec![
+                PlayerAction { pid: 1, data: Bytes::from(&[0x10, 0x42, 0x00][..]) },
+                PlayerAction { pid: 2, data: Bytes::from(&[0x16, 0x01][..]) }
+            ]
+        };
+
+        vec![
+            W3GSPacket::PingFromHost(0x1234_5678),
+            W3GSPacket::SlotInfoJoin(SlotInfoJoin { slot_info: Bytes::from(&[1, 2, 3][..]), pid: 2, address: address(6112) }),
+            W3GSPacket::RejectJoin(RejectReason::Full as u32),
+            W3GSPacket::PlayerInfo(PlayerInfo { join_counter: 2, pid: 3, name: string("Grubby"), external_address: address(6113), internal_address: address(6114) }),
+            W3GSPacket::PlayerLeaveOthers(PlayerLeave { pid: 3, reason: LeaveReason::Lobby as u32 }),
+            W3GSPacket::GameLoadedOthers(3),
+            W3GSPacket::SlotInfo(Bytes::from(&[4, 5, 6, 7][..])),
+            W3GSPacket::CountdownStart,
+            W3GSPacket::CountdownEnd,
+            W3GSPacket::IncomingAction(actions.clone()),
+            W3GSPacket::IncomingAction(IncomingAction { send_interval: 100, actions: Vec::new() }),
+            W3GSPacket::ChatFromHost(chat(ChatCommand::Message(string("hello")))),
+            W3GSPacket::StartLag(vec![LagPlayer { pid: 2, lag_time: 500 }, LagPlayer { pid: 4, lag_time: 0 }]),
+            W3GSPacket::StopLag(LagPlayer { pid: 2, lag_time: 1500 }),
+            W3GSPacket::ReqJoin(ReqJoin { host_counter: 7, entry_key: 0xdead_beef, unknown: 0, listen_port: 6112, peer_key: 9, name: string("Moon"), unknown2: 0, internal_address: address(6112) }),
+            W3GSPacket::LeaveGame(LeaveReason::Lost as u32),
+            W3GSPacket::GameLoadedSelf,
+            W3GSPacket::OutgoingAction(OutgoingAction { crc: 0x1111_2222, data: Bytes::from(&[0x10, 0x42][..]) }),
+            W3GSPacket::OutgoingKeepalive(OutgoingKeepalive { unknown: 0, checksum: 0xcafe_babe }),
+            W3GSPacket::ChatToHost(chat(ChatCommand::TeamChange(1))),
+            W3GSPacket::ChatToHost(chat(ChatCommand::ColorChange(2))),
+            W3GSPacket::ChatToHost(chat(ChatCommand::RaceChange(0x40))),
+            W3GSPacket::ChatToHost(chat(ChatCommand::HandicapChange(90))),
+            W3GSPacket::ChatToHost(chat(ChatCommand::MessageExtra(0, string("gl hf")))),
+            W3GSPacket::DropReq,
+            W3GSPacket::SearchGame(SearchGame { product: PRODUCT_TFT, version: 26 }),
+            W3GSPacket::GameInfo(GameInfo { product: PRODUCT_TFT, version: 26, host_counter: 7, entry_key: 9, game_name: string("test game"), stat: Bytes::from(&b"\x01\x03\x05"[..]), slots_total: 12, game_type: GAMEINFO_TYPE_CUSTOM, slots_open: 10, uptime: 60, port: 6112 }),
+            W3GSPacket::CreateGame(CreateGame { product: PRODUCT_TFT, version: 26, host_counter: 7 }),
+            W3GSPacket::RefreshGame(RefreshGame { host_counter: 7, players: 2, slots: 12 }),
+            W3GSPacket::DecreateGame(7),
+            W3GSPacket::MapCheck(MapCheck { map_path: Bytes::from(&b"Maps\\Download\\x.w3x"[..]), map_size: 1000, map_info: 1, map_crc: 2, map_sha1: Some([3; 20]) }),
+            W3GSPacket::MapCheck(MapCheck { map_path: Bytes::from(&b"Maps\\x.w3m"[..]), map_size: 1000, map_info: 1, map_crc: 2, map_sha1: None }),
+            W3GSPacket::StartDownload(StartDownload { from_pid: 1 }),
+            W3GSPacket::MapSize(MapSize { size_flag: MapSizeFlag::Downloading as u8, map_size: 1000 }),
+            W3GSPacket::MapPart(map_part()),
+            W3GSPacket::MapPartOk(MapPartOk { from_pid: 2, to_pid: 1, position: 2884 }),
+            W3GSPacket::PongToHost(0x1234_5678),
+            W3GSPacket::IncomingAction2(actions),
+            W3GSPacket::Unknown { id: 0x77, body: Bytes::from(&[1, 0, 2][..]) }
+        ]
+    }
+
+    #[test]
+    fn packet_ids_round_trip() {
+        for id in 0..256u32 {
+            if let Some(packet_id) = W3GSPacketID::from_id(id as u8) {
+                assert_eq!(packet_id.id(), id as u8);
+            }
+        }
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        for packet in packets() {
+            let encoded = packet.encode().unwrap();
+            let length = (encoded[2] as usize) | ((encoded[3] as usize) << 8);
+
+            assert_eq!(encoded[0], W3GS_HEADER);
+            assert_eq!(encoded[1], packet.id());
+            assert_eq!(length, encoded.len());
+            assert_eq!(W3GSPacket::from_datagram(&encoded).unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn known_bytes() {
+        assert_eq!(&W3GSPacket::PingFromHost(0x1234_5678).encode().unwrap()[..], &[0xf7, 0x01, 0x08, 0x00, 0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(&W3GSPacket::CountdownStart.encode().unwrap()[..], &[0xf7, 0x0a, 0x04, 0x00]);
+        assert_eq!(&W3GSPacket::RejectJoin(RejectReason::Full as u32).encode().unwrap()[..], &[0xf7, 0x05, 0x08, 0x00, 0x09, 0x00, 0x00, 0x00]);
+        assert_eq!(&W3GSPacket::PlayerLeaveOthers(PlayerLeave { pid: 3, reason: 0x0d }).encode().unwrap()[..],
+            &[0xf7, 0x07, 0x09, 0x00, 0x03, 0x0d, 0x00, 0x00, 0x00]);
+        assert_eq!(&W3GSPacket::ChatFromHost(chat(ChatCommand::Message(string("hi")))).encode().unwrap()[..],
+            &[0xf7, 0x0f, 0x0c, 0x00, 0x02, 0x02, 0x03, 0x01, 0x10, b'h', b'i', 0x00]);
+    }
+
+    #[test]
+    fn codec_round_trip() {
+        let mut codec = W3GSCodec::new();
+        let mut buf = BytesMut::new();
+        for packet in packets() {
+            codec.encode(packet, &mut buf).unwrap();
+        }
+
+        let mut decoded = Vec::new();
+        while let Some(packet) = codec.decode(&mut buf).unwrap() {
+            decoded.push(packet);
+        }
+        assert_eq!(decoded, packets());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn codec_waits_for_the_whole_frame() {
+        let encoded = W3GSPacket::PongToHost(5).encode().unwrap();
+        let mut codec = W3GSCodec::new();
+        let mut buf = BytesMut::from(&encoded[..2]);
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(&encoded[2..6]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(&encoded[6..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(W3GSPacket::PongToHost(5)));
+    }
+
+    #[test]
+    fn codec_rejects_bad_headers() {
+        let kind = |data: &[u8]| W3GSCodec::new().decode(&mut BytesMut::from(data)).err().map(|e| e.kind());
+
+        assert_eq!(kind(&[0xff, 0x01, 0x08, 0x00]), Some(io::ErrorKind::InvalidData));
+        assert_eq!(kind(&[0xf7, 0x01, 0x03, 0x00]), Some(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        for packet in packets() {
+            let encoded = packet.encode().unwrap();
+            if encoded.len() == W3GS_HEADER_LENGTH {
+                continue;
+            }
+            match packet {
+                // these read whatever is left or ignore a trailing field, a shorter body is
+                // still a valid packet
+                W3GSPacket::IncomingAction(_) | W3GSPacket::IncomingAction2(_) | W3GSPacket::OutgoingAction(_) |
+                W3GSPacket::MapCheck(_) | W3GSPacket::MapPart(_) | W3GSPacket::SearchGame(_) |
+                W3GSPacket::Unknown { .. } => continue,
+                _ => {}
+            }
+
+            let body = encoded.slice_from(W3GS_HEADER_LENGTH);
+            let truncated = body.slice_to(body.len() - 1);
+            assert!(W3GSPacket::decode(packet.id(), truncated).is_err(), "{:?}", packet);
+        }
+    }
+
+    #[test]
+    fn rejects_map_parts_with_bad_crc() {
+        let mut encoded = BytesMut::from(&W3GSPacket::MapPart(map_part()).encode().unwrap()[..]);
+        let last = encoded.len() - 1;
+        encoded[last] ^= 0xff;
+
+        assert!(W3GSPacket::from_datagram(&encoded).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_chat_flags() {
+        let body = [0x01, 0x02, 0x01, 0x55, 0x00];
+        assert!(W3GSPacket::decode(W3GSPacketID::CHAT_TO_HOST.id(), Bytes::from(&body[..])).is_err());
+    }
+
+    #[test]
+    fn datagram_length_is_checked() {
+        let mut encoded = BytesMut::from(&W3GSPacket::DecreateGame(7).encode().unwrap()[..]);
+        encoded[2] = 0x10;
+        assert!(W3GSPacket::from_datagram(&encoded).is_err());
+    }
+
+    #[test]
+    fn refuses_packets_too_long_for_the_length_field() {
+        let mut buf = BytesMut::from(&W3GSPacket::CountdownStart.encode().unwrap()[..]);
+        let packet = W3GSPacket::SlotInfo(Bytes::from(vec![0; 0xffff]));
+        assert!(packet.encode_into(&mut buf).is_err());
+        assert_eq!(&buf[..], &[0xf7, 0x0a, 0x04, 0x00]);
+
+        // the longest one that still fits
+        let packet = W3GSPacket::SlotInfo(Bytes::from(vec![0; 0xffff - 6]));
+        let encoded = packet.encode().unwrap();
+        assert_eq!(&encoded[2..4], &[0xff, 0xff]);
+    }
+}
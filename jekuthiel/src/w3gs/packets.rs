@@ -0,0 +1,813 @@
+#![allow(dead_code)]
+
+use bytes::*;
+use crc::crc32;
+
+use std::io::{self, Cursor};
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use packets::string::BnetString;
+
+pub type E = LittleEndian;
+
+type R = Cursor<Bytes>;
+
+// W3GS packets come from whoever connects to us, so nothing here is allowed to panic on a
+// short packet
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "truncated W3GS packet")
+}
+
+pub fn read_u8(buf: &mut R) -> io::Result<u8> {
+    if buf.remaining() < 1 {
+        return Err(truncated());
+    }
+    Ok(buf.get_u8())
+}
+
+pub fn read_u16(buf: &mut R) -> io::Result<u16> {
+    if buf.remaining() < 2 {
+        return Err(truncated());
+    }
+    Ok(buf.get_u16::<E>())
+}
+
+pub fn read_u32(buf: &mut R) -> io::Result<u32> {
+    if buf.remaining() < 4 {
+        return Err(truncated());
+    }
+    Ok(buf.get_u32::<E>())
+}
+
+pub fn read_bytes(buf: &mut R, length: usize) -> io::Result<Bytes> {
+    if buf.remaining() < length {
+        return Err(truncated());
+    }
+    let start = buf.position() as usize;
+    let slice = buf.get_ref().slice(start, start + length);
+    buf.advance(length);
+    Ok(slice)
+}
+
+pub fn read_rest(buf: &mut R) -> Bytes {
+    let length = buf.remaining();
+    read_bytes(buf, length).unwrap()
+}
+
+pub fn read_cstring(buf: &mut R) -> io::Result<Bytes> {
+    let length = buf.bytes().iter().position(|&c| c == 0).ok_or_else(truncated)?;
+    let slice = read_bytes(buf, length)?;
+    // skip null byte
+    buf.advance(1);
+    Ok(slice)
+}
+
+pub fn read_string(buf: &mut R) -> io::Result<BnetString> {
+    Ok(BnetString::from_bytes_unchecked(read_cstring(buf)?))
+}
+
+// sockaddr_in with the family in little endian and the rest in network byte order
+pub fn read_sockaddr(buf: &mut R) -> io::Result<SocketAddrV4> {
+    if buf.remaining() < 16 {
+        return Err(truncated());
+    }
+    let _family = buf.get_u16::<E>();
+    let port = buf.get_u16::<BigEndian>();
+    let mut ip = [0u8; 4];
+    buf.copy_to_slice(&mut ip);
+    // sin_zero
+    buf.advance(8);
+    Ok(SocketAddrV4::new(Ipv4Addr::from(ip), port))
+}
+
+pub fn write_sockaddr(buf: &mut BytesMut, address: &SocketAddrV4) {
+    buf.reserve(16);
+    // AF_INET
+    buf.put_u16::<E>(2);
+    buf.put_u16::<BigEndian>(address.port());
+    buf.put_slice(&address.ip().octets());
+    buf.put_slice(&[0u8; 8]);
+}
+
+pub fn write_cstring(buf: &mut BytesMut, string: &[u8]) {
+    buf.reserve(string.len() + 1);
+    buf.put(string);
+    buf.put(0u8);
+}
+
+fn read_sha1(buf: &mut R) -> io::Result<Option<[u8; 20]>> {
+    if buf.remaining() < 20 {
+        return Ok(None);
+    }
+    let mut sha1 = [0u8; 20];
+    buf.copy_to_slice(&mut sha1);
+    Ok(Some(sha1))
+}
+
+// "W3XP" and "WAR3", stored reversed on the wire like the realm product ids
+pub const PRODUCT_TFT: u32 = 0x5733_5850;
+pub const PRODUCT_ROC: u32 = 0x5741_5233;
+
+// the game type GAMEINFO carries for custom games
+pub const GAMEINFO_TYPE_CUSTOM: u32 = 0x01;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RejectReason {
+    Full = 0x09,
+    Started = 0x0A,
+    WrongPassword = 0x1B
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeaveReason {
+    Disconnect = 0x01,
+    Lost = 0x07,
+    LostBuildings = 0x08,
+    Won = 0x09,
+    Draw = 0x0A,
+    Observer = 0x0B,
+    Lobby = 0x0D
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlotInfoJoin {
+    pub slot_info: Bytes,
+    pub pid: u8,
+    pub address: SocketAddrV4
+}
+
+impl SlotInfoJoin {
+    pub fn write(&self, buf: &mut BytesMut) {
+        // can't wrap once encode_into is happy with the length of the whole packet
+        buf.reserve(2 + self.slot_info.len() + 1);
+        buf.put_u16::<E>(self.slot_info.len() as u16);
+        buf.put(&self.slot_info);
+        buf.put(self.pid);
+        write_sockaddr(buf, &self.address);
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        let length = read_u16(buf)? as usize;
+        let slot_info = read_bytes(buf, length)?;
+        let pid = read_u8(buf)?;
+        let address = read_sockaddr(buf)?;
+
+        Ok(SlotInfoJoin {
+            slot_info,
+            pid,
+            address
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlayerInfo {
+    pub join_counter: u32,
+    pub pid: u8,
+    pub name: BnetString,
+    pub external_address: SocketAddrV4,
+    pub internal_address: SocketAddrV4
+}
+
+impl PlayerInfo {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(4 + 1 + 2);
+        buf.put_u32::<E>(self.join_counter);
+        buf.put(self.pid);
+        write_cstring(buf, self.name.as_bytes());
+        buf.reserve(2);
+        // unknown, always 1
+        buf.put_u16::<E>(1);
+        write_sockaddr(buf, &self.external_address);
+        write_sockaddr(buf, &self.internal_address);
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        let join_counter = read_u32(buf)?;
+        let pid = read_u8(buf)?;
+        let name = read_string(buf)?;
+        let _unknown = read_u16(buf)?;
+        let external_address = read_sockaddr(buf)?;
+        let internal_address = read_sockaddr(buf)?;
+
+        Ok(PlayerInfo {
+            join_counter,
+            pid,
+            name,
+            external_address,
+            internal_address
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlayerLeave {
+    pub pid: u8,
+    pub reason: u32
+}
+
+impl PlayerLeave {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(5);
+        buf.put(self.pid);
+        buf.put_u32::<E>(self.reason);
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        Ok(PlayerLeave {
+            pid: read_u8(buf)?,
+            reason: read_u32(buf)?
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlayerAction {
+    pub pid: u8,
+    pub data: Bytes
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IncomingAction {
+    pub send_interval: u16,
+    pub actions: Vec<PlayerAction>
+}
+
+impl IncomingAction {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(2);
+        buf.put_u16::<E>(self.send_interval);
+
+        if self.actions.is_empty() {
+            return;
+        }
+
+        let length = self.actions.iter().map(|x| 3 + x.data.len()).sum();
+        let mut actions = BytesMut::with_capacity(length);
+        for action in &self.actions {
+            actions.put(action.pid);
+            actions.put_u16::<E>(action.data.len() as u16);
+            actions.put(&action.data);
+        }
+
+        // only the low half of the crc is sent
+        buf.reserve(2 + actions.len());
+        buf.put_u16::<E>(crc32::checksum_ieee(&actions) as u16);
+        buf.put(actions);
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        let send_interval = read_u16(buf)?;
+        let mut actions = Vec::new();
+
+        if buf.has_remaining() {
+            let _crc = read_u16(buf)?;
+            while buf.has_remaining() {
+                let pid = read_u8(buf)?;
+                let length = read_u16(buf)? as usize;
+                let data = read_bytes(buf, length)?;
+                actions.push(PlayerAction { pid, data });
+            }
+        }
+
+        Ok(IncomingAction {
+            send_interval,
+            actions
+        })
+    }
+}
+
+// a player the others are waiting for, lag_time is how long they've been waiting in milliseconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LagPlayer {
+    pub pid: u8,
+    pub lag_time: u32
+}
+
+impl LagPlayer {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(5);
+        buf.put(self.pid);
+        buf.put_u32::<E>(self.lag_time);
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        Ok(LagPlayer {
+            pid: read_u8(buf)?,
+            lag_time: read_u32(buf)?
+        })
+    }
+}
+
+pub fn write_start_lag(buf: &mut BytesMut, players: &[LagPlayer]) {
+    buf.reserve(1);
+    buf.put(players.len() as u8);
+    for player in players {
+        player.write(buf);
+    }
+}
+
+pub fn read_start_lag(buf: &mut R) -> io::Result<Vec<LagPlayer>> {
+    let count = read_u8(buf)?;
+    (0..count).map(|_| LagPlayer::read(buf)).collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChatFlag {
+    Message = 0x10,
+    TeamChange = 0x11,
+    ColorChange = 0x12,
+    RaceChange = 0x13,
+    HandicapChange = 0x14,
+    MessageExtra = 0x20
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChatCommand {
+    Message(BnetString),
+    TeamChange(u8),
+    ColorChange(u8),
+    RaceChange(u8),
+    HandicapChange(u8),
+    // in-game chat, the extra flags select the recipients (all, allies, observers, ...)
+    MessageExtra(u32, BnetString)
+}
+
+// shared by CHAT_TO_HOST and CHAT_FROM_HOST, lobby slot changes are sent as chat commands too
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chat {
+    pub to_pids: Vec<u8>,
+    pub from_pid: u8,
+    pub command: ChatCommand
+}
+
+impl Chat {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(1 + self.to_pids.len() + 1 + 1 + 4);
+        buf.put(self.to_pids.len() as u8);
+        buf.put_slice(&self.to_pids);
+        buf.put(self.from_pid);
+
+        match self.command {
+            ChatCommand::Message(ref message) => {
+                buf.put(ChatFlag::Message as u8);
+                write_cstring(buf, message.as_bytes());
+            }
+            ChatCommand::TeamChange(x) => {
+                buf.put(ChatFlag::TeamChange as u8);
+                buf.put(x);
+            }
+            ChatCommand::ColorChange(x) => {
+                buf.put(ChatFlag::ColorChange as u8);
+                buf.put(x);
+            }
+            ChatCommand::RaceChange(x) => {
+                buf.put(ChatFlag::RaceChange as u8);
+                buf.put(x);
+            }
+            ChatCommand::HandicapChange(x) => {
+                buf.put(ChatFlag::HandicapChange as u8);
+                buf.put(x);
+            }
+            ChatCommand::MessageExtra(flags, ref message) => {
+                buf.put(ChatFlag::MessageExtra as u8);
+                buf.put_u32::<E>(flags);
+                write_cstring(buf, message.as_bytes());
+            }
+        }
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        let count = read_u8(buf)? as usize;
+        let to_pids = read_bytes(buf, count)?.to_vec();
+        let from_pid = read_u8(buf)?;
+
+        let command = match read_u8(buf)? {
+            0x10 => ChatCommand::Message(read_string(buf)?),
+            0x11 => ChatCommand::TeamChange(read_u8(buf)?),
+            0x12 => ChatCommand::ColorChange(read_u8(buf)?),
+            0x13 => ChatCommand::RaceChange(read_u8(buf)?),
+            0x14 => ChatCommand::HandicapChange(read_u8(buf)?),
+            0x20 => {
+                let flags = read_u32(buf)?;
+                ChatCommand::MessageExtra(flags, read_string(buf)?)
+            }
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown W3GS chat flag"))
+        };
+
+        Ok(Chat {
+            to_pids,
+            from_pid,
+            command
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReqJoin {
+    pub host_counter: u32,
+    pub entry_key: u32,
+    pub unknown: u8,
+    pub listen_port: u16,
+    pub peer_key: u32,
+    pub name: BnetString,
+    pub unknown2: u32,
+    pub internal_address: SocketAddrV4
+}
+
+impl ReqJoin {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(4 + 4 + 1 + 2 + 4);
+        buf.put_u32::<E>(self.host_counter);
+        buf.put_u32::<E>(self.entry_key);
+        buf.put(self.unknown);
+        buf.put_u16::<E>(self.listen_port);
+        buf.put_u32::<E>(self.peer_key);
+        write_cstring(buf, self.name.as_bytes());
+        buf.reserve(4 + 2 + 4);
+        buf.put_u32::<E>(self.unknown2);
+        buf.put_u16::<E>(self.internal_address.port());
+        buf.put_slice(&self.internal_address.ip().octets());
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        let host_counter = read_u32(buf)?;
+        let entry_key = read_u32(buf)?;
+        let unknown = read_u8(buf)?;
+        let listen_port = read_u16(buf)?;
+        let peer_key = read_u32(buf)?;
+        let name = read_string(buf)?;
+        let unknown2 = read_u32(buf)?;
+        let internal_port = read_u16(buf)?;
+        let mut internal_ip = [0u8; 4];
+        internal_ip.copy_from_slice(&read_bytes(buf, 4)?);
+
+        Ok(ReqJoin {
+            host_counter,
+            entry_key,
+            unknown,
+            listen_port,
+            peer_key,
+            name,
+            unknown2,
+            internal_address: SocketAddrV4::new(Ipv4Addr::from(internal_ip), internal_port)
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutgoingAction {
+    pub crc: u32,
+    pub data: Bytes
+}
+
+impl OutgoingAction {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(4 + self.data.len());
+        buf.put_u32::<E>(self.crc);
+        buf.put(&self.data);
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        Ok(OutgoingAction {
+            crc: read_u32(buf)?,
+            data: read_rest(buf)
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutgoingKeepalive {
+    pub unknown: u8,
+    pub checksum: u32
+}
+
+impl OutgoingKeepalive {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(5);
+        buf.put(self.unknown);
+        buf.put_u32::<E>(self.checksum);
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        Ok(OutgoingKeepalive {
+            unknown: read_u8(buf)?,
+            checksum: read_u32(buf)?
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchGame {
+    pub product: u32,
+    pub version: u32
+}
+
+impl SearchGame {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(12);
+        buf.put_u32::<E>(self.product);
+        buf.put_u32::<E>(self.version);
+        buf.put_u32::<E>(0);
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        let product = read_u32(buf)?;
+        let version = read_u32(buf)?;
+
+        Ok(SearchGame {
+            product,
+            version
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameInfo {
+    pub product: u32,
+    pub version: u32,
+    pub host_counter: u32,
+    pub entry_key: u32,
+    pub game_name: BnetString,
+    // encoded GameStat, without the slot count and host counter prefix used on the realm
+    pub stat: Bytes,
+    pub slots_total: u32,
+    pub game_type: u32,
+    pub slots_open: u32,
+    pub uptime: u32,
+    pub port: u16
+}
+
+impl GameInfo {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(4 * 4);
+        buf.put_u32::<E>(self.product);
+        buf.put_u32::<E>(self.version);
+        buf.put_u32::<E>(self.host_counter);
+        buf.put_u32::<E>(self.entry_key);
+        write_cstring(buf, self.game_name.as_bytes());
+        // empty password
+        write_cstring(buf, &[]);
+        write_cstring(buf, &self.stat);
+        buf.reserve(4 * 5 + 2);
+        buf.put_u32::<E>(self.slots_total);
+        buf.put_u32::<E>(self.game_type);
+        // unknown, always 1
+        buf.put_u32::<E>(1);
+        buf.put_u32::<E>(self.slots_open);
+        buf.put_u32::<E>(self.uptime);
+        buf.put_u16::<E>(self.port);
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        let product = read_u32(buf)?;
+        let version = read_u32(buf)?;
+        let host_counter = read_u32(buf)?;
+        let entry_key = read_u32(buf)?;
+        let game_name = read_string(buf)?;
+        let _password = read_cstring(buf)?;
+        let stat = read_cstring(buf)?;
+        let slots_total = read_u32(buf)?;
+        let game_type = read_u32(buf)?;
+        let _unknown = read_u32(buf)?;
+        let slots_open = read_u32(buf)?;
+        let uptime = read_u32(buf)?;
+        let port = read_u16(buf)?;
+
+        Ok(GameInfo {
+            product,
+            version,
+            host_counter,
+            entry_key,
+            game_name,
+            stat,
+            slots_total,
+            game_type,
+            slots_open,
+            uptime,
+            port
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CreateGame {
+    pub product: u32,
+    pub version: u32,
+    pub host_counter: u32
+}
+
+impl CreateGame {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(12);
+        buf.put_u32::<E>(self.product);
+        buf.put_u32::<E>(self.version);
+        buf.put_u32::<E>(self.host_counter);
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        Ok(CreateGame {
+            product: read_u32(buf)?,
+            version: read_u32(buf)?,
+            host_counter: read_u32(buf)?
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RefreshGame {
+    pub host_counter: u32,
+    pub players: u32,
+    pub slots: u32
+}
+
+impl RefreshGame {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(12);
+        buf.put_u32::<E>(self.host_counter);
+        buf.put_u32::<E>(self.players);
+        buf.put_u32::<E>(self.slots);
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        Ok(RefreshGame {
+            host_counter: read_u32(buf)?,
+            players: read_u32(buf)?,
+            slots: read_u32(buf)?
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapCheck {
+    pub map_path: Bytes,
+    pub map_size: u32,
+    // crc32 of the whole map file
+    pub map_info: u32,
+    // the "xoro" crc, same as in the statstring
+    pub map_crc: u32,
+    pub map_sha1: Option<[u8; 20]>
+}
+
+impl MapCheck {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(4);
+        // unknown, always 1
+        buf.put_u32::<E>(1);
+        write_cstring(buf, &self.map_path);
+        buf.reserve(4 * 3 + 20);
+        buf.put_u32::<E>(self.map_size);
+        buf.put_u32::<E>(self.map_info);
+        buf.put_u32::<E>(self.map_crc);
+        if let Some(ref sha1) = self.map_sha1 {
+            buf.put_slice(sha1);
+        }
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        let _unknown = read_u32(buf)?;
+        let map_path = read_cstring(buf)?;
+        let map_size = read_u32(buf)?;
+        let map_info = read_u32(buf)?;
+        let map_crc = read_u32(buf)?;
+        let map_sha1 = read_sha1(buf)?;
+
+        Ok(MapCheck {
+            map_path,
+            map_size,
+            map_info,
+            map_crc,
+            map_sha1
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StartDownload {
+    pub from_pid: u8
+}
+
+impl StartDownload {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(5);
+        // unknown, always 1
+        buf.put_u32::<E>(1);
+        buf.put(self.from_pid);
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        let _unknown = read_u32(buf)?;
+
+        Ok(StartDownload {
+            from_pid: read_u8(buf)?
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapSizeFlag {
+    // sent right after joining, the player either has the map or needs it
+    Check = 0x01,
+    // sent during a download
+    Downloading = 0x03
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MapSize {
+    pub size_flag: u8,
+    pub map_size: u32
+}
+
+impl MapSize {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(9);
+        // unknown, always 1
+        buf.put_u32::<E>(1);
+        buf.put(self.size_flag);
+        buf.put_u32::<E>(self.map_size);
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        let _unknown = read_u32(buf)?;
+
+        Ok(MapSize {
+            size_flag: read_u8(buf)?,
+            map_size: read_u32(buf)?
+        })
+    }
+}
+
+pub const MAPPART_MAX_LENGTH: usize = 1442;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapPart {
+    pub to_pid: u8,
+    pub from_pid: u8,
+    pub position: u32,
+    pub data: Bytes
+}
+
+impl MapPart {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(1 + 1 + 4 * 3 + self.data.len());
+        buf.put(self.to_pid);
+        buf.put(self.from_pid);
+        // unknown, always 1
+        buf.put_u32::<E>(1);
+        buf.put_u32::<E>(self.position);
+        buf.put_u32::<E>(crc32::checksum_ieee(&self.data));
+        buf.put(&self.data);
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        let to_pid = read_u8(buf)?;
+        let from_pid = read_u8(buf)?;
+        let _unknown = read_u32(buf)?;
+        let position = read_u32(buf)?;
+        let crc = read_u32(buf)?;
+        let data = read_rest(buf);
+
+        if crc32::checksum_ieee(&data) != crc {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "W3GS map part crc mismatch"));
+        }
+
+        Ok(MapPart {
+            to_pid,
+            from_pid,
+            position,
+            data
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MapPartOk {
+    pub from_pid: u8,
+    pub to_pid: u8,
+    pub position: u32
+}
+
+impl MapPartOk {
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(1 + 1 + 4 + 4);
+        buf.put(self.from_pid);
+        buf.put(self.to_pid);
+        // unknown, always 1
+        buf.put_u32::<E>(1);
+        buf.put_u32::<E>(self.position);
+    }
+
+    pub fn read(buf: &mut R) -> io::Result<Self> {
+        let from_pid = read_u8(buf)?;
+        let to_pid = read_u8(buf)?;
+        let _unknown = read_u32(buf)?;
+        let position = read_u32(buf)?;
+
+        Ok(MapPartOk {
+            from_pid,
+            to_pid,
+            position
+        })
+    }
+}
@@ -0,0 +1,542 @@
+// lobby slot layout as carried by SLOTINFO and SLOTINFOJOIN, along with the rules the host
+// applies when players ask to change their slot
+#![allow(dead_code)]
+
+use bytes::*;
+
+use std::io::{self, Cursor};
+
+use super::packets::{E, read_u8, read_u32};
+
+pub const MAX_SLOTS: usize = 12;
+// team number used for observers and referees
+pub const OBSERVER_TEAM: u8 = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlotStatus {
+    Open = 0x00,
+    Closed = 0x01,
+    Occupied = 0x02
+}
+
+impl SlotStatus {
+    pub fn from_id(id: u8) -> Self {
+        match id {
+            0x01 => SlotStatus::Closed,
+            0x02 => SlotStatus::Occupied,
+            _ => SlotStatus::Open
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Race {
+    Human = 0x01,
+    Orc = 0x02,
+    NightElf = 0x04,
+    Undead = 0x08,
+    Random = 0x20
+}
+
+impl Race {
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0x01 => Some(Race::Human),
+            0x02 => Some(Race::Orc),
+            0x04 => Some(Race::NightElf),
+            0x08 => Some(Race::Undead),
+            0x20 => Some(Race::Random),
+            _ => None
+        }
+    }
+}
+
+// set in the race byte when the player is allowed to pick their race
+pub const RACE_SELECTABLE: u8 = 0x40;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComputerDifficulty {
+    Easy = 0x00,
+    Normal = 0x01,
+    Insane = 0x02
+}
+
+impl ComputerDifficulty {
+    pub fn from_id(id: u8) -> Self {
+        match id {
+            0x00 => ComputerDifficulty::Easy,
+            0x02 => ComputerDifficulty::Insane,
+            _ => ComputerDifficulty::Normal
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayoutStyle {
+    Melee = 0x00,
+    CustomForces = 0x01,
+    FixedPlayerSettings = 0x03
+}
+
+impl LayoutStyle {
+    pub fn from_id(id: u8) -> Self {
+        match id {
+            0x00 => LayoutStyle::Melee,
+            0x01 => LayoutStyle::CustomForces,
+            _ => LayoutStyle::FixedPlayerSettings
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlotError {
+    // the map doesn't allow changing this
+    Fixed,
+    InvalidValue,
+    // another player already has it
+    Taken,
+    NoOpenSlot,
+    NotInLobby
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Slot {
+    pub pid: u8,
+    // 0-100, 255 until we know whether the player has the map
+    pub download_status: u8,
+    pub status: SlotStatus,
+    pub computer: bool,
+    pub team: u8,
+    pub color: u8,
+    pub race: u8,
+    pub computer_type: ComputerDifficulty,
+    pub handicap: u8
+}
+
+pub const SLOT_LENGTH: usize = 9;
+
+impl Slot {
+    pub fn open(team: u8, color: u8, race: u8) -> Slot {
+        Slot {
+            pid: 0,
+            download_status: 255,
+            status: SlotStatus::Open,
+            computer: false,
+            team,
+            color,
+            race,
+            computer_type: ComputerDifficulty::Normal,
+            handicap: 100
+        }
+    }
+
+    pub fn is_player(&self) -> bool {
+        self.status == SlotStatus::Occupied && !self.computer
+    }
+
+    pub fn write(&self, buf: &mut BytesMut) {
+        buf.reserve(SLOT_LENGTH);
+        buf.put(self.pid);
+        buf.put(self.download_status);
+        buf.put(self.status as u8);
+        buf.put(self.computer as u8);
+        buf.put(self.team);
+        buf.put(self.color);
+        buf.put(self.race);
+        buf.put(self.computer_type as u8);
+        buf.put(self.handicap);
+    }
+
+    pub fn read(buf: &mut Cursor<Bytes>) -> io::Result<Self> {
+        Ok(Slot {
+            pid: read_u8(buf)?,
+            download_status: read_u8(buf)?,
+            status: SlotStatus::from_id(read_u8(buf)?),
+            computer: read_u8(buf)? != 0,
+            team: read_u8(buf)?,
+            color: read_u8(buf)?,
+            race: read_u8(buf)?,
+            computer_type: ComputerDifficulty::from_id(read_u8(buf)?),
+            handicap: read_u8(buf)?
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlotTable {
+    pub slots: Vec<Slot>,
+    pub random_seed: u32,
+    pub layout_style: LayoutStyle,
+    // amount of slots that aren't observer slots
+    pub player_slots: u8,
+    // not part of the wire format, whether players may move to the observer team
+    pub observers: bool
+}
+
+impl SlotTable {
+    // a melee layout with every player on their own team
+    pub fn melee(player_slots: u8) -> SlotTable {
+        let slots = (0..player_slots).map(|i| Slot::open(i, i, Race::Random as u8 | RACE_SELECTABLE)).collect();
+
+        SlotTable {
+            slots,
+            random_seed: 0,
+            layout_style: LayoutStyle::Melee,
+            player_slots,
+            observers: false
+        }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(1 + self.slots.len() * SLOT_LENGTH + 4 + 1 + 1);
+        buf.put(self.slots.len() as u8);
+        for slot in &self.slots {
+            slot.write(&mut buf);
+        }
+        buf.put_u32::<E>(self.random_seed);
+        buf.put(self.layout_style as u8);
+        buf.put(self.player_slots);
+        buf.freeze()
+    }
+
+    pub fn decode(data: Bytes) -> io::Result<Self> {
+        let mut buf = Cursor::new(data);
+        let count = read_u8(&mut buf)?;
+        let mut slots = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            slots.push(Slot::read(&mut buf)?);
+        }
+
+        Ok(SlotTable {
+            slots,
+            random_seed: read_u32(&mut buf)?,
+            layout_style: LayoutStyle::from_id(read_u8(&mut buf)?),
+            player_slots: read_u8(&mut buf)?,
+            observers: false
+        })
+    }
+
+    pub fn slot_of(&self, pid: u8) -> Option<usize> {
+        self.slots.iter().position(|x| x.is_player() && x.pid == pid)
+    }
+
+    pub fn open_slot(&self) -> Option<usize> {
+        self.slots.iter().position(|x| x.status == SlotStatus::Open)
+    }
+
+    pub fn players(&self) -> usize {
+        self.slots.iter().filter(|x| x.is_player()).count()
+    }
+
+    pub fn open_slots(&self) -> usize {
+        self.slots.iter().filter(|x| x.status == SlotStatus::Open).count()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.open_slot().is_none()
+    }
+
+    pub fn occupy(&mut self, pid: u8) -> Result<usize, SlotError> {
+        let index = self.open_slot().ok_or(SlotError::NoOpenSlot)?;
+
+        let slot = &mut self.slots[index];
+        slot.pid = pid;
+        slot.status = SlotStatus::Occupied;
+        slot.download_status = 255;
+        slot.computer = false;
+
+        Ok(index)
+    }
+
+    pub fn free(&mut self, pid: u8) {
+        if let Some(index) = self.slot_of(pid) {
+            let slot = &mut self.slots[index];
+            slot.pid = 0;
+            slot.status = SlotStatus::Open;
+            slot.download_status = 255;
+        }
+    }
+
+    pub fn set_status(&mut self, index: usize, status: SlotStatus) -> Result<(), SlotError> {
+        let slot = self.slots.get_mut(index).ok_or(SlotError::InvalidValue)?;
+
+        if slot.status == SlotStatus::Occupied {
+            return Err(SlotError::Taken);
+        }
+
+        slot.status = status;
+        Ok(())
+    }
+
+    pub fn add_computer(&mut self, index: usize, difficulty: ComputerDifficulty) -> Result<(), SlotError> {
+        let slot = self.slots.get_mut(index).ok_or(SlotError::InvalidValue)?;
+
+        if slot.is_player() {
+            return Err(SlotError::Taken);
+        }
+
+        slot.pid = 0;
+        slot.status = SlotStatus::Occupied;
+        slot.computer = true;
+        slot.computer_type = difficulty;
+        slot.download_status = 100;
+        Ok(())
+    }
+
+    pub fn set_download_status(&mut self, pid: u8, status: u8) {
+        if let Some(index) = self.slot_of(pid) {
+            self.slots[index].download_status = status;
+        }
+    }
+
+    fn player_slot(&self, pid: u8) -> Result<usize, SlotError> {
+        self.slot_of(pid).ok_or(SlotError::NotInLobby)
+    }
+
+    // with custom forces the team belongs to the slot, so the player moves to an open slot on
+    // that team instead; in melee the team is just a number on the slot
+    pub fn request_team(&mut self, pid: u8, team: u8) -> Result<(), SlotError> {
+        let index = self.player_slot(pid)?;
+
+        if team == OBSERVER_TEAM && !self.observers {
+            return Err(SlotError::InvalidValue);
+        }
+
+        match self.layout_style {
+            LayoutStyle::FixedPlayerSettings => Err(SlotError::Fixed),
+            LayoutStyle::CustomForces => {
+                let target = self.slots.iter()
+                    .position(|x| x.status == SlotStatus::Open && x.team == team)
+                    .ok_or(SlotError::NoOpenSlot)?;
+
+                self.swap(index, target);
+                Ok(())
+            }
+            LayoutStyle::Melee => {
+                if team >= self.player_slots && team != OBSERVER_TEAM {
+                    return Err(SlotError::InvalidValue);
+                }
+
+                self.slots[index].team = team;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn request_color(&mut self, pid: u8, color: u8) -> Result<(), SlotError> {
+        let index = self.player_slot(pid)?;
+
+        if self.layout_style == LayoutStyle::FixedPlayerSettings {
+            return Err(SlotError::Fixed);
+        }
+
+        if color as usize >= MAX_SLOTS || self.slots[index].team == OBSERVER_TEAM {
+            return Err(SlotError::InvalidValue);
+        }
+
+        match self.slots.iter().position(|x| x.color == color) {
+            Some(other) if other == index => Ok(()),
+            Some(other) if self.slots[other].status == SlotStatus::Occupied => Err(SlotError::Taken),
+            Some(other) => {
+                // unused slots hold on to their colors, hand ours over
+                self.slots[other].color = self.slots[index].color;
+                self.slots[index].color = color;
+                Ok(())
+            }
+            None => {
+                self.slots[index].color = color;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn request_race(&mut self, pid: u8, race: u8) -> Result<(), SlotError> {
+        let index = self.player_slot(pid)?;
+
+        if self.layout_style == LayoutStyle::FixedPlayerSettings || self.slots[index].race & RACE_SELECTABLE == 0 {
+            return Err(SlotError::Fixed);
+        }
+
+        let race = Race::from_id(race & !RACE_SELECTABLE).ok_or(SlotError::InvalidValue)?;
+        self.slots[index].race = race as u8 | RACE_SELECTABLE;
+        Ok(())
+    }
+
+    pub fn request_handicap(&mut self, pid: u8, handicap: u8) -> Result<(), SlotError> {
+        let index = self.player_slot(pid)?;
+
+        if self.layout_style == LayoutStyle::FixedPlayerSettings {
+            return Err(SlotError::Fixed);
+        }
+
+        match handicap {
+            50 | 60 | 70 | 80 | 90 | 100 => {
+                self.slots[index].handicap = handicap;
+                Ok(())
+            }
+            _ => Err(SlotError::InvalidValue)
+        }
+    }
+
+    // moves the occupant over, team, color and race belong to the slot with custom forces
+    fn swap(&mut self, a: usize, b: usize) {
+        let (first, second) = (self.slots[a], self.slots[b]);
+
+        self.slots[a] = Slot { team: first.team, color: first.color, race: first.race, ..second };
+        self.slots[b] = Slot { team: second.team, color: second.color, race: second.race, ..first };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom_forces() -> SlotTable {
+        let mut table = SlotTable::melee(4);
+        table.layout_style = LayoutStyle::CustomForces;
+        for (i, slot) in table.slots.iter_mut().enumerate() {
+            slot.team = (i / 2) as u8;
+        }
+        table
+    }
+
+    #[test]
+    fn encode_known_bytes() {
+        let mut table = SlotTable::melee(2);
+        table.random_seed = 0x0102_0304;
+        table.occupy(1).unwrap();
+
+        assert_eq!(&table.encode()[..], &[
+            2,
+            1, 255, 2, 0, 0, 0, 0x60, 1, 100,
+            0, 255, 0, 0, 1, 1, 0x60, 1, 100,
+            0x04, 0x03, 0x02, 0x01, 0x00, 2
+        ][..]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut table = custom_forces();
+        table.occupy(2).unwrap();
+        table.add_computer(3, ComputerDifficulty::Insane).unwrap();
+        table.set_status(2, SlotStatus::Closed).unwrap();
+
+        assert_eq!(SlotTable::decode(table.encode()).unwrap(), table);
+    }
+
+    #[test]
+    fn decode_rejects_truncated() {
+        let encoded = SlotTable::melee(3).encode();
+        for length in 0..encoded.len() {
+            assert!(SlotTable::decode(encoded.slice_to(length)).is_err());
+        }
+    }
+
+    #[test]
+    fn occupy_and_free() {
+        let mut table = SlotTable::melee(2);
+        assert_eq!(table.occupy(1), Ok(0));
+        assert_eq!(table.occupy(2), Ok(1));
+        assert!(table.is_full());
+        assert_eq!(table.occupy(3), Err(SlotError::NoOpenSlot));
+
+        table.free(1);
+        assert_eq!(table.players(), 1);
+        assert_eq!(table.slot_of(1), None);
+        assert_eq!(table.open_slot(), Some(0));
+    }
+
+    #[test]
+    fn slots_with_players_stay_put() {
+        let mut table = SlotTable::melee(2);
+        table.occupy(1).unwrap();
+
+        assert_eq!(table.set_status(0, SlotStatus::Closed), Err(SlotError::Taken));
+        assert_eq!(table.add_computer(0, ComputerDifficulty::Easy), Err(SlotError::Taken));
+        assert_eq!(table.set_status(5, SlotStatus::Closed), Err(SlotError::InvalidValue));
+    }
+
+    #[test]
+    fn melee_team_changes() {
+        let mut table = SlotTable::melee(4);
+        table.occupy(1).unwrap();
+
+        assert_eq!(table.request_team(1, 3), Ok(()));
+        assert_eq!(table.slots[0].team, 3);
+        assert_eq!(table.request_team(1, 4), Err(SlotError::InvalidValue));
+        assert_eq!(table.request_team(1, OBSERVER_TEAM), Err(SlotError::InvalidValue));
+
+        table.observers = true;
+        assert_eq!(table.request_team(1, OBSERVER_TEAM), Ok(()));
+        assert_eq!(table.request_team(2, 0), Err(SlotError::NotInLobby));
+    }
+
+    #[test]
+    fn custom_forces_team_changes_move_the_player() {
+        let mut table = custom_forces();
+        table.occupy(1).unwrap();
+        let color = table.slots[2].color;
+
+        assert_eq!(table.request_team(1, 1), Ok(()));
+        assert_eq!(table.slot_of(1), Some(2));
+        assert_eq!(table.slots[2].team, 1);
+        assert_eq!(table.slots[2].color, color);
+        assert_eq!(table.slots[0].status, SlotStatus::Open);
+
+        table.occupy(2).unwrap();
+        table.occupy(3).unwrap();
+        assert_eq!(table.request_team(1, 0), Err(SlotError::NoOpenSlot));
+    }
+
+    #[test]
+    fn fixed_settings_refuse_changes() {
+        let mut table = SlotTable::melee(2);
+        table.layout_style = LayoutStyle::FixedPlayerSettings;
+        table.occupy(1).unwrap();
+
+        assert_eq!(table.request_team(1, 1), Err(SlotError::Fixed));
+        assert_eq!(table.request_color(1, 1), Err(SlotError::Fixed));
+        assert_eq!(table.request_race(1, Race::Orc as u8), Err(SlotError::Fixed));
+        assert_eq!(table.request_handicap(1, 50), Err(SlotError::Fixed));
+    }
+
+    #[test]
+    fn color_changes() {
+        let mut table = SlotTable::melee(3);
+        table.occupy(1).unwrap();
+        table.occupy(2).unwrap();
+
+        assert_eq!(table.request_color(1, 1), Err(SlotError::Taken));
+        // the open slot's color gets swapped with ours
+        assert_eq!(table.request_color(1, 2), Ok(()));
+        assert_eq!(table.slots[0].color, 2);
+        assert_eq!(table.slots[2].color, 0);
+        assert_eq!(table.request_color(1, 11), Ok(()));
+        assert_eq!(table.slots[0].color, 11);
+        assert_eq!(table.request_color(1, 12), Err(SlotError::InvalidValue));
+    }
+
+    #[test]
+    fn race_changes() {
+        let mut table = SlotTable::melee(2);
+        table.occupy(1).unwrap();
+
+        assert_eq!(table.request_race(1, Race::NightElf as u8), Ok(()));
+        assert_eq!(table.slots[0].race, Race::NightElf as u8 | RACE_SELECTABLE);
+        assert_eq!(table.request_race(1, 0x03), Err(SlotError::InvalidValue));
+
+        table.slots[0].race = Race::Human as u8;
+        assert_eq!(table.request_race(1, Race::Orc as u8), Err(SlotError::Fixed));
+    }
+
+    #[test]
+    fn handicap_changes() {
+        let mut table = SlotTable::melee(2);
+        table.occupy(1).unwrap();
+
+        assert_eq!(table.request_handicap(1, 70), Ok(()));
+        assert_eq!(table.slots[0].handicap, 70);
+        assert_eq!(table.request_handicap(1, 75), Err(SlotError::InvalidValue));
+        assert_eq!(table.request_handicap(1, 110), Err(SlotError::InvalidValue));
+    }
+}
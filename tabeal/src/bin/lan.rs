@@ -0,0 +1,62 @@
+extern crate jekuthiel;
+extern crate tabeal;
+extern crate tokio_core;
+#[macro_use]
+extern crate serde_json;
+
+use jekuthiel::w3gs;
+use tabeal::lan::{self, ScanConfig};
+use tokio_core::reactor::Core;
+
+use std::env;
+use std::io;
+use std::process;
+
+fn usage() -> ! {
+    eprintln!("usage: lan [--json] [version]");
+    process::exit(1);
+}
+
+fn run(config: ScanConfig, json: bool) -> io::Result<()> {
+    let mut core = Core::new()?;
+    let scan = lan::scan(&core.handle(), config)?;
+    let games = core.run(scan)?;
+
+    if json {
+        let games: Vec<_> = games.iter().map(|x| x.to_json(w3gs::ENCODING)).collect();
+        println!("{}", serde_json::to_string_pretty(&json!({ "games": games })).unwrap());
+        return Ok(());
+    }
+
+    if games.is_empty() {
+        eprintln!("no games found");
+    }
+
+    for game in games {
+        let map = game.stat.as_ref().map(|x| String::from_utf8_lossy(&x.map_path).into_owned()).unwrap_or_default();
+        println!("{:<32} {:>2}/{:<2} {:<21} {}",
+            game.info.game_name.decode(w3gs::ENCODING), game.players(), game.info.slots_total, game.address, map);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let json = args.first().map_or(false, |x| x == "--json");
+    if json {
+        args.remove(0);
+    }
+
+    let mut config = ScanConfig::default();
+    match args.len() {
+        0 => {}
+        1 => config.version = args[0].parse().unwrap_or_else(|_| usage()),
+        _ => usage()
+    }
+
+    if let Err(e) = run(config, json) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
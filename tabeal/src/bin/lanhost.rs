@@ -0,0 +1,172 @@
+extern crate env_logger;
+extern crate futures;
+extern crate jekuthiel;
+extern crate log;
+extern crate tabeal;
+extern crate tokio_core;
+
+use futures::{Future, Stream};
+use jekuthiel::packets::string::BnetString;
+use jekuthiel::w3gs;
+use log::LevelFilter;
+use tabeal::host::{console, counter};
+use tabeal::host::autohost::{self, Autohost, AutohostConfig, AutohostMap};
+use tabeal::host::counter::HostCounter;
+use tabeal::host::lan::{self, LanConfig};
+use tabeal::host::lobby::{Lobby, LobbyConfig};
+use tabeal::host::server::{self, Host, HostConfig};
+use tokio_core::reactor::{Core, Handle, Interval};
+
+use std::cell::RefCell;
+use std::env;
+use std::io;
+use std::process;
+use std::rc::Rc;
+use std::time::Duration;
+
+fn usage() -> ! {
+    eprintln!("usage: lanhost [options] <map> <common.j> <blizzard.j>");
+    eprintln!("       lanhost --autohost [options] <map>... <common.j> <blizzard.j>");
+    eprintln!("options: --name <game name>   defaults to the map's file name, with --autohost {{map}} and {{}}");
+    eprintln!("                              are replaced with the map's name and the game's number");
+    eprintln!("         --host <host name>   defaults to tabeal");
+    eprintln!("         --port <port>        TCP port players join on, defaults to 6112");
+    eprintln!("         --version <minor>    game version, defaults to 26 for 1.26");
+    eprintln!("         --counter <file>     where the host counter is kept, defaults to host_counter");
+    eprintln!("         --autohost           keep opening lobbies, going through the maps in turn");
+    eprintln!("         --min-players <n>    players it takes to start an autohosted game, defaults to 2");
+    process::exit(1);
+}
+
+struct Options {
+    maps: Vec<String>,
+    common_j: String,
+    blizzard_j: String,
+    game_name: Option<String>,
+    host_name: String,
+    counter: String,
+    autohost: bool,
+    min_players: usize,
+    lan: LanConfig
+}
+
+fn parse_options() -> Options {
+    let mut args = env::args().skip(1);
+    let mut positional = Vec::new();
+    let mut game_name = None;
+    let mut host_name = "tabeal".to_string();
+    let mut counter = "host_counter".to_string();
+    let mut autohost = false;
+    let mut min_players = AutohostConfig::default().min_players;
+    let mut lan = LanConfig::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--name" => game_name = Some(args.next().unwrap_or_else(|| usage())),
+            "--host" => host_name = args.next().unwrap_or_else(|| usage()),
+            "--counter" => counter = args.next().unwrap_or_else(|| usage()),
+            "--port" => lan.game_port = args.next().and_then(|x| x.parse().ok()).unwrap_or_else(|| usage()),
+            "--version" => lan.version = args.next().and_then(|x| x.parse().ok()).unwrap_or_else(|| usage()),
+            "--autohost" => autohost = true,
+            "--min-players" => min_players = args.next().and_then(|x| x.parse().ok()).unwrap_or_else(|| usage()),
+            _ if arg.starts_with("--") => usage(),
+            _ => positional.push(arg)
+        }
+    }
+
+    // only autohost can make use of more than one map
+    if positional.len() < 3 || (positional.len() > 3 && !autohost) {
+        usage();
+    }
+    let blizzard_j = positional.pop().unwrap();
+    let common_j = positional.pop().unwrap();
+    let maps = positional;
+
+    Options { maps, common_j, blizzard_j, game_name, host_name, counter, autohost, min_players, lan }
+}
+
+fn encode(text: &str) -> io::Result<BnetString> {
+    BnetString::encode(text, w3gs::ENCODING).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "names can't contain NUL"))
+}
+
+// console commands go to whichever lobby is current when they're typed
+fn read_commands<F>(handle: &Handle, current: F) where F: Fn() -> Option<Rc<RefCell<Lobby>>> + 'static {
+    handle.spawn(console::stdin_lines().for_each(move |line| {
+        let output = match current() {
+            Some(lobby) => console::command(&mut lobby.borrow_mut(), &line),
+            None => vec!["no lobby open right now".to_string()]
+        };
+        for line in output {
+            println!("{}", line);
+        }
+        Ok(())
+    }));
+}
+
+fn run(options: Options) -> io::Result<()> {
+    let mut maps = Vec::new();
+    for path in &options.maps {
+        maps.push(AutohostMap::load(path, &options.common_j, &options.blizzard_j)?);
+    }
+    let mut host_counter = HostCounter::load(&options.counter)?;
+
+    let mut core = Core::new()?;
+    let handle = core.handle();
+
+    let host = Rc::new(RefCell::new(Host::new(&handle, HostConfig::default(), None)));
+    let serving = server::serve(&handle, options.lan.game_port, host.clone())?;
+    let advertising = lan::advertise_host(&handle, options.lan, host.clone())?;
+    let running = serving.join(advertising).map(|_| ());
+
+    if options.autohost {
+        let defaults = AutohostConfig::default();
+        let config = AutohostConfig {
+            name_template: options.game_name.unwrap_or_else(|| defaults.name_template.clone()),
+            host_name: options.host_name,
+            min_players: options.min_players,
+            ..defaults
+        };
+        let map_count = maps.len();
+        let autohost = Rc::new(RefCell::new(Autohost::new(config, maps, host_counter)?));
+
+        let current = host.clone();
+        read_commands(&handle, move || current.borrow().lobby());
+
+        let autohosting = autohost::run(&handle, autohost, host)?;
+        println!("autohosting {} map(s) on port {}, type help for commands", map_count, options.lan.game_port);
+        return core.run(running.join(autohosting)).map(|_| ());
+    }
+
+    let map = &maps[0];
+    let game_name = options.game_name.unwrap_or_else(|| map.name.clone());
+    let config = LobbyConfig::from_map(encode(&game_name)?, encode(&options.host_name)?, host_counter.next()?,
+        counter::entry_key(), &map.map, map.map_path.clone());
+    let lobby = host.borrow_mut().create(config)?;
+
+    let current = lobby.clone();
+    read_commands(&handle, move || Some(current.clone()));
+
+    // there's only the one game, we're done once it's over
+    let finished = Interval::new(Duration::from_secs(1), &handle)?
+        .take_while(move |_| Ok(!lobby.borrow().is_finished()))
+        .for_each(|_| Ok(()));
+
+    println!("hosting {} on port {}, type help for commands", game_name, options.lan.game_port);
+    core.run(finished.select(running).map(|_| ()).map_err(|(e, _)| e))
+}
+
+// what the host has to say goes to stderr, RUST_LOG can change how much of it
+fn init_logger() {
+    env_logger::Builder::new()
+        .filter(None, LevelFilter::Info)
+        .parse(&env::var("RUST_LOG").unwrap_or_default())
+        .init();
+}
+
+fn main() {
+    init_logger();
+    if let Err(e) = run(parse_options()) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
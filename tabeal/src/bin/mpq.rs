@@ -0,0 +1,86 @@
+extern crate tabeal;
+
+use tabeal::mpq::Archive;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+fn usage() -> ! {
+    eprintln!("usage: mpq list <archive>");
+    eprintln!("       mpq extract <archive> <file> [output]");
+    eprintln!("       mpq extract-all <archive> <directory>");
+    process::exit(1);
+}
+
+fn list(path: &str) -> io::Result<()> {
+    let mut archive = Archive::open(path)?;
+
+    match archive.list() {
+        Ok(files) => {
+            for name in files {
+                println!("{}", name);
+            }
+        }
+        // names can't be recovered from the hashes, all we can say is how much is in there
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            let count = archive.blocks().iter().filter(|x| x.exists()).count();
+            eprintln!("archive has no listfile, {} files inside", count);
+        }
+        Err(e) => return Err(e)
+    }
+
+    Ok(())
+}
+
+fn extract(path: &str, name: &str, output: Option<&str>) -> io::Result<()> {
+    let mut archive = Archive::open(path)?;
+    let data = archive.read_file(name)?;
+
+    match output {
+        Some(output) => File::create(output)?.write_all(&data),
+        None => io::stdout().write_all(&data)
+    }
+}
+
+fn extract_all(path: &str, directory: &str) -> io::Result<()> {
+    let mut archive = Archive::open(path)?;
+
+    for name in archive.list()? {
+        let data = match archive.read_file(&name) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                continue;
+            }
+        };
+
+        let output = name.split('\\').fold(Path::new(directory).to_path_buf(), |path, x| path.join(x));
+        if let Some(parent) = output.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        File::create(&output)?.write_all(&data)?;
+        println!("{}", name);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let result = match (args.get(1).map(|x| x.as_str()), args.len()) {
+        (Some("list"), 3) => list(&args[2]),
+        (Some("extract"), 4) => extract(&args[2], &args[3], None),
+        (Some("extract"), 5) => extract(&args[2], &args[3], Some(&args[4])),
+        (Some("extract-all"), 4) => extract_all(&args[2], &args[3]),
+        _ => usage()
+    };
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
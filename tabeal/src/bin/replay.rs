@@ -0,0 +1,141 @@
+extern crate jekuthiel;
+#[macro_use]
+extern crate serde_json;
+
+use jekuthiel::replay::{self, Replay};
+use jekuthiel::replay::parser::ChatMessage;
+
+use std::env;
+use std::io;
+use std::process;
+
+fn usage() -> ! {
+    eprintln!("usage: replay [--json] <file>");
+    process::exit(1);
+}
+
+// m:ss, or h:mm:ss for the long ones
+fn format_time(millis: u32) -> String {
+    let seconds = millis / 1000;
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{}:{:02}:{:02}", hours, seconds / 60 % 60, seconds % 60)
+    }
+}
+
+fn name(replay: &Replay, pid: u8) -> String {
+    match replay.player_name(pid) {
+        Some(name) => name.decode(replay::ENCODING).into_owned(),
+        None => format!("#{}", pid)
+    }
+}
+
+fn chat_target(replay: &Replay, message: &ChatMessage) -> String {
+    match message.mode {
+        None => "Lobby".to_string(),
+        Some(0) => "All".to_string(),
+        Some(1) => "Allies".to_string(),
+        Some(2) => "Observers".to_string(),
+        // whispers go to a slot, not a player
+        Some(mode) => {
+            let slot = (mode - 3) as usize;
+            match replay.game.slots.slots.get(slot) {
+                Some(slot) if slot.is_player() => format!("To {}", name(replay, slot.pid)),
+                _ => format!("To slot {}", slot + 1)
+            }
+        }
+    }
+}
+
+fn print_text(replay: &Replay) {
+    let stat = &replay.game.stat;
+    println!("{}", replay.game.game_name.decode(replay::ENCODING));
+    println!("map: {}", String::from_utf8_lossy(&stat.map_path));
+    println!("host: {}", String::from_utf8_lossy(&stat.host_name));
+    println!("version: 1.{} ({})", replay.header.version, replay.header.build);
+    println!("duration: {}", format_time(replay.duration()));
+    if !replay.complete {
+        println!("only part of the replay could be read");
+    }
+
+    println!();
+    println!("players:");
+    for player in replay.player_stats() {
+        let left = if player.left { format!(", left at {}", format_time(player.time)) } else { String::new() };
+        println!("  {:<16} {:>4.0} APM ({} actions{})", name(replay, player.pid), player.apm(), player.actions, left);
+    }
+
+    println!();
+    println!("chat:");
+    for message in replay.chat() {
+        println!("  [{}] [{}] {}: {}",
+            format_time(message.time), chat_target(replay, &message), name(replay, message.pid),
+            message.message.decode(replay::ENCODING));
+    }
+}
+
+fn print_json(replay: &Replay) {
+    let stat = &replay.game.stat;
+
+    let players: Vec<_> = replay.player_stats().iter()
+        .map(|x| json!({
+            "pid": x.pid,
+            "name": name(replay, x.pid),
+            "actions": x.actions,
+            "apm": x.apm(),
+            "time": x.time,
+            "left": x.left
+        }))
+        .collect();
+
+    let chat: Vec<_> = replay.chat().iter()
+        .map(|x| json!({
+            "time": x.time,
+            "pid": x.pid,
+            "name": name(replay, x.pid),
+            "target": chat_target(replay, x),
+            "message": x.message.decode(replay::ENCODING)
+        }))
+        .collect();
+
+    let output = json!({
+        "game_name": replay.game.game_name.decode(replay::ENCODING),
+        "map_path": String::from_utf8_lossy(&stat.map_path),
+        "host_name": String::from_utf8_lossy(&stat.host_name),
+        "version": replay.header.version,
+        "build": replay.header.build,
+        "duration": replay.duration(),
+        "complete": replay.complete,
+        "players": players,
+        "chat": chat
+    });
+
+    println!("{}", serde_json::to_string_pretty(&output).unwrap());
+}
+
+fn run(path: &str, json: bool) -> io::Result<()> {
+    let replay = Replay::open(path)?;
+
+    if json {
+        print_json(&replay);
+    } else {
+        print_text(&replay);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let result = match (args.get(1).map(|x| x.as_str()), args.len()) {
+        (Some("--json"), 3) => run(&args[2], true),
+        (Some(_), 2) => run(&args[1], false),
+        _ => usage()
+    };
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,443 @@
+// keeps track of the realm's public game list and reports what changed between polls
+#![allow(dead_code)]
+
+use futures::{future, Future, Sink, Stream};
+use futures::future::Either;
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::{Handle, Interval};
+use tokio_io::io::{read, write_all};
+
+use jekuthiel::packets::{BNetIncomingPacket, BNetOutgoingPacket};
+use jekuthiel::packets::c2s::{self, GameListQuery};
+use jekuthiel::packets::s2c::{GetAdvListEx, GetAdvListExItem, GetAdvListExStatus};
+use jekuthiel::packets::string::{BnetString, Encoding};
+
+use serde_json;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::net::{SocketAddr, SocketAddrV4};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use realm::RealmConnection;
+
+// game state flags, same values as the ones we send in STARTADVEX3
+const GAME_STATUS_FULL: u32 = 0x02;
+const GAME_STATUS_IN_PROGRESS: u32 = 0x08;
+// a reply holding as many games as we asked for may have left some out, a game has to be
+// missing from this many of those in a row before it counts as gone
+const MISSED_REPLIES: u32 = 3;
+
+#[derive(Clone, Debug)]
+pub struct MonitorConfig {
+    pub query: GameListQuery,
+    // seconds between game list requests
+    pub interval: u64,
+    // where the list is written after every reply, if anywhere
+    pub json_path: Option<PathBuf>,
+    // where the list is served over http, if anywhere
+    pub http_address: Option<SocketAddr>
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        MonitorConfig {
+            query: GameListQuery::default(),
+            interval: 10,
+            json_path: None,
+            http_address: None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameInfo {
+    pub name: String,
+    pub address: SocketAddrV4,
+    pub host_counter: u32,
+    pub host_name: String,
+    pub map_path: String,
+    // None when the statstring couldn't be read
+    pub free_slots: Option<u8>,
+    pub status: u32,
+    pub elapsed_time: u32
+}
+
+// statstring fields were null terminated inside the statstring, so they can't contain one
+fn decode(bytes: &[u8], encoding: Encoding) -> String {
+    BnetString::from_bytes(bytes).map(|x| x.decode(encoding).into_owned()).unwrap_or_default()
+}
+
+impl GameInfo {
+    fn from_item(item: &GetAdvListExItem, encoding: Encoding) -> GameInfo {
+        let stat = item.stat();
+
+        GameInfo {
+            name: item.game_name.decode(encoding).into_owned(),
+            address: item.address,
+            host_counter: stat.as_ref().map(|x| x.host_counter).unwrap_or(0),
+            host_name: stat.as_ref().map(|x| decode(&x.stat.host_name, encoding)).unwrap_or_default(),
+            map_path: stat.as_ref().map(|x| decode(&x.stat.map_path, encoding)).unwrap_or_default(),
+            free_slots: stat.as_ref().map(|x| x.free_slots),
+            status: item.game_status,
+            elapsed_time: item.elapsed_time
+        }
+    }
+
+    // the same name can be reused by a host for a new game, the host counter tells them apart
+    fn key(&self) -> (SocketAddrV4, u32, String) {
+        (self.address, self.host_counter, self.name.clone())
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.status & GAME_STATUS_FULL != 0 || self.free_slots == Some(0)
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.status & GAME_STATUS_IN_PROGRESS != 0
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "name": self.name,
+            "address": self.address.to_string(),
+            "host_counter": self.host_counter,
+            "host_name": self.host_name,
+            "map_path": self.map_path,
+            "free_slots": self.free_slots,
+            "full": self.is_full(),
+            "started": self.is_started(),
+            "elapsed_time": self.elapsed_time
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GameListEvent {
+    Created(GameInfo),
+    Filled(GameInfo),
+    Started(GameInfo),
+    // dropped off the list without us ever seeing it start
+    Vanished(GameInfo)
+}
+
+struct TrackedGame {
+    info: GameInfo,
+    // replies in a row the game wasn't in
+    missed: u32
+}
+
+pub struct GameListMonitor {
+    // the most games a reply to our query can hold
+    max_games: u32,
+    games: HashMap<(SocketAddrV4, u32, String), TrackedGame>
+}
+
+impl GameListMonitor {
+    pub fn new(query: &GameListQuery) -> GameListMonitor {
+        GameListMonitor {
+            max_games: query.max_games(),
+            games: HashMap::new()
+        }
+    }
+
+    pub fn games(&self) -> Vec<&GameInfo> {
+        let mut games: Vec<_> = self.games.values().map(|x| &x.info).collect();
+        games.sort_by(|a, b| a.name.cmp(&b.name));
+        games
+    }
+
+    // replaces the current snapshot with a new list and returns the differences, the names are
+    // in the encoding of the realm connection the list came from
+    pub fn update(&mut self, list: &GetAdvListEx, encoding: Encoding) -> Vec<GameListEvent> {
+        let mut events = Vec::new();
+        let mut games = HashMap::new();
+
+        let items: &[GetAdvListExItem] = match list.status {
+            GetAdvListExStatus::OK(ref items) => items,
+            GetAdvListExStatus::EMPTY(_) => &[]
+        };
+
+        for item in items {
+            let game = GameInfo::from_item(item, encoding);
+
+            match self.games.remove(&game.key()).map(|x| x.info) {
+                None => {
+                    events.push(GameListEvent::Created(game.clone()));
+                    if game.is_full() {
+                        events.push(GameListEvent::Filled(game.clone()));
+                    }
+                    if game.is_started() {
+                        events.push(GameListEvent::Started(game.clone()));
+                    }
+                }
+                Some(old) => {
+                    if game.is_full() && !old.is_full() {
+                        events.push(GameListEvent::Filled(game.clone()));
+                    }
+                    if game.is_started() && !old.is_started() {
+                        events.push(GameListEvent::Started(game.clone()));
+                    }
+                }
+            }
+
+            games.insert(game.key(), TrackedGame { info: game, missed: 0 });
+        }
+
+        // whatever is left wasn't in the new list
+        let capped = items.len() as u32 >= self.max_games;
+        for (key, mut game) in self.games.drain() {
+            game.missed += 1;
+            if capped && game.missed < MISSED_REPLIES {
+                games.insert(key, game);
+                continue;
+            }
+
+            // a full lobby disappearing almost always means it was started
+            let game = game.info;
+            if game.is_full() && !game.is_started() {
+                events.push(GameListEvent::Started(game));
+            } else {
+                events.push(GameListEvent::Vanished(game));
+            }
+        }
+
+        self.games = games;
+        events
+    }
+
+    // the realm packets we care about are game lists, everything else gives no events
+    pub fn receive(&mut self, packet: &BNetIncomingPacket, encoding: Encoding) -> Vec<GameListEvent> {
+        match *packet {
+            BNetIncomingPacket::GetAdvListEx(ref list) => self.update(list, encoding),
+            _ => Vec::new()
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let games: Vec<_> = self.games().iter().map(|x| x.to_json()).collect();
+        serde_json::to_string_pretty(&json!({ "games": games })).unwrap()
+    }
+
+    // writes to a temporary file first so readers never see a half-written list
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+
+        {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(self.to_json().as_bytes())?;
+        }
+
+        fs::rename(tmp_path, path)
+    }
+}
+
+// sends a game list request every `interval`, the replies come back through the realm connection
+pub fn poll_game_list<S>(handle: &Handle, sink: S, interval: Duration, query: GameListQuery) -> io::Result<impl Future<Item=(), Error=io::Error>>
+    where S: Sink<SinkItem=BNetOutgoingPacket, SinkError=io::Error> {
+    let request = c2s::get_adv_list_ex(&query);
+
+    Ok(Interval::new(interval, handle)?
+        .fold(sink, move |sink, _| sink.send(BNetOutgoingPacket { data: request.clone() }))
+        .map(|_| ()))
+}
+
+// bare-bones http endpoint that answers every request with the current list
+pub fn serve_json(handle: &Handle, address: &SocketAddr, monitor: Rc<RefCell<GameListMonitor>>) -> io::Result<impl Future<Item=(), Error=io::Error>> {
+    let listener = TcpListener::bind(address, handle)?;
+    let handle = handle.clone();
+
+    Ok(listener.incoming().for_each(move |(socket, _)| {
+        let body = monitor.borrow().to_json();
+        let response = format!("HTTP/1.0 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\n\r\n{}", body.len(), body);
+
+        // read (and ignore) the request before answering so the client doesn't get reset
+        let reply = read(socket, vec![0u8; 1024])
+            .and_then(move |(socket, _, _)| write_all(socket, response.into_bytes()));
+
+        handle.spawn(reply.map(|_| ()).map_err(|_| ()));
+        Ok(())
+    }))
+}
+
+// keeps the monitor fed from a logged in realm connection: polls the game list, diffs every
+// reply against the last one and hands the events to on_event, then writes and serves the list
+// wherever the config says
+pub fn run<T, F>(handle: &Handle, realm: &RealmConnection, stream: T, config: MonitorConfig, monitor: Rc<RefCell<GameListMonitor>>, mut on_event: F) -> io::Result<impl Future<Item=(), Error=io::Error>>
+    where T: Stream<Item=BNetIncomingPacket, Error=io::Error>,
+          F: FnMut(GameListEvent) {
+    let polling = poll_game_list(handle, realm.sink(), Duration::from_secs(config.interval), config.query)?;
+
+    let serving = match config.http_address {
+        Some(ref address) => Either::A(serve_json(handle, address, monitor.clone())?),
+        None => Either::B(future::empty())
+    };
+
+    let json_path = config.json_path;
+    let encoding = realm.encoding();
+    let receiving = stream.for_each(move |packet| {
+        let events = match packet {
+            BNetIncomingPacket::GetAdvListEx(ref list) => monitor.borrow_mut().update(list, encoding),
+            _ => return Ok(())
+        };
+
+        for event in events {
+            on_event(event);
+        }
+        match json_path {
+            Some(ref path) => monitor.borrow().write_json(path),
+            None => Ok(())
+        }
+    });
+
+    Ok(polling.select(receiving).map(|_| ()).map_err(|(e, _)| e)
+        .select(serving).map(|_| ()).map_err(|(e, _)| e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use jekuthiel::packets::statstring::{AdvertisedStat, GameFlags, GameStat};
+
+    use std::net::Ipv4Addr;
+
+    fn item(name: &str, free_slots: Option<u8>, status: u32) -> GetAdvListExItem {
+        let stat = free_slots.map(|free_slots| AdvertisedStat {
+            free_slots,
+            host_counter: 1,
+            stat: GameStat {
+                flags: GameFlags::default(),
+                map_width: 116,
+                map_height: 84,
+                map_crc: 0x1122_3344,
+                map_path: Bytes::from(&b"Maps\\Download\\test.w3x"[..]),
+                host_name: Bytes::from(&b"someone"[..]),
+                map_sha1: None
+            }
+        });
+
+        GetAdvListExItem {
+            game_settings: 0,
+            language_id: 0,
+            address_family: 2,
+            address: SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 6112),
+            game_status: status,
+            elapsed_time: 0,
+            game_name: BnetString::encode(name, Encoding::Utf8).unwrap(),
+            game_password: BnetString::default(),
+            // garbage for the games without a statstring
+            game_statstring: stat.map(|x| x.encode()).unwrap_or_else(|| Bytes::from(&b"?"[..]))
+        }
+    }
+
+    fn list(items: Vec<GetAdvListExItem>) -> GetAdvListEx {
+        GetAdvListEx {
+            count: items.len() as u32,
+            status: GetAdvListExStatus::OK(items)
+        }
+    }
+
+    fn monitor(max_games: u32) -> GameListMonitor {
+        GameListMonitor::new(&GameListQuery::new().count(max_games))
+    }
+
+    // event kinds and game names, which is all the tests look at
+    fn names(events: Vec<GameListEvent>) -> Vec<(&'static str, String)> {
+        events.into_iter()
+            .map(|x| match x {
+                GameListEvent::Created(game) => ("created", game.name),
+                GameListEvent::Filled(game) => ("filled", game.name),
+                GameListEvent::Started(game) => ("started", game.name),
+                GameListEvent::Vanished(game) => ("vanished", game.name)
+            })
+            .collect()
+    }
+
+    fn event(kind: &'static str, name: &str) -> (&'static str, String) {
+        (kind, name.to_string())
+    }
+
+    #[test]
+    fn created() {
+        let mut monitor = monitor(20);
+        let events = monitor.update(&list(vec![item("open", Some(3), 0), item("full", Some(0), 0), item("playing", Some(2), GAME_STATUS_IN_PROGRESS)]), Encoding::Utf8);
+
+        assert_eq!(names(events), vec![
+            event("created", "open"),
+            event("created", "full"), event("filled", "full"),
+            event("created", "playing"), event("started", "playing")
+        ]);
+        assert_eq!(monitor.games().len(), 3);
+        assert!(monitor.update(&list(vec![item("open", Some(3), 0), item("full", Some(0), 0), item("playing", Some(2), GAME_STATUS_IN_PROGRESS)]), Encoding::Utf8).is_empty());
+    }
+
+    #[test]
+    fn filled_and_started() {
+        let mut monitor = monitor(20);
+        monitor.update(&list(vec![item("a", Some(2), 0), item("b", Some(1), 0)]), Encoding::Utf8);
+
+        let events = monitor.update(&list(vec![item("a", Some(0), 0), item("b", Some(1), GAME_STATUS_FULL)]), Encoding::Utf8);
+        assert_eq!(names(events), vec![event("filled", "a"), event("filled", "b")]);
+
+        let events = monitor.update(&list(vec![item("a", Some(0), GAME_STATUS_IN_PROGRESS), item("b", Some(1), GAME_STATUS_FULL)]), Encoding::Utf8);
+        assert_eq!(names(events), vec![event("started", "a")]);
+    }
+
+    #[test]
+    fn vanished() {
+        let mut monitor = monitor(20);
+        monitor.update(&list(vec![item("open", Some(2), 0), item("full", Some(0), 0), item("keep", Some(2), 0)]), Encoding::Utf8);
+
+        let mut events = names(monitor.update(&list(vec![item("keep", Some(2), 0)]), Encoding::Utf8));
+        events.sort();
+        // the full lobby most likely started, the open one was given up on
+        assert_eq!(events, vec![event("started", "full"), event("vanished", "open")]);
+
+        let events = monitor.update(&GetAdvListEx { count: 0, status: GetAdvListExStatus::EMPTY(0) }, Encoding::Utf8);
+        assert_eq!(names(events), vec![event("vanished", "keep")]);
+        assert!(monitor.games().is_empty());
+    }
+
+    #[test]
+    fn unknown_free_slots_arent_full() {
+        let mut monitor = monitor(20);
+        let events = monitor.update(&list(vec![item("odd", None, 0)]), Encoding::Utf8);
+        assert_eq!(names(events), vec![event("created", "odd")]);
+        assert_eq!(monitor.games()[0].free_slots, None);
+        assert!(!monitor.games()[0].is_full());
+
+        let events = monitor.update(&list(vec![]), Encoding::Utf8);
+        assert_eq!(names(events), vec![event("vanished", "odd")]);
+    }
+
+    #[test]
+    fn capped_replies() {
+        let mut monitor = monitor(2);
+        monitor.update(&list(vec![item("a", Some(2), 0), item("b", Some(2), 0)]), Encoding::Utf8);
+
+        // a full reply may just have left a out
+        for _ in 1..MISSED_REPLIES {
+            let events = monitor.update(&list(vec![item("b", Some(2), 0), item("c", Some(2), 0)]), Encoding::Utf8);
+            assert!(names(events).iter().all(|x| x.1 == "c"));
+            assert_eq!(monitor.games().len(), 3);
+        }
+
+        // coming back resets the count
+        monitor.update(&list(vec![item("a", Some(2), 0), item("b", Some(2), 0)]), Encoding::Utf8);
+        for _ in 1..MISSED_REPLIES {
+            assert!(monitor.update(&list(vec![item("b", Some(2), 0), item("c", Some(2), 0)]), Encoding::Utf8).is_empty());
+        }
+        let events = monitor.update(&list(vec![item("b", Some(2), 0), item("c", Some(2), 0)]), Encoding::Utf8);
+        assert_eq!(names(events), vec![event("vanished", "a")]);
+
+        // a reply with room to spare has every game in it
+        let events = monitor.update(&list(vec![item("b", Some(2), 0)]), Encoding::Utf8);
+        assert_eq!(names(events), vec![event("vanished", "c")]);
+    }
+}
@@ -0,0 +1,315 @@
+// keeps a lobby listed on the realm: advertises it while it's open, updates the listing as
+// slots fill up and takes it down once the game starts
+#![allow(dead_code)]
+
+use futures::{Future, Sink, Stream};
+use futures::future::{self, Either};
+use tokio_core::reactor::{Handle, Interval};
+
+use jekuthiel::packets::BNetOutgoingPacket;
+use jekuthiel::packets::c2s::{self, StartAdvEx3GameState};
+use jekuthiel::packets::s2c::StartAdvEx3Status;
+use jekuthiel::packets::statstring::AdvertisedStat;
+use jekuthiel::packets::string::{BnetString, Encoding};
+use jekuthiel::w3gs;
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use super::lobby::{Lobby, Phase};
+
+// longest game name the realm accepts
+const MAX_GAME_NAME_LENGTH: usize = 31;
+// how often the lobby is checked for changes worth telling the realm about
+const UPDATE_INTERVAL: u64 = 1;
+
+#[derive(Clone, Copy, Debug)]
+pub struct AdvertiseConfig {
+    pub game_type: u16,
+    pub sub_game_type: u16,
+    // not the game version, the client always sends 0x03FF
+    pub provider_version: u32,
+    pub ladder_type: u32,
+    // private games don't show up in the list, players have to know the name
+    pub private: bool,
+    // keep the game listed as in progress until it's over instead of taking it down at the start
+    pub list_in_progress: bool,
+    // seconds between advertisements while nothing changes
+    pub refresh_interval: u64,
+    // how many suffixed names to try before giving up when the realm turns a name down
+    pub retries: u32
+}
+
+impl Default for AdvertiseConfig {
+    fn default() -> Self {
+        AdvertiseConfig {
+            game_type: 0x0001,
+            sub_game_type: 0,
+            provider_version: 0x03FF,
+            ladder_type: 0,
+            private: false,
+            list_in_progress: false,
+            refresh_interval: 5,
+            retries: 5
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdvertiserState {
+    // the realm hasn't answered the last advertisement yet, it goes out again after a while
+    Pending,
+    // the last advertisement was turned down, the next one goes out under a new name
+    Retrying,
+    Advertised,
+    // taken down, or we gave up on finding a name
+    Stopped
+}
+
+pub struct GameAdvertiser {
+    config: AdvertiseConfig,
+    // the realm connection's, which needn't be what the lobby uses
+    encoding: Encoding,
+    base_name: String,
+    game_name: BnetString,
+    attempt: u32,
+    state: AdvertiserState,
+    // the state flags and free slots we last told the realm about
+    sent: Option<(u32, u8)>,
+    refreshed: Instant
+}
+
+// the base name cut short enough for the suffix to fit, never in the middle of a character
+fn suffixed_name(base: &str, attempt: u32) -> String {
+    let suffix = format!(" ({})", attempt + 1);
+    let mut end = MAX_GAME_NAME_LENGTH.saturating_sub(suffix.len());
+    if end < base.len() {
+        while !base.is_char_boundary(end) {
+            end -= 1;
+        }
+    } else {
+        end = base.len();
+    }
+
+    format!("{}{}", &base[..end], suffix)
+}
+
+// names that don't fit the realm's encoding go out as UTF-8, which beats not listing the game
+fn realm_name(name: &str, encoding: Encoding) -> BnetString {
+    BnetString::encode(name, encoding)
+        .or_else(|_| BnetString::encode(name, Encoding::Utf8))
+        .unwrap()
+}
+
+impl GameAdvertiser {
+    pub fn new(config: AdvertiseConfig, lobby: &Lobby, encoding: Encoding) -> GameAdvertiser {
+        let base_name = lobby.config().game_name.decode(w3gs::ENCODING).into_owned();
+
+        GameAdvertiser {
+            config,
+            encoding,
+            game_name: realm_name(&base_name, encoding),
+            base_name,
+            attempt: 0,
+            state: AdvertiserState::Pending,
+            sent: None,
+            refreshed: Instant::now()
+        }
+    }
+
+    pub fn state(&self) -> AdvertiserState {
+        self.state
+    }
+
+    // the name the game is listed under, which isn't the lobby's own after a retry
+    pub fn game_name(&self) -> &BnetString {
+        &self.game_name
+    }
+
+    fn states(&self, lobby: &Lobby) -> Vec<StartAdvEx3GameState> {
+        let mut states = Vec::new();
+        if self.config.private {
+            states.push(StartAdvEx3GameState::Private);
+        }
+        if lobby.slots().is_full() {
+            states.push(StartAdvEx3GameState::Full);
+        }
+        if lobby.slots().players() > 0 {
+            states.push(StartAdvEx3GameState::NotEmpty);
+        }
+        if lobby.phase() != Phase::Lobby {
+            states.push(StartAdvEx3GameState::InProgress);
+        }
+        states
+    }
+
+    fn advertisement(&mut self, lobby: &Lobby) -> BNetOutgoingPacket {
+        let states = self.states(lobby);
+        let free_slots = lobby.slots().open_slots() as u8;
+        let stat = AdvertisedStat {
+            free_slots,
+            host_counter: lobby.config().host_counter,
+            stat: lobby.config().game_stat.clone()
+        };
+
+        self.sent = Some((states.iter().fold(0, |acc, &x| acc | x as u32), free_slots));
+        self.refreshed = Instant::now();
+
+        // the realm doesn't want the lobby's password, that's what the entry key is for
+        let password = BnetString::default();
+        BNetOutgoingPacket {
+            data: c2s::start_adv_ex3(&states, lobby.uptime().as_secs() as u32, self.config.game_type,
+                self.config.sub_game_type, self.config.provider_version, self.config.ladder_type,
+                &self.game_name, &password, &stat.encode())
+        }
+    }
+
+    // the first advertisement, everything after that comes out of update
+    pub fn start(&mut self, lobby: &Lobby) -> BNetOutgoingPacket {
+        self.state = AdvertiserState::Pending;
+        self.advertisement(lobby)
+    }
+
+    // what to send the realm now, if anything
+    pub fn update(&mut self, lobby: &Lobby) -> Option<BNetOutgoingPacket> {
+        let over = match lobby.phase() {
+            Phase::Lobby => false,
+            Phase::Ended => true,
+            _ => !self.config.list_in_progress
+        };
+
+        match self.state {
+            AdvertiserState::Stopped => None,
+            _ if over => {
+                self.state = AdvertiserState::Stopped;
+                Some(BNetOutgoingPacket { data: c2s::stop_adv() })
+            }
+            // the answer may have been lost, or this realm doesn't send one at all
+            AdvertiserState::Pending if self.refreshed.elapsed() >= Duration::from_secs(self.config.refresh_interval) => {
+                Some(self.advertisement(lobby))
+            }
+            AdvertiserState::Pending => None,
+            AdvertiserState::Retrying => {
+                self.state = AdvertiserState::Pending;
+                Some(self.advertisement(lobby))
+            }
+            AdvertiserState::Advertised => {
+                let states = self.states(lobby).iter().fold(0, |acc, &x| acc | x as u32);
+                let changed = self.sent != Some((states, lobby.slots().open_slots() as u8));
+
+                if changed || self.refreshed.elapsed() >= Duration::from_secs(self.config.refresh_interval) {
+                    Some(self.advertisement(lobby))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    // the realm's answer to an advertisement
+    pub fn receive(&mut self, status: StartAdvEx3Status) {
+        if self.state == AdvertiserState::Stopped {
+            return;
+        }
+
+        if status == StartAdvEx3Status::Ok {
+            self.state = AdvertiserState::Advertised;
+            return;
+        }
+
+        // most likely someone else already has a game with that name
+        if self.attempt >= self.config.retries {
+            warn!("realm refused to list {:?}, giving up", self.base_name);
+            self.state = AdvertiserState::Stopped;
+            return;
+        }
+
+        self.attempt += 1;
+        let name = suffixed_name(&self.base_name, self.attempt);
+        info!("realm refused to list {:?}, retrying as {:?}", self.game_name.decode(self.encoding), name);
+        self.game_name = realm_name(&name, self.encoding);
+        self.state = AdvertiserState::Retrying;
+    }
+}
+
+// advertises the lobby and keeps the listing up to date until it's taken down; the realm's
+// answers come back through the realm connection and have to be passed to receive
+pub fn advertise<S>(handle: &Handle, sink: S, advertiser: Rc<RefCell<GameAdvertiser>>, lobby: Rc<RefCell<Lobby>>) -> io::Result<impl Future<Item=S, Error=io::Error>>
+    where S: Sink<SinkItem=BNetOutgoingPacket, SinkError=io::Error> {
+    lobby.borrow_mut().set_listed_on_realm();
+    let first = advertiser.borrow_mut().start(&lobby.borrow());
+
+    let running = advertiser.clone();
+    let ticks = Interval::new(Duration::from_secs(UPDATE_INTERVAL), handle)?
+        .take_while(move |_| Ok(running.borrow().state() != AdvertiserState::Stopped));
+
+    Ok(sink.send(first).and_then(move |sink| ticks.fold(sink, move |sink, _| {
+        match advertiser.borrow_mut().update(&lobby.borrow()) {
+            Some(packet) => Either::A(sink.send(packet)),
+            None => Either::B(future::ok(sink))
+        }
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use host::lobby::tests::config as lobby_config;
+
+    #[test]
+    fn suffixed_names() {
+        assert_eq!(suffixed_name("short", 1), "short (2)");
+
+        let long = "a".repeat(40);
+        assert_eq!(suffixed_name(&long, 1), format!("{} (2)", &long[..27]));
+        assert_eq!(suffixed_name(&long, 9), format!("{} (10)", &long[..26]));
+
+        // two bytes each, the 27th byte is in the middle of one
+        let wide = "é".repeat(20);
+        let name = suffixed_name(&wide, 1);
+        assert_eq!(name, format!("{} (2)", "é".repeat(13)));
+        assert!(name.len() <= MAX_GAME_NAME_LENGTH);
+    }
+
+    #[test]
+    fn receive() {
+        let config = AdvertiseConfig { retries: 2, ..AdvertiseConfig::default() };
+        let lobby = Lobby::new(lobby_config());
+        let mut advertiser = GameAdvertiser::new(config, &lobby, Encoding::Utf8);
+        assert_eq!(advertiser.state(), AdvertiserState::Pending);
+
+        advertiser.receive(StartAdvEx3Status::Failed);
+        assert_eq!(advertiser.state(), AdvertiserState::Retrying);
+        assert_eq!(advertiser.game_name().decode(Encoding::Utf8), "test game (2)");
+
+        // the retry goes out under the new name and waits for an answer again
+        assert!(advertiser.update(&lobby).is_some());
+        assert_eq!(advertiser.state(), AdvertiserState::Pending);
+
+        advertiser.receive(StartAdvEx3Status::Ok);
+        assert_eq!(advertiser.state(), AdvertiserState::Advertised);
+        assert_eq!(advertiser.game_name().decode(Encoding::Utf8), "test game (2)");
+    }
+
+    #[test]
+    fn receive_gives_up() {
+        let config = AdvertiseConfig { retries: 2, ..AdvertiseConfig::default() };
+        let lobby = Lobby::new(lobby_config());
+        let mut advertiser = GameAdvertiser::new(config, &lobby, Encoding::Utf8);
+
+        advertiser.receive(StartAdvEx3Status::Failed);
+        advertiser.receive(StartAdvEx3Status::Failed);
+        assert_eq!(advertiser.game_name().decode(Encoding::Utf8), "test game (3)");
+        assert_eq!(advertiser.state(), AdvertiserState::Retrying);
+
+        advertiser.receive(StartAdvEx3Status::Invalid);
+        assert_eq!(advertiser.state(), AdvertiserState::Stopped);
+        assert!(advertiser.update(&lobby).is_none());
+
+        // too late to change anything
+        advertiser.receive(StartAdvEx3Status::Ok);
+        assert_eq!(advertiser.state(), AdvertiserState::Stopped);
+    }
+}
@@ -0,0 +1,401 @@
+// opens lobbies on its own: whenever the host has room for another game the next map in the
+// rotation gets a lobby, which starts by itself once enough players have joined
+#![allow(dead_code)]
+
+use bytes::Bytes;
+use futures::{Future, Stream};
+use tokio_core::reactor::{Handle, Interval};
+
+use jekuthiel::packets::string::BnetString;
+use jekuthiel::w3gs;
+
+use std::cell::RefCell;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use map::MapInfo;
+
+use super::counter::{self, HostCounter};
+use super::download::DownloadConfig;
+use super::game::GameConfig;
+use super::lobby::{message, Lobby, LobbyConfig, Phase};
+use super::replay::ReplayConfig;
+use super::server::Host;
+
+// how often we check for room for a new lobby and tick the countdown, in seconds
+const UPDATE_INTERVAL: u64 = 1;
+
+#[derive(Clone, Debug)]
+pub struct AutohostConfig {
+    // {} is replaced with the game's number and {map} with the map's name, e.g. "DotA -ap #{}"
+    pub name_template: String,
+    // the number the first game gets, counting up from there
+    pub first_game: u32,
+    // pick a random map every time instead of going through them in order
+    pub random: bool,
+    // players in the lobby before the countdown begins, a full lobby starts right away anyway
+    pub min_players: usize,
+    // seconds between enough players being in and the game starting
+    pub countdown: u64,
+    pub host_name: String,
+    pub admins: Vec<BnetString>,
+    pub downloads: DownloadConfig,
+    pub game: GameConfig,
+    pub replays: Option<ReplayConfig>
+}
+
+impl Default for AutohostConfig {
+    fn default() -> Self {
+        AutohostConfig {
+            name_template: "{map} #{}".to_string(),
+            first_game: 1,
+            random: false,
+            min_players: 2,
+            countdown: 10,
+            host_name: "tabeal".to_string(),
+            admins: Vec::new(),
+            downloads: DownloadConfig::default(),
+            game: GameConfig::default(),
+            replays: None
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AutohostMap {
+    pub name: String,
+    pub map: MapInfo,
+    // where players will find the map
+    pub map_path: Bytes
+}
+
+impl AutohostMap {
+    // players are expected to have the map in their download folder, or get it from us
+    pub fn load<P: AsRef<Path>, C: AsRef<Path>, B: AsRef<Path>>(path: P, common_j: C, blizzard_j: B) -> io::Result<AutohostMap> {
+        let file_name = path.as_ref().file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+        let map = MapInfo::load(path, common_j, blizzard_j)?;
+
+        Ok(AutohostMap {
+            name: file_name.trim_right_matches(".w3x").trim_right_matches(".w3m").to_string(),
+            map,
+            map_path: Bytes::from(format!("Maps\\Download\\{}", file_name))
+        })
+    }
+}
+
+struct Countdown {
+    // the lobby it's counting down for
+    host_counter: u32,
+    started: Instant,
+    // the most players we've seen since it began, anyone leaving calls it off
+    players: usize,
+    // the last number of seconds we told the lobby about
+    announced: u64
+}
+
+pub struct Autohost {
+    config: AutohostConfig,
+    maps: Vec<AutohostMap>,
+    next_map: usize,
+    next_game: u32,
+    host_counter: HostCounter,
+    countdown: Option<Countdown>
+}
+
+fn encode(text: &str) -> io::Result<BnetString> {
+    BnetString::encode(text, w3gs::ENCODING).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "names can't contain NUL"))
+}
+
+// everyone in the lobby can start playing
+fn has_maps(lobby: &Lobby) -> bool {
+    lobby.players().iter()
+        .all(|x| lobby.slots().slot_of(x.pid).map_or(false, |slot| lobby.slots().slots[slot].download_status == 100))
+}
+
+// worth a chat message: every ten seconds, then every second for the last five
+fn announce(seconds: u64) -> bool {
+    seconds <= 5 || seconds % 10 == 0
+}
+
+impl Autohost {
+    pub fn new(config: AutohostConfig, maps: Vec<AutohostMap>, host_counter: HostCounter) -> io::Result<Autohost> {
+        if maps.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no maps to host"));
+        }
+
+        Ok(Autohost {
+            next_game: config.first_game,
+            config,
+            maps,
+            next_map: 0,
+            host_counter,
+            countdown: None
+        })
+    }
+
+    pub fn config(&self) -> &AutohostConfig {
+        &self.config
+    }
+
+    pub fn maps(&self) -> &[AutohostMap] {
+        &self.maps
+    }
+
+    // the name the next game gets
+    pub fn game_name(&self, map: &AutohostMap) -> String {
+        self.config.name_template
+            .replace("{map}", &map.name)
+            .replace("{}", &self.next_game.to_string())
+    }
+
+    fn pick_map(&mut self) -> usize {
+        if self.config.random {
+            counter::random() as usize % self.maps.len()
+        } else {
+            let index = self.next_map % self.maps.len();
+            self.next_map = index + 1;
+            index
+        }
+    }
+
+    // opens a lobby for the next map
+    fn create(&mut self, host: &mut Host) -> io::Result<()> {
+        let index = self.pick_map();
+        let name = self.game_name(&self.maps[index]);
+
+        let mut config = {
+            let map = &self.maps[index];
+            LobbyConfig::from_map(encode(&name)?, encode(&self.config.host_name)?, self.host_counter.next()?,
+                counter::entry_key(), &map.map, map.map_path.clone())
+        };
+        config.admins = self.config.admins.clone();
+        config.downloads = self.config.downloads;
+        config.game = self.config.game;
+        config.replays = self.config.replays.clone();
+
+        host.create(config)?;
+        self.next_game += 1;
+        info!("autohost: opened {}", name);
+        Ok(())
+    }
+
+    // counts down once enough players are in and starts the game when it's over
+    fn update_countdown(&mut self, lobby: &mut Lobby) {
+        let host_counter = lobby.config().host_counter;
+        let players = lobby.players().len();
+        let ready = players >= self.config.min_players && has_maps(lobby);
+
+        let mut countdown = match self.countdown.take() {
+            Some(ref countdown) if countdown.host_counter != host_counter => return,
+            Some(countdown) => countdown,
+            None => {
+                if ready {
+                    lobby.send_all_chat(message(&format!("Starting in {} seconds.", self.config.countdown)));
+                    self.countdown = Some(Countdown {
+                        host_counter,
+                        started: Instant::now(),
+                        players,
+                        announced: self.config.countdown
+                    });
+                }
+                return;
+            }
+        };
+
+        if players < countdown.players {
+            lobby.send_all_chat(message("Countdown aborted, someone left."));
+            return;
+        }
+        if !ready {
+            lobby.send_all_chat(message("Countdown aborted, waiting for everyone to get the map."));
+            return;
+        }
+        countdown.players = players;
+
+        let left = self.config.countdown.saturating_sub(countdown.started.elapsed().as_secs());
+        if left == 0 {
+            lobby.start();
+            return;
+        }
+        if left < countdown.announced && announce(left) {
+            lobby.send_all_chat(message(&format!("{}...", left)));
+            countdown.announced = left;
+        }
+        self.countdown = Some(countdown);
+    }
+
+    pub fn update(&mut self, host: &mut Host) -> io::Result<()> {
+        let lobby = match host.lobby() {
+            Some(lobby) => lobby,
+            None => {
+                self.countdown = None;
+                if host.can_create() {
+                    self.create(host)?;
+                }
+                return Ok(());
+            }
+        };
+
+        let mut lobby = lobby.borrow_mut();
+        if lobby.phase() == Phase::Lobby {
+            self.update_countdown(&mut lobby);
+        } else {
+            self.countdown = None;
+        }
+        Ok(())
+    }
+}
+
+// keeps a lobby open whenever there's room for one, next to serve which takes care of the rest
+pub fn run(handle: &Handle, autohost: Rc<RefCell<Autohost>>, host: Rc<RefCell<Host>>) -> io::Result<impl Future<Item=(), Error=io::Error>> {
+    Ok(Interval::new(Duration::from_secs(UPDATE_INTERVAL), handle)?
+        .for_each(move |_| {
+            // whatever went wrong may be gone by the next tick, giving up would leave us idle
+            if let Err(e) = autohost.borrow_mut().update(&mut host.borrow_mut()) {
+                warn!("autohost: {}", e);
+            }
+            Ok(())
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::sync::mpsc::UnboundedReceiver;
+
+    use jekuthiel::w3gs::W3GSPacket;
+    use jekuthiel::w3gs::packets::ChatCommand;
+
+    use map::w3i::{Controller, Player, W3i};
+
+    use host::lobby::tests::{config as lobby_config, got_map, join};
+
+    fn map(name: &str) -> AutohostMap {
+        let players = (0..4)
+            .map(|number| Player {
+                number,
+                controller: Controller::Human,
+                race: None,
+                fixed_start_position: false,
+                name: String::new(),
+                start_position: (0.0, 0.0)
+            })
+            .collect();
+
+        AutohostMap {
+            name: name.to_string(),
+            map: MapInfo {
+                size: 1000,
+                info: 0,
+                crc: 0,
+                sha1: [0; 20],
+                w3i: W3i {
+                    version: 25,
+                    map_version: 1,
+                    editor_version: 6059,
+                    name: name.to_string(),
+                    author: String::new(),
+                    description: String::new(),
+                    players_recommended: String::new(),
+                    playable_width: 116,
+                    playable_height: 84,
+                    flags: 0,
+                    players,
+                    forces: Vec::new()
+                },
+                data: Bytes::from(vec![0; 1000])
+            },
+            map_path: Bytes::from(format!("Maps\\Download\\{}.w3x", name))
+        }
+    }
+
+    fn autohost(config: AutohostConfig) -> Autohost {
+        Autohost::new(config, vec![map("Test")], HostCounter::in_memory()).unwrap()
+    }
+
+    // what the host told a connection, only once the lobby dropped it
+    fn chat(receiver: UnboundedReceiver<W3GSPacket>) -> Vec<String> {
+        receiver.collect().wait().unwrap().into_iter()
+            .filter_map(|x| match x {
+                W3GSPacket::ChatFromHost(chat) => match chat.command {
+                    ChatCommand::Message(text) => Some(text.decode(w3gs::ENCODING).into_owned()),
+                    _ => None
+                },
+                _ => None
+            })
+            .collect()
+    }
+
+    #[test]
+    fn game_name() {
+        let mut autohost = autohost(AutohostConfig::default());
+        assert_eq!(autohost.game_name(&map("DotA")), "DotA #1");
+
+        autohost.config.name_template = "{} {map} {}".to_string();
+        autohost.next_game = 12;
+        assert_eq!(autohost.game_name(&map("DotA")), "12 DotA 12");
+    }
+
+    #[test]
+    fn countdown_waits_for_players_and_maps() {
+        let mut autohost = autohost(AutohostConfig::default());
+        let mut lobby = Lobby::new(lobby_config());
+
+        let first = join(&mut lobby, "first");
+        got_map(&mut lobby, 2);
+        autohost.update_countdown(&mut lobby);
+        assert!(autohost.countdown.is_none());
+
+        let _second = join(&mut lobby, "second");
+        autohost.update_countdown(&mut lobby);
+        assert!(autohost.countdown.is_none());
+
+        got_map(&mut lobby, 3);
+        autohost.update_countdown(&mut lobby);
+        assert!(autohost.countdown.is_some());
+
+        // the time is up
+        autohost.countdown.as_mut().unwrap().started -= Duration::from_secs(10);
+        autohost.update_countdown(&mut lobby);
+        assert_eq!(lobby.phase(), Phase::Countdown);
+
+        lobby.end();
+        assert_eq!(chat(first), vec!["Starting in 10 seconds."]);
+    }
+
+    #[test]
+    fn countdown_aborts() {
+        let mut autohost = autohost(AutohostConfig::default());
+        let mut lobby = Lobby::new(lobby_config());
+        let first = join(&mut lobby, "first");
+        let _second = join(&mut lobby, "second");
+        got_map(&mut lobby, 2);
+        got_map(&mut lobby, 3);
+
+        autohost.update_countdown(&mut lobby);
+        lobby.kick(3);
+        autohost.update_countdown(&mut lobby);
+        assert!(autohost.countdown.is_none());
+
+        let _second = join(&mut lobby, "second");
+        got_map(&mut lobby, 3);
+        autohost.update_countdown(&mut lobby);
+        assert!(autohost.countdown.is_some());
+
+        // someone new without the map holds everyone up
+        let _third = join(&mut lobby, "third");
+        autohost.update_countdown(&mut lobby);
+        assert!(autohost.countdown.is_none());
+        assert_eq!(lobby.phase(), Phase::Lobby);
+
+        lobby.end();
+        assert_eq!(chat(first), vec![
+            "Starting in 10 seconds.",
+            "Countdown aborted, someone left.",
+            "Starting in 10 seconds.",
+            "Countdown aborted, waiting for everyone to get the map."
+        ]);
+    }
+}
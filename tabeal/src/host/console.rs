@@ -0,0 +1,156 @@
+// commands typed into the terminal the bot runs in, for when there's no realm to whisper it on
+#![allow(dead_code)]
+
+use futures::sync::mpsc::{self, UnboundedReceiver};
+
+use jekuthiel::packets::string::BnetString;
+use jekuthiel::w3gs;
+
+use std::io::{self, BufRead};
+use std::thread;
+
+use super::lobby::{Lobby, Phase};
+
+// stdin has no place in the event loop, so a thread reads it and passes the lines on
+pub fn stdin_lines() -> UnboundedReceiver<String> {
+    let (sender, receiver) = mpsc::unbounded();
+
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => if sender.unbounded_send(line).is_err() {
+                    break;
+                },
+                Err(_) => break
+            }
+        }
+    });
+
+    receiver
+}
+
+const HELP: &[&str] = &[
+    "start [force]   start the countdown, force starts even if someone is missing the map",
+    "say <message>   chat to everyone in the lobby or game",
+    "kick <name>     remove a player",
+    "players         list who's in the game",
+    "end             close the lobby or end the game for everyone",
+    "help            show this"
+];
+
+// runs one line of input, returns what to print back
+pub fn command(lobby: &mut Lobby, line: &str) -> Vec<String> {
+    let line = line.trim();
+    let (command, argument) = match line.find(' ') {
+        Some(index) => (&line[..index], line[index + 1..].trim()),
+        None => (line, "")
+    };
+
+    match command.to_ascii_lowercase().as_str() {
+        "" => Vec::new(),
+        "start" => start(lobby, argument == "force"),
+        "say" if !argument.is_empty() => match BnetString::encode(argument, w3gs::ENCODING) {
+            Ok(text) => {
+                lobby.send_all_chat(text);
+                Vec::new()
+            }
+            Err(_) => vec!["messages can't contain NUL".to_string()]
+        },
+        "kick" if !argument.is_empty() => match lobby.find_player(argument) {
+            Some(pid) => {
+                lobby.kick(pid);
+                vec![format!("kicked {}", argument)]
+            }
+            None => vec![format!("no player named {}", argument)]
+        },
+        "players" => players(lobby),
+        "end" => {
+            lobby.end();
+            vec!["game over".to_string()]
+        }
+        "help" => HELP.iter().map(|x| x.to_string()).collect(),
+        _ => vec![format!("unknown command {:?}, try help", line)]
+    }
+}
+
+fn start(lobby: &mut Lobby, force: bool) -> Vec<String> {
+    if lobby.phase() != Phase::Lobby {
+        return vec!["the game has already started".to_string()];
+    }
+    if lobby.players().is_empty() {
+        return vec!["nobody to play with".to_string()];
+    }
+
+    let missing: Vec<String> = lobby.players().iter()
+        .filter(|x| lobby.slots().slot_of(x.pid).map_or(true, |slot| lobby.slots().slots[slot].download_status != 100))
+        .map(|x| lobby.player_name(x.pid))
+        .collect();
+    if !missing.is_empty() && !force {
+        return vec![format!("still waiting for the map: {}, use start force to start anyway", missing.join(", "))];
+    }
+
+    lobby.start();
+    vec!["starting".to_string()]
+}
+
+fn players(lobby: &Lobby) -> Vec<String> {
+    let players = lobby.players();
+    if players.is_empty() {
+        return vec!["nobody here".to_string()];
+    }
+
+    players.iter()
+        .map(|x| {
+            let slot = lobby.slots().slot_of(x.pid);
+            let download = slot.map_or(0, |slot| lobby.slots().slots[slot].download_status);
+            let ping = x.ping.map_or("?".to_string(), |ping| ping.to_string());
+
+            format!("{:>2} {:<16} slot {:<2} ping {:>4} ms, map {}%",
+                x.pid, lobby.player_name(x.pid), slot.map_or(0, |x| x + 1), ping, download)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use host::lobby::tests::{config, join};
+
+    #[test]
+    fn starts() {
+        let mut lobby = Lobby::new(config());
+        assert_eq!(command(&mut lobby, "start"), vec!["nobody to play with"]);
+
+        let _receiver = join(&mut lobby, "someone");
+        assert_eq!(command(&mut lobby, "start"), vec!["still waiting for the map: someone, use start force to start anyway"]);
+        assert_eq!(lobby.phase(), Phase::Lobby);
+
+        assert_eq!(command(&mut lobby, "  START  force "), vec!["starting"]);
+        assert_eq!(lobby.phase(), Phase::Countdown);
+        assert_eq!(command(&mut lobby, "start force"), vec!["the game has already started"]);
+    }
+
+    #[test]
+    fn kicks() {
+        let mut lobby = Lobby::new(config());
+        let _receiver = join(&mut lobby, "someone");
+        let _other = join(&mut lobby, "someone else");
+
+        assert_eq!(command(&mut lobby, "kick nobody"), vec!["no player named nobody"]);
+        assert_eq!(command(&mut lobby, "kick someone else"), vec!["kicked someone else"]);
+        assert_eq!(lobby.players().len(), 1);
+        assert_eq!(lobby.find_player("someone"), Some(lobby.players()[0].pid));
+    }
+
+    #[test]
+    fn unknown_commands() {
+        let mut lobby = Lobby::new(config());
+        assert_eq!(command(&mut lobby, "launch"), vec!["unknown command \"launch\", try help"]);
+        // the ones that need an argument don't count without one
+        assert_eq!(command(&mut lobby, "kick"), vec!["unknown command \"kick\", try help"]);
+        assert_eq!(command(&mut lobby, "say "), vec!["unknown command \"say\", try help"]);
+        assert!(command(&mut lobby, "   ").is_empty());
+        assert_eq!(command(&mut lobby, "help").len(), HELP.len());
+    }
+}
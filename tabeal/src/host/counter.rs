@@ -0,0 +1,161 @@
+// host counters and entry keys, which is how a join request names the game it's meant for
+#![allow(dead_code)]
+
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+// GHost++ keeps the top bits for telling realms apart, so do we
+const MAX_HOST_COUNTER: u32 = 0x0FFF_FFFF;
+
+// hands out host counters that keep going up across restarts, so a client holding on to an old
+// advertisement can't end up in a new game that happens to reuse its counter
+pub struct HostCounter {
+    path: Option<PathBuf>,
+    next: u32
+}
+
+impl HostCounter {
+    // a missing file just means we start over at 1
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<HostCounter> {
+        let path = path.as_ref();
+        let next = match File::open(path) {
+            Ok(mut file) => {
+                let mut text = String::new();
+                file.read_to_string(&mut text)?;
+                text.trim().parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "host counter file doesn't hold a number"))?
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => 1,
+            Err(e) => return Err(e)
+        };
+
+        Ok(HostCounter {
+            path: Some(path.to_path_buf()),
+            next
+        })
+    }
+
+    // for when nothing needs to survive a restart
+    pub fn in_memory() -> HostCounter {
+        HostCounter {
+            path: None,
+            next: 1
+        }
+    }
+
+    // the counter for a new game, which is saved before it's handed out
+    pub fn next(&mut self) -> io::Result<u32> {
+        let counter = match self.next {
+            0 => 1,
+            x if x > MAX_HOST_COUNTER => 1,
+            x => x
+        };
+        self.next = counter + 1;
+        self.save()?;
+        Ok(counter)
+    }
+
+    // writes to a temporary file first so a crash can't leave it half written
+    fn save(&self) -> io::Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(())
+        };
+        let tmp_path = path.with_extension("tmp");
+
+        {
+            let mut file = File::create(&tmp_path)?;
+            write!(file, "{}", self.next)?;
+        }
+
+        fs::rename(tmp_path, path)
+    }
+}
+
+// std has no random numbers, but its hash maps get randomly keyed
+pub fn random() -> u32 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(0);
+    hasher.finish() as u32
+}
+
+// a fresh key for every game, only players who saw the advertisement know it
+pub fn entry_key() -> u32 {
+    random()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // a file of its own for every test, tests run side by side
+    fn path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("tabeal-counter-{}-{:08x}", name, random()))
+    }
+
+    fn write_file(path: &Path, text: &str) {
+        File::create(path).unwrap().write_all(text.as_bytes()).unwrap();
+    }
+
+    fn contents(path: &Path) -> String {
+        let mut text = String::new();
+        File::open(path).unwrap().read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn missing_file() {
+        let path = path("missing");
+        let mut counter = HostCounter::load(&path).unwrap();
+        assert_eq!(counter.next().unwrap(), 1);
+        assert_eq!(contents(&path), "2");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn persists() {
+        let path = path("persists");
+        write_file(&path, "41\n");
+
+        let mut counter = HostCounter::load(&path).unwrap();
+        assert_eq!(counter.next().unwrap(), 41);
+        assert_eq!(counter.next().unwrap(), 42);
+        assert_eq!(contents(&path), "43");
+        assert!(!path.with_extension("tmp").exists());
+
+        assert_eq!(HostCounter::load(&path).unwrap().next().unwrap(), 43);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn wraps() {
+        let path = path("wraps");
+        write_file(&path, &MAX_HOST_COUNTER.to_string());
+
+        let mut counter = HostCounter::load(&path).unwrap();
+        assert_eq!(counter.next().unwrap(), MAX_HOST_COUNTER);
+        assert_eq!(counter.next().unwrap(), 1);
+
+        // nor is 0 ever handed out
+        write_file(&path, "0");
+        assert_eq!(HostCounter::load(&path).unwrap().next().unwrap(), 1);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn garbage() {
+        let path = path("garbage");
+        write_file(&path, "forty-one");
+        assert_eq!(HostCounter::load(&path).err().map(|x| x.kind()), Some(io::ErrorKind::InvalidData));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn in_memory() {
+        let mut counter = HostCounter::in_memory();
+        assert_eq!((counter.next().unwrap(), counter.next().unwrap()), (1, 2));
+    }
+}
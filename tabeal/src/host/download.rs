@@ -0,0 +1,268 @@
+// serves the map to players who don't have it, in MAPPART chunks paced by their acknowledgements
+#![allow(dead_code)]
+
+use bytes::Bytes;
+
+use jekuthiel::w3gs::packets::{MapPart, MAPPART_MAX_LENGTH};
+
+use std::cmp;
+use std::time::Duration;
+
+use super::lobby::HOST_PID;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DownloadPolicy {
+    Disabled,
+    AdminsOnly,
+    Everyone
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DownloadConfig {
+    pub policy: DownloadPolicy,
+    // bytes per second shared by everyone downloading, None for no limit
+    pub bandwidth: Option<u32>,
+    // how far a single player may get ahead of their acknowledgements
+    pub window: u32
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        DownloadConfig {
+            policy: DownloadPolicy::Everyone,
+            bandwidth: None,
+            window: MAPPART_MAX_LENGTH as u32 * 100
+        }
+    }
+}
+
+struct Download {
+    pid: u8,
+    // everything before this has been sent
+    sent: u32,
+    // everything before this has arrived
+    acked: u32
+}
+
+pub struct Downloads {
+    config: DownloadConfig,
+    data: Bytes,
+    // in the order they get served
+    active: Vec<Download>,
+    // bytes we may still send before the bandwidth cap kicks in
+    budget: u64
+}
+
+impl Downloads {
+    pub fn new(config: DownloadConfig, data: Bytes) -> Downloads {
+        Downloads {
+            config,
+            data,
+            active: Vec::new(),
+            budget: 0
+        }
+    }
+
+    pub fn config(&self) -> &DownloadConfig {
+        &self.config
+    }
+
+    pub fn is_allowed(&self, admin: bool) -> bool {
+        match self.config.policy {
+            DownloadPolicy::Disabled => false,
+            DownloadPolicy::AdminsOnly => admin,
+            DownloadPolicy::Everyone => true
+        }
+    }
+
+    pub fn is_downloading(&self, pid: u8) -> bool {
+        self.active.iter().any(|x| x.pid == pid)
+    }
+
+    pub fn start(&mut self, pid: u8) {
+        if !self.is_downloading(pid) {
+            self.active.push(Download {
+                pid,
+                sent: 0,
+                acked: 0
+            });
+        }
+    }
+
+    pub fn stop(&mut self, pid: u8) {
+        self.active.retain(|x| x.pid != pid);
+    }
+
+    fn percent(&self, acked: u32) -> u8 {
+        match self.data.len() {
+            0 => 100,
+            size => (acked as u64 * 100 / size as u64) as u8
+        }
+    }
+
+    // records how much the player has received, returns their new progress in percent if it
+    // changed; finished downloads are forgotten
+    pub fn ack(&mut self, pid: u8, position: u32) -> Option<u8> {
+        let index = self.active.iter().position(|x| x.pid == pid)?;
+
+        // can't have received what we haven't sent
+        let position = cmp::min(position, self.active[index].sent);
+        let before = self.percent(self.active[index].acked);
+        self.active[index].acked = cmp::max(self.active[index].acked, position);
+
+        let after = self.percent(self.active[index].acked);
+        if self.active[index].acked as usize >= self.data.len() {
+            self.active.remove(index);
+        }
+
+        if after != before {
+            Some(after)
+        } else {
+            None
+        }
+    }
+
+    // the parts to send now, given how long it's been since the last call
+    pub fn poll(&mut self, elapsed: Duration) -> Vec<MapPart> {
+        let mut parts = Vec::new();
+
+        if let Some(bandwidth) = self.config.bandwidth {
+            let millis = elapsed.as_secs() * 1000 + elapsed.subsec_nanos() as u64 / 1_000_000;
+            // don't let an idle period build up into a burst, but always allow for a full part
+            let limit = cmp::max(bandwidth as u64, MAPPART_MAX_LENGTH as u64);
+            self.budget = cmp::min(self.budget + bandwidth as u64 * millis / 1000, limit);
+        }
+
+        // one part per player per round so everyone gets a fair share of the budget, and who goes
+        // first rotates so a tight budget doesn't always end up with the same player
+        if !self.active.is_empty() {
+            let first = self.active.remove(0);
+            self.active.push(first);
+        }
+
+        loop {
+            let mut progress = false;
+
+            for download in &mut self.active {
+                let position = download.sent as usize;
+                if position >= self.data.len() || download.sent - download.acked >= self.config.window {
+                    continue;
+                }
+
+                let length = cmp::min(MAPPART_MAX_LENGTH, self.data.len() - position);
+                if self.config.bandwidth.is_some() {
+                    if self.budget < length as u64 {
+                        return parts;
+                    }
+                    self.budget -= length as u64;
+                }
+
+                parts.push(MapPart {
+                    to_pid: download.pid,
+                    from_pid: HOST_PID,
+                    position: download.sent,
+                    data: self.data.slice(position, position + length)
+                });
+                download.sent += length as u32;
+                progress = true;
+            }
+
+            if !progress {
+                return parts;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PART: usize = MAPPART_MAX_LENGTH;
+
+    fn downloads(size: usize, bandwidth: Option<u32>, window: u32) -> Downloads {
+        let config = DownloadConfig {
+            policy: DownloadPolicy::Everyone,
+            bandwidth,
+            window
+        };
+        Downloads::new(config, Bytes::from(vec![0x55; size]))
+    }
+
+    fn positions(parts: &[MapPart]) -> Vec<(u8, u32)> {
+        parts.iter().map(|x| (x.to_pid, x.position)).collect()
+    }
+
+    #[test]
+    fn is_allowed() {
+        let mut downloads = downloads(10, None, PART as u32);
+        assert!(downloads.is_allowed(false) && downloads.is_allowed(true));
+
+        downloads.config.policy = DownloadPolicy::AdminsOnly;
+        assert!(!downloads.is_allowed(false) && downloads.is_allowed(true));
+
+        downloads.config.policy = DownloadPolicy::Disabled;
+        assert!(!downloads.is_allowed(false) && !downloads.is_allowed(true));
+    }
+
+    #[test]
+    fn percent() {
+        let downloads = downloads(1000, None, PART as u32);
+        assert_eq!(downloads.percent(0), 0);
+        assert_eq!(downloads.percent(333), 33);
+        assert_eq!(downloads.percent(1000), 100);
+
+        // nothing to download is as good as done
+        assert_eq!(Downloads::new(DownloadConfig::default(), Bytes::new()).percent(0), 100);
+    }
+
+    #[test]
+    fn window() {
+        let mut downloads = downloads(PART * 5, None, PART as u32 * 2);
+        downloads.start(2);
+
+        let parts = downloads.poll(Duration::from_millis(0));
+        assert_eq!(positions(&parts), vec![(2, 0), (2, PART as u32)]);
+        assert_eq!(parts[0].data.len(), PART);
+        assert!(downloads.poll(Duration::from_millis(0)).is_empty());
+
+        // each acknowledged part makes room for another one
+        downloads.ack(2, PART as u32);
+        assert_eq!(positions(&downloads.poll(Duration::from_millis(0))), vec![(2, PART as u32 * 2)]);
+    }
+
+    #[test]
+    fn bandwidth() {
+        let mut downloads = downloads(PART * 10, Some(PART as u32 * 2), PART as u32 * 10);
+        downloads.start(2);
+        downloads.start(3);
+
+        // nothing saved up yet
+        assert!(downloads.poll(Duration::from_millis(0)).is_empty());
+
+        // half a second is one part's worth, shared out one at a time and whoever goes first rotates
+        assert_eq!(positions(&downloads.poll(Duration::from_millis(500))), vec![(2, 0)]);
+        assert_eq!(positions(&downloads.poll(Duration::from_millis(500))), vec![(3, 0)]);
+        assert_eq!(positions(&downloads.poll(Duration::from_millis(1000))), vec![(2, PART as u32), (3, PART as u32)]);
+
+        // a long wait doesn't add up to more than a second's worth
+        assert_eq!(downloads.poll(Duration::from_secs(60)).len(), 2);
+    }
+
+    #[test]
+    fn ack() {
+        let size = PART * 2 + 100;
+        let mut downloads = downloads(size, None, size as u32);
+        downloads.start(2);
+        assert_eq!(downloads.poll(Duration::from_millis(0)).len(), 3);
+
+        assert_eq!(downloads.ack(3, 100), None);
+        assert_eq!(downloads.ack(2, PART as u32), Some((PART * 100 / size) as u8));
+        // old acknowledgements don't move anything back
+        assert_eq!(downloads.ack(2, 0), None);
+        // and nobody gets ahead of what we sent
+        assert_eq!(downloads.ack(2, size as u32 * 2), Some(100));
+        assert!(!downloads.is_downloading(2));
+        assert_eq!(downloads.ack(2, size as u32), None);
+    }
+}
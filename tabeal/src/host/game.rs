@@ -0,0 +1,691 @@
+// state of a started game: who is still loading, the actions waiting to go out and the
+// checksums players send back for every batch
+#![allow(dead_code)]
+
+use bytes::Bytes;
+
+use jekuthiel::w3gs::W3GSPacket;
+use jekuthiel::w3gs::packets::{IncomingAction, LagPlayer, PlayerAction};
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// milliseconds between action batches
+pub const DEFAULT_LATENCY: u32 = 100;
+// the client's own countdown after COUNTDOWN_START
+pub const COUNTDOWN_DURATION: u64 = 5;
+// the most action data a single INCOMING_ACTION may carry
+const MAX_ACTIONS_LENGTH: usize = 1452;
+
+// how often the automatic latency is reconsidered
+const LATENCY_ADJUST_INTERVAL: u64 = 2;
+// most the automatic latency moves in one step, in milliseconds
+const LATENCY_ADJUST_STEP: u32 = 10;
+// added on top of the one way trip of the slowest player
+const LATENCY_MARGIN: u32 = 20;
+
+#[derive(Clone, Copy, Debug)]
+pub struct GameConfig {
+    pub latency: u32,
+    // bounds for the latency, both for the automatic mode and commands
+    pub min_latency: u32,
+    pub max_latency: u32,
+    // follow the players' pings instead of keeping the latency fixed
+    pub auto_latency: bool,
+    // how many batches a player may fall behind before everyone has to wait for them
+    pub sync_limit: u32,
+    // seconds until players we're waiting for get dropped without a vote, None to wait forever
+    pub auto_drop: Option<u64>,
+    // whether to kick players whose game went out of sync with the majority
+    pub kick_desynced: bool
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            latency: DEFAULT_LATENCY,
+            min_latency: 20,
+            max_latency: 500,
+            auto_latency: false,
+            sync_limit: 50,
+            auto_drop: Some(60),
+            kick_desynced: false
+        }
+    }
+}
+
+struct GamePlayer {
+    pid: u8,
+    loading: bool,
+    // keepalives received, one for every batch
+    sync_counter: u32,
+    // keepalive checksums not yet compared, oldest first
+    checksums: VecDeque<u32>,
+    lagging_since: Option<Instant>,
+    drop_vote: bool
+}
+
+// players stopped agreeing on the game state at some batch
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Desync {
+    pub tick: u32,
+    // players grouped by the checksum they sent, biggest group first
+    pub groups: Vec<Vec<u8>>
+}
+
+fn millis(duration: Duration) -> u32 {
+    (duration.as_secs() * 1000 + duration.subsec_nanos() as u64 / 1_000_000) as u32
+}
+
+pub struct Game {
+    config: GameConfig,
+    players: Vec<GamePlayer>,
+    actions: Vec<PlayerAction>,
+    // batches sent so far
+    sync_counter: u32,
+    // batches everyone's checksums have been compared for
+    checked_counter: u32,
+    // the groups of the last reported desync, so it isn't reported again every batch
+    desync: Option<Vec<Vec<u8>>>,
+    next_action: Instant,
+    latency_adjusted: Instant
+}
+
+impl Game {
+    pub fn new(pids: &[u8], config: GameConfig) -> Game {
+        let players = pids.iter()
+            .map(|&pid| GamePlayer {
+                pid,
+                loading: true,
+                sync_counter: 0,
+                checksums: VecDeque::new(),
+                lagging_since: None,
+                drop_vote: false
+            })
+            .collect();
+
+        Game {
+            config,
+            players,
+            actions: Vec::new(),
+            sync_counter: 0,
+            checked_counter: 0,
+            desync: None,
+            next_action: Instant::now(),
+            latency_adjusted: Instant::now()
+        }
+    }
+
+    pub fn latency(&self) -> u32 {
+        self.config.latency
+    }
+
+    // returns the latency actually set, which is kept within the configured bounds
+    pub fn set_latency(&mut self, latency: u32) -> u32 {
+        self.config.latency = cmp::max(self.config.min_latency, cmp::min(latency, self.config.max_latency));
+        self.config.latency
+    }
+
+    pub fn is_auto_latency(&self) -> bool {
+        self.config.auto_latency
+    }
+
+    pub fn set_auto_latency(&mut self, auto_latency: bool) {
+        self.config.auto_latency = auto_latency;
+    }
+
+    pub fn sync_limit(&self) -> u32 {
+        self.config.sync_limit
+    }
+
+    pub fn set_sync_limit(&mut self, sync_limit: u32) {
+        self.config.sync_limit = sync_limit;
+    }
+
+    // batches the slowest player is behind
+    fn max_behind(&self) -> u32 {
+        self.players.iter().map(|x| self.sync_counter.saturating_sub(x.sync_counter)).max().unwrap_or(0)
+    }
+
+    // moves the latency a step towards what the slowest ping needs, or up if players keep falling
+    // behind anyway; max_ping is the longest round trip in milliseconds. returns the new latency
+    // if it changed
+    pub fn adjust_latency(&mut self, max_ping: Option<u32>) -> Option<u32> {
+        if !self.config.auto_latency || self.latency_adjusted.elapsed() < Duration::from_secs(LATENCY_ADJUST_INTERVAL) {
+            return None;
+        }
+        self.latency_adjusted = Instant::now();
+
+        let current = self.config.latency;
+        let target = match max_ping {
+            Some(ping) => ping / 2 + LATENCY_MARGIN,
+            None => current
+        };
+
+        // a couple of batches in flight is normal, more means they can't keep up
+        let target = if self.max_behind() > 2 {
+            cmp::max(target, current + LATENCY_ADJUST_STEP)
+        } else {
+            target
+        };
+
+        let next = if target > current {
+            current + cmp::min(target - current, LATENCY_ADJUST_STEP)
+        } else {
+            current - cmp::min(current - target, LATENCY_ADJUST_STEP)
+        };
+
+        match self.set_latency(next) {
+            latency if latency != current => Some(latency),
+            _ => None
+        }
+    }
+
+    pub fn sync_counter(&self) -> u32 {
+        self.sync_counter
+    }
+
+    fn player_mut(&mut self, pid: u8) -> Option<&mut GamePlayer> {
+        self.players.iter_mut().find(|x| x.pid == pid)
+    }
+
+    // false if the player wasn't loading in the first place
+    pub fn loaded(&mut self, pid: u8) -> bool {
+        let was_loading = match self.player_mut(pid) {
+            Some(player) => {
+                let was_loading = player.loading;
+                player.loading = false;
+                was_loading
+            }
+            None => false
+        };
+
+        // the first batch goes out as soon as everyone is in
+        if !self.is_loading() {
+            self.next_action = Instant::now();
+        }
+
+        was_loading
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.players.iter().any(|x| x.loading)
+    }
+
+    pub fn queue_action(&mut self, pid: u8, data: Bytes) {
+        self.actions.push(PlayerAction { pid, data });
+    }
+
+    pub fn keepalive(&mut self, pid: u8, checksum: u32) {
+        if let Some(player) = self.player_mut(pid) {
+            player.sync_counter += 1;
+            player.checksums.push_back(checksum);
+        }
+    }
+
+    // returns how long the others had been waiting if the player was lagging
+    pub fn remove(&mut self, pid: u8) -> Option<u32> {
+        let index = self.players.iter().position(|x| x.pid == pid)?;
+        self.players.remove(index).lagging_since.map(|x| millis(x.elapsed()))
+    }
+
+    pub fn is_lagging(&self) -> bool {
+        self.players.iter().any(|x| x.lagging_since.is_some())
+    }
+
+    pub fn laggers(&self) -> Vec<u8> {
+        self.players.iter().filter(|x| x.lagging_since.is_some()).map(|x| x.pid).collect()
+    }
+
+    // starts the lag screen for players who fell too far behind and stops it for those who
+    // caught up, returns the packets that tell everyone about it
+    pub fn update_lag(&mut self) -> Vec<W3GSPacket> {
+        let (sync_counter, sync_limit) = (self.sync_counter, self.config.sync_limit);
+        let mut started = Vec::new();
+        let mut packets = Vec::new();
+
+        for player in &mut self.players {
+            let behind = sync_counter.saturating_sub(player.sync_counter);
+
+            match player.lagging_since {
+                None if behind > sync_limit => {
+                    player.lagging_since = Some(Instant::now());
+                    started.push(LagPlayer { pid: player.pid, lag_time: 0 });
+                }
+                Some(since) if behind < sync_limit => {
+                    player.lagging_since = None;
+                    packets.push(W3GSPacket::StopLag(LagPlayer { pid: player.pid, lag_time: millis(since.elapsed()) }));
+                }
+                _ => {}
+            }
+        }
+
+        if !started.is_empty() {
+            packets.insert(0, W3GSPacket::StartLag(started));
+        }
+
+        // votes only count for the lag screen they were cast on
+        if !self.is_lagging() {
+            for player in &mut self.players {
+                player.drop_vote = false;
+            }
+        }
+
+        packets
+    }
+
+    // true once more than half of the players we're not waiting for want to drop the others
+    pub fn vote_drop(&mut self, pid: u8) -> bool {
+        if !self.is_lagging() {
+            return false;
+        }
+
+        if let Some(player) = self.player_mut(pid) {
+            if player.lagging_since.is_none() {
+                player.drop_vote = true;
+            }
+        }
+
+        let voters = self.players.iter().filter(|x| x.lagging_since.is_none());
+        let (votes, total) = voters.fold((0, 0), |(votes, total), x| (votes + x.drop_vote as usize, total + 1));
+        votes * 2 > total
+    }
+
+    // compares checksums for every batch all players have answered, returns the desyncs that
+    // weren't reported before
+    pub fn check_desync(&mut self) -> Vec<Desync> {
+        let mut desyncs = Vec::new();
+
+        while !self.players.is_empty() && self.players.iter().all(|x| !x.checksums.is_empty()) {
+            self.checked_counter += 1;
+
+            let mut groups: Vec<(u32, Vec<u8>)> = Vec::new();
+            for player in &mut self.players {
+                let checksum = player.checksums.pop_front().unwrap();
+                match groups.iter().position(|x| x.0 == checksum) {
+                    Some(index) => groups[index].1.push(player.pid),
+                    None => groups.push((checksum, vec![player.pid]))
+                }
+            }
+
+            if groups.len() == 1 {
+                self.desync = None;
+                continue;
+            }
+
+            groups.sort_by(|a, b| b.1.len().cmp(&a.1.len()));
+            let groups: Vec<Vec<u8>> = groups.into_iter().map(|x| x.1).collect();
+
+            if self.desync.as_ref() != Some(&groups) {
+                self.desync = Some(groups.clone());
+                desyncs.push(Desync {
+                    tick: self.checked_counter,
+                    groups
+                });
+            }
+        }
+
+        desyncs
+    }
+
+    // whether everyone has waited long enough to drop the laggers without asking
+    pub fn should_auto_drop(&self) -> bool {
+        let longest = self.players.iter().filter_map(|x| x.lagging_since).min();
+
+        match (longest, self.config.auto_drop) {
+            (Some(since), Some(limit)) => since.elapsed() >= Duration::from_secs(limit),
+            _ => false
+        }
+    }
+
+    // how long until the next batch is due
+    pub fn until_next_action(&self) -> Duration {
+        let now = Instant::now();
+        if self.next_action > now {
+            self.next_action - now
+        } else {
+            Duration::from_millis(0)
+        }
+    }
+
+    // the packets for the next batch, once it's due; actions that don't fit into a single
+    // INCOMING_ACTION go out in INCOMING_ACTION2s before it. nothing goes out while we're
+    // waiting for someone
+    pub fn take_actions(&mut self) -> Option<Vec<W3GSPacket>> {
+        if self.is_loading() || self.is_lagging() || Instant::now() < self.next_action {
+            return None;
+        }
+
+        let latency = Duration::from_millis(self.config.latency as u64);
+
+        // keep to the schedule, but don't try to catch up after a stall
+        self.next_action += latency;
+        if self.next_action < Instant::now() {
+            self.next_action = Instant::now() + latency;
+        }
+
+        let mut packets = Vec::new();
+        let mut batch = Vec::new();
+        let mut length = 0;
+
+        for action in self.actions.drain(..) {
+            let action_length = 3 + action.data.len();
+            if length + action_length > MAX_ACTIONS_LENGTH && !batch.is_empty() {
+                packets.push(W3GSPacket::IncomingAction2(IncomingAction {
+                    send_interval: 0,
+                    actions: batch
+                }));
+                batch = Vec::new();
+                length = 0;
+            }
+
+            length += action_length;
+            batch.push(action);
+        }
+
+        packets.push(W3GSPacket::IncomingAction(IncomingAction {
+            send_interval: self.config.latency as u16,
+            actions: batch
+        }));
+        self.sync_counter += 1;
+
+        Some(packets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // everyone in the game and loaded
+    fn playing(pids: &[u8], config: GameConfig) -> Game {
+        let mut game = Game::new(pids, config);
+        for &pid in pids {
+            game.loaded(pid);
+        }
+        game
+    }
+
+    fn action(pid: u8, length: usize) -> Bytes {
+        Bytes::from(vec![pid; length])
+    }
+
+    #[test]
+    fn loading() {
+        let mut game = Game::new(&[2, 3], GameConfig::default());
+        assert!(game.is_loading());
+        assert_eq!(game.take_actions(), None);
+
+        assert!(game.loaded(2));
+        assert!(!game.loaded(2));
+        assert!(!game.loaded(9));
+        assert!(game.is_loading());
+        assert_eq!(game.take_actions(), None);
+
+        assert!(game.loaded(3));
+        assert!(!game.is_loading());
+        assert_eq!(game.until_next_action(), Duration::from_millis(0));
+        assert!(game.take_actions().is_some());
+    }
+
+    #[test]
+    fn take_actions() {
+        let mut game = playing(&[2, 3], GameConfig::default());
+        assert_eq!(game.take_actions(), Some(vec![W3GSPacket::IncomingAction(IncomingAction {
+            send_interval: DEFAULT_LATENCY as u16,
+            actions: Vec::new()
+        })]));
+        assert_eq!(game.sync_counter(), 1);
+
+        // not due again until the latency is up
+        assert_eq!(game.take_actions(), None);
+        assert!(game.until_next_action() <= Duration::from_millis(DEFAULT_LATENCY as u64));
+    }
+
+    #[test]
+    fn take_actions_splits() {
+        let mut game = playing(&[2, 3], GameConfig::default());
+        // with its pid and length each takes 500 bytes, only two fit
+        for &pid in &[2, 3, 2] {
+            game.queue_action(pid, action(pid, 497));
+        }
+        // fills one up exactly
+        game.queue_action(3, action(3, MAX_ACTIONS_LENGTH - 3));
+
+        let packets = game.take_actions().unwrap();
+        let batches: Vec<(bool, u16, Vec<u8>)> = packets.iter()
+            .map(|x| match *x {
+                W3GSPacket::IncomingAction2(ref x) => (false, x.send_interval, x.actions.iter().map(|x| x.pid).collect()),
+                W3GSPacket::IncomingAction(ref x) => (true, x.send_interval, x.actions.iter().map(|x| x.pid).collect()),
+                _ => panic!("unexpected {:?}", x)
+            })
+            .collect();
+        assert_eq!(batches, vec![(false, 0, vec![2, 3]), (false, 0, vec![2]), (true, DEFAULT_LATENCY as u16, vec![3])]);
+        assert_eq!(game.sync_counter(), 1);
+
+        // the queue is empty now
+        game.next_action = Instant::now();
+        assert_eq!(game.take_actions().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn schedule() {
+        let mut game = playing(&[2], GameConfig::default());
+        let latency = Duration::from_millis(DEFAULT_LATENCY as u64);
+
+        // a little late keeps to the schedule
+        let due = Instant::now() - Duration::from_millis(10);
+        game.next_action = due;
+        game.take_actions().unwrap();
+        assert_eq!(game.next_action, due + latency);
+
+        // a stall starts over from now instead of sending everything we missed at once
+        game.next_action = Instant::now() - Duration::from_secs(10);
+        let now = Instant::now();
+        game.take_actions().unwrap();
+        assert!(game.next_action >= now + latency);
+        assert_eq!(game.take_actions(), None);
+    }
+
+    fn lag_pids(packets: &[W3GSPacket]) -> Vec<(&'static str, Vec<u8>)> {
+        packets.iter()
+            .map(|x| match *x {
+                W3GSPacket::StartLag(ref x) => ("start", x.iter().map(|x| x.pid).collect()),
+                W3GSPacket::StopLag(ref x) => ("stop", vec![x.pid]),
+                _ => panic!("unexpected {:?}", x)
+            })
+            .collect()
+    }
+
+    fn keepalives(game: &mut Game, pid: u8, count: u32) {
+        for _ in 0..count {
+            game.keepalive(pid, 0);
+        }
+    }
+
+    #[test]
+    fn update_lag() {
+        let config = GameConfig { sync_limit: 3, ..GameConfig::default() };
+        let mut game = playing(&[2, 3, 4], config);
+        game.sync_counter = 3;
+        keepalives(&mut game, 4, 3);
+
+        // exactly at the limit is still fine
+        assert!(game.update_lag().is_empty());
+
+        game.sync_counter = 4;
+        keepalives(&mut game, 4, 1);
+        assert_eq!(lag_pids(&game.update_lag()), vec![("start", vec![2, 3])]);
+        assert_eq!(game.laggers(), vec![2, 3]);
+        assert!(game.update_lag().is_empty());
+
+        // back at the limit isn't enough to get going again
+        keepalives(&mut game, 2, 1);
+        assert!(game.update_lag().is_empty());
+        keepalives(&mut game, 2, 1);
+        assert_eq!(lag_pids(&game.update_lag()), vec![("stop", vec![2])]);
+        assert_eq!(game.laggers(), vec![3]);
+
+        // leaving ends the wait as well
+        assert!(game.remove(3).is_some());
+        assert!(!game.is_lagging());
+        assert_eq!(game.remove(2), None);
+    }
+
+    #[test]
+    fn vote_drop() {
+        let mut game = playing(&[2, 3, 4, 5], GameConfig::default());
+        assert!(!game.vote_drop(2));
+
+        let limit = game.config.sync_limit;
+        game.sync_counter = limit + 1;
+        for &pid in &[2, 3, 4] {
+            keepalives(&mut game, pid, limit + 1);
+        }
+        game.update_lag();
+        assert_eq!(game.laggers(), vec![5]);
+
+        // whoever we're waiting for doesn't get a say, it takes two of the other three
+        assert!(!game.vote_drop(5));
+        assert!(!game.vote_drop(2));
+        assert!(!game.vote_drop(2));
+        assert!(game.vote_drop(3));
+    }
+
+    #[test]
+    fn votes_reset_with_the_lag_screen() {
+        let mut game = playing(&[2, 3, 4], GameConfig::default());
+        let limit = game.config.sync_limit;
+        game.sync_counter = limit + 1;
+        keepalives(&mut game, 2, limit + 1);
+        keepalives(&mut game, 3, limit + 1);
+        game.update_lag();
+        assert!(!game.vote_drop(2));
+
+        keepalives(&mut game, 4, 2);
+        assert_eq!(lag_pids(&game.update_lag()), vec![("stop", vec![4])]);
+
+        // a new lag screen, the old vote doesn't carry over
+        game.sync_counter += 2;
+        keepalives(&mut game, 2, 2);
+        keepalives(&mut game, 3, 2);
+        assert_eq!(lag_pids(&game.update_lag()), vec![("start", vec![4])]);
+        assert!(!game.vote_drop(3));
+        assert!(game.vote_drop(2));
+    }
+
+    #[test]
+    fn should_auto_drop() {
+        let mut game = playing(&[2, 3], GameConfig::default());
+        assert!(!game.should_auto_drop());
+
+        game.players[1].lagging_since = Some(Instant::now());
+        assert!(!game.should_auto_drop());
+
+        // whoever has been lagging the longest counts
+        game.players[0].lagging_since = Some(Instant::now() - Duration::from_secs(60));
+        assert!(game.should_auto_drop());
+
+        game.config.auto_drop = None;
+        assert!(!game.should_auto_drop());
+    }
+
+    fn desyncs(game: &mut Game, checksums: &[(u8, u32)]) -> Vec<Desync> {
+        for &(pid, checksum) in checksums {
+            game.keepalive(pid, checksum);
+        }
+        game.check_desync()
+    }
+
+    #[test]
+    fn check_desync() {
+        let mut game = playing(&[2, 3, 4, 5], GameConfig::default());
+
+        // only once everyone answered
+        assert!(desyncs(&mut game, &[(2, 1), (3, 1), (4, 1)]).is_empty());
+        assert!(desyncs(&mut game, &[(5, 1)]).is_empty());
+
+        assert_eq!(desyncs(&mut game, &[(2, 7), (3, 8), (4, 8), (5, 9)]), vec![Desync {
+            tick: 2,
+            groups: vec![vec![3, 4], vec![2], vec![5]]
+        }]);
+
+        // the same split isn't news
+        assert!(desyncs(&mut game, &[(2, 1), (3, 2), (4, 2), (5, 3)]).is_empty());
+
+        // a different one is
+        assert_eq!(desyncs(&mut game, &[(2, 1), (3, 2), (4, 2), (5, 2)]), vec![Desync {
+            tick: 4,
+            groups: vec![vec![3, 4, 5], vec![2]]
+        }]);
+    }
+
+    #[test]
+    fn desync_after_resync() {
+        let mut game = playing(&[2, 3, 4], GameConfig::default());
+
+        assert_eq!(desyncs(&mut game, &[(2, 1), (3, 1), (4, 2)]).len(), 1);
+        assert!(desyncs(&mut game, &[(2, 1), (3, 1), (4, 1)]).is_empty());
+
+        // several batches at once are compared one at a time
+        let found = desyncs(&mut game, &[(2, 1), (3, 1), (4, 2), (2, 1), (3, 1), (4, 2)]);
+        assert_eq!(found, vec![Desync {
+            tick: 3,
+            groups: vec![vec![2, 3], vec![4]]
+        }]);
+    }
+
+    // as if the last adjustment was long enough ago
+    fn adjust_latency(game: &mut Game, max_ping: Option<u32>) -> Option<u32> {
+        game.latency_adjusted = Instant::now() - Duration::from_secs(LATENCY_ADJUST_INTERVAL);
+        game.adjust_latency(max_ping)
+    }
+
+    #[test]
+    fn set_latency() {
+        let mut game = playing(&[2], GameConfig::default());
+        assert_eq!(game.set_latency(150), 150);
+        assert_eq!(game.set_latency(1000), 500);
+        assert_eq!(game.set_latency(0), 20);
+        assert_eq!(game.latency(), 20);
+    }
+
+    #[test]
+    fn adjust_latency_steps() {
+        let config = GameConfig { auto_latency: true, ..GameConfig::default() };
+        let mut game = playing(&[2, 3], config);
+
+        // half the round trip and the margin is 170, a step at a time
+        assert_eq!(adjust_latency(&mut game, Some(300)), Some(110));
+        assert_eq!(game.adjust_latency(Some(300)), None);
+        assert_eq!(adjust_latency(&mut game, Some(300)), Some(120));
+
+        assert_eq!(adjust_latency(&mut game, Some(196)), Some(118));
+        assert_eq!(adjust_latency(&mut game, Some(196)), None);
+        assert_eq!(adjust_latency(&mut game, None), None);
+        assert_eq!(adjust_latency(&mut game, Some(0)), Some(108));
+
+        // falling behind goes up whatever the pings say
+        game.sync_counter = 3;
+        keepalives(&mut game, 3, 3);
+        assert_eq!(adjust_latency(&mut game, Some(0)), Some(118));
+
+        game.set_auto_latency(false);
+        assert_eq!(adjust_latency(&mut game, Some(300)), None);
+        assert_eq!(game.latency(), 118);
+    }
+
+    #[test]
+    fn adjust_latency_bounds() {
+        let config = GameConfig { latency: 495, auto_latency: true, ..GameConfig::default() };
+        let mut game = playing(&[2], config);
+
+        assert_eq!(adjust_latency(&mut game, Some(2000)), Some(500));
+        assert_eq!(adjust_latency(&mut game, Some(2000)), None);
+
+        game.set_latency(25);
+        assert_eq!(adjust_latency(&mut game, Some(0)), Some(20));
+        assert_eq!(adjust_latency(&mut game, Some(0)), None);
+    }
+}
@@ -0,0 +1,246 @@
+// makes the lobby show up in the LAN screen: answers searches and tells the network when the
+// game is created, changes and goes away
+#![allow(dead_code)]
+
+use futures::{Future, Sink, Stream};
+use futures::sync::mpsc;
+use tokio_core::net::UdpSocket;
+use tokio_core::reactor::{Handle, Interval};
+
+use jekuthiel::w3gs::{W3GSPacket, W3GSUdpCodec};
+use jekuthiel::w3gs::packets::{CreateGame, PRODUCT_TFT};
+
+use std::cell::RefCell;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use super::lobby::{Lobby, Phase};
+use super::server::Host;
+
+// the client only ever searches on this one
+pub const LAN_PORT: u16 = 6112;
+// how often we check whether the lobby changed, in seconds
+const UPDATE_INTERVAL: u64 = 1;
+
+#[derive(Clone, Copy, Debug)]
+pub struct LanConfig {
+    // where we listen for searches
+    pub port: u16,
+    // where CREATEGAME, REFRESHGAME and DECREATEGAME go
+    pub broadcast: SocketAddrV4,
+    // the TCP port players join on
+    pub game_port: u16,
+    // the minor version clients have to be on, 26 for 1.26
+    pub version: u32,
+    // seconds between REFRESHGAMEs
+    pub refresh_interval: u64
+}
+
+impl Default for LanConfig {
+    fn default() -> Self {
+        LanConfig {
+            port: LAN_PORT,
+            broadcast: SocketAddrV4::new(Ipv4Addr::new(255, 255, 255, 255), LAN_PORT),
+            game_port: LAN_PORT,
+            version: 26,
+            refresh_interval: 5
+        }
+    }
+}
+
+// which lobby, if any, LAN players see right now; None ends the advertising unless it's to go
+// on for good
+struct Listing<F> {
+    open: F,
+    forever: bool,
+    // the host counter of the lobby we last told the network about
+    listed: Option<u32>,
+    refreshed: Instant
+}
+
+impl<F: Fn() -> Option<Rc<RefCell<Lobby>>>> Listing<F> {
+    fn open(&self) -> Option<Rc<RefCell<Lobby>>> {
+        (self.open)().and_then(|x| if x.borrow().phase() == Phase::Lobby { Some(x) } else { None })
+    }
+
+    // what to broadcast now that a second went by
+    fn update(&mut self, config: &LanConfig) -> Vec<W3GSPacket> {
+        let open = self.open();
+        let host_counter = open.as_ref().map(|x| x.borrow().config().host_counter);
+        let mut packets = Vec::new();
+
+        if host_counter != self.listed {
+            if let Some(listed) = self.listed {
+                packets.push(W3GSPacket::DecreateGame(listed));
+            }
+            if let (Some(ref lobby), Some(host_counter)) = (open.as_ref(), host_counter) {
+                lobby.borrow_mut().set_listed_on_lan();
+                packets.push(W3GSPacket::CreateGame(CreateGame {
+                    product: PRODUCT_TFT,
+                    version: config.version,
+                    host_counter
+                }));
+            }
+            self.listed = host_counter;
+            self.refreshed = Instant::now();
+        } else if let Some(ref lobby) = open {
+            if self.refreshed.elapsed() >= Duration::from_secs(config.refresh_interval) {
+                packets.push(W3GSPacket::RefreshGame(lobby.borrow().refresh_game()));
+                self.refreshed = Instant::now();
+            }
+        }
+
+        packets
+    }
+}
+
+fn run<F>(handle: &Handle, config: LanConfig, open: F, forever: bool) -> io::Result<impl Future<Item=(), Error=io::Error>>
+    where F: Fn() -> Option<Rc<RefCell<Lobby>>> + 'static {
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), config.port);
+    let socket = UdpSocket::bind(&address, handle)?;
+    socket.set_broadcast(true)?;
+
+    let (sink, stream) = socket.framed(W3GSUdpCodec).split();
+    let (sender, receiver) = mpsc::unbounded();
+
+    // the queue ends once every sender below is gone, the last one after DECREATEGAME
+    let receiver = receiver.map_err(|_| io::Error::new(io::ErrorKind::Other, "LAN queue failed"));
+    handle.spawn(sink.send_all(receiver).map(|_| ()).map_err(|_| ()));
+
+    let broadcast = SocketAddr::V4(config.broadcast);
+    let listing = Rc::new(RefCell::new(Listing {
+        open,
+        forever,
+        listed: None,
+        refreshed: Instant::now()
+    }));
+    for packet in listing.borrow_mut().update(&config) {
+        let _ = sender.unbounded_send((broadcast, packet));
+    }
+
+    let answers = {
+        let listing = listing.clone();
+        let sender = sender.clone();
+        stream.for_each(move |(address, packet)| {
+            if let Some(W3GSPacket::SearchGame(search)) = packet {
+                if let Some(lobby) = listing.borrow().open() {
+                    if search.product == PRODUCT_TFT && search.version == config.version {
+                        let info = lobby.borrow().game_info(PRODUCT_TFT, config.version, config.game_port);
+                        let _ = sender.unbounded_send((address, W3GSPacket::GameInfo(info)));
+                    }
+                }
+            }
+            Ok(())
+        })
+    };
+
+    let updates = {
+        let listing = listing.clone();
+        let sender = sender.clone();
+        Interval::new(Duration::from_secs(UPDATE_INTERVAL), handle)?
+            .take_while(move |_| {
+                let mut listing = listing.borrow_mut();
+                for packet in listing.update(&config) {
+                    let _ = sender.unbounded_send((broadcast, packet));
+                }
+                Ok(listing.forever || listing.listed.is_some())
+            })
+            .for_each(|_| Ok(()))
+    };
+
+    Ok(updates.select(answers).map(|_| ()).map_err(|(e, _)| e)
+        .then(move |result| {
+            if let Some(listed) = listing.borrow().listed {
+                let _ = sender.unbounded_send((broadcast, W3GSPacket::DecreateGame(listed)));
+            }
+            result
+        }))
+}
+
+// runs until the lobby closes, the game is taken off the LAN screens on the way out
+pub fn advertise(handle: &Handle, config: LanConfig, lobby: Rc<RefCell<Lobby>>) -> io::Result<impl Future<Item=(), Error=io::Error>> {
+    run(handle, config, move || Some(lobby.clone()), false)
+}
+
+// lists whichever lobby the host has open, for as long as the host is around; the socket stays
+// the same for every lobby, a new one couldn't bind the port before the last let go of it
+pub fn advertise_host(handle: &Handle, config: LanConfig, host: Rc<RefCell<Host>>) -> io::Result<impl Future<Item=(), Error=io::Error>> {
+    run(handle, config, move || host.borrow().lobby(), true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use host::lobby::tests::{config, join};
+    use jekuthiel::w3gs::packets::RefreshGame;
+
+    fn lobby(host_counter: u32) -> Rc<RefCell<Lobby>> {
+        let mut config = config();
+        config.host_counter = host_counter;
+        Rc::new(RefCell::new(Lobby::new(config)))
+    }
+
+    fn create(host_counter: u32) -> W3GSPacket {
+        W3GSPacket::CreateGame(CreateGame {
+            product: PRODUCT_TFT,
+            version: 26,
+            host_counter
+        })
+    }
+
+    #[test]
+    fn update() {
+        let open = Rc::new(RefCell::new(Some(lobby(7))));
+        let mut listing = {
+            let open = open.clone();
+            Listing {
+                open: move || open.borrow().clone(),
+                forever: true,
+                listed: None,
+                refreshed: Instant::now()
+            }
+        };
+        let mut config = LanConfig::default();
+
+        assert_eq!(listing.update(&config), vec![create(7)]);
+        assert_eq!(listing.update(&config), vec![]);
+
+        config.refresh_interval = 0;
+        assert_eq!(listing.update(&config), vec![W3GSPacket::RefreshGame(RefreshGame {
+            host_counter: 7,
+            players: 0,
+            slots: 4
+        })]);
+
+        *open.borrow_mut() = Some(lobby(8));
+        assert_eq!(listing.update(&config), vec![W3GSPacket::DecreateGame(7), create(8)]);
+
+        *open.borrow_mut() = None;
+        assert_eq!(listing.update(&config), vec![W3GSPacket::DecreateGame(8)]);
+        assert_eq!(listing.listed, None);
+        assert_eq!(listing.update(&config), vec![]);
+    }
+
+    #[test]
+    fn started_lobbies_are_taken_down() {
+        let lobby = lobby(7);
+        let mut listing = {
+            let lobby = lobby.clone();
+            Listing {
+                open: move || Some(lobby.clone()),
+                forever: false,
+                listed: None,
+                refreshed: Instant::now()
+            }
+        };
+        let config = LanConfig::default();
+
+        assert_eq!(listing.update(&config), vec![create(7)]);
+        let _receiver = join(&mut lobby.borrow_mut(), "someone");
+        lobby.borrow_mut().start();
+        assert_eq!(listing.update(&config), vec![W3GSPacket::DecreateGame(7)]);
+        assert_eq!(listing.listed, None);
+    }
+}
@@ -0,0 +1,1190 @@
+// state of a single game lobby, driven by the packets players send to the listener
+#![allow(dead_code)]
+
+use bytes::Bytes;
+use futures::sync::mpsc::UnboundedSender;
+
+use jekuthiel::packets::statstring::{GameFlags, GameStat};
+use jekuthiel::packets::string::BnetString;
+use jekuthiel::replay::{ReplayGame, ReplayPlayer, ReplayRecord, ReplayWriter, GAME_TYPE_CUSTOM, LEAVE_LOCAL, LEAVE_REMOTE};
+use jekuthiel::w3gs::{self, W3GSPacket};
+use jekuthiel::w3gs::packets::*;
+use jekuthiel::w3gs::slots::SlotTable;
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use map::MapInfo;
+
+use super::download::{DownloadConfig, Downloads};
+use super::game::{Desync, Game, GameConfig, COUNTDOWN_DURATION};
+use super::replay::ReplayConfig;
+
+// pid of the fake player the bot uses to talk in the lobby, never occupies a slot
+pub const HOST_PID: u8 = 1;
+const MAX_PID: u8 = 12;
+
+pub struct LobbyConfig {
+    pub game_name: BnetString,
+    pub game_stat: GameStat,
+    pub host_name: BnetString,
+    pub host_counter: u32,
+    pub entry_key: u32,
+    pub map_check: MapCheck,
+    // the map file itself, without it nobody can download the map from us
+    pub map_data: Option<Bytes>,
+    pub downloads: DownloadConfig,
+    pub slots: SlotTable,
+    pub admins: Vec<BnetString>,
+    pub game: GameConfig,
+    // None to not record replays
+    pub replays: Option<ReplayConfig>
+}
+
+impl LobbyConfig {
+    // slots are laid out the way the map's w3i says, map_path is where players will find the map
+    pub fn from_map(game_name: BnetString, host_name: BnetString, host_counter: u32, entry_key: u32, map: &MapInfo, map_path: Bytes) -> LobbyConfig {
+        let game_stat = GameStat {
+            flags: GameFlags::default(),
+            map_width: map.w3i.playable_width as u16,
+            map_height: map.w3i.playable_height as u16,
+            map_crc: map.crc,
+            map_path: map_path.clone(),
+            host_name: host_name.clone().into_bytes(),
+            map_sha1: Some(map.sha1)
+        };
+
+        LobbyConfig {
+            game_name,
+            game_stat,
+            host_name,
+            host_counter,
+            entry_key,
+            map_check: map.map_check(map_path),
+            map_data: Some(map.data.clone()),
+            downloads: DownloadConfig::default(),
+            slots: map.w3i.slot_table(),
+            admins: Vec::new(),
+            game: GameConfig::default(),
+            replays: None
+        }
+    }
+}
+
+pub struct Player {
+    pub pid: u8,
+    pub name: BnetString,
+    pub internal_address: SocketAddrV4,
+    // round trip in milliseconds, None until the first pong
+    pub ping: Option<u32>
+}
+
+struct Connection {
+    address: SocketAddrV4,
+    sender: UnboundedSender<W3GSPacket>,
+    player: Option<Player>
+}
+
+pub type ConnectionID = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Lobby,
+    Countdown,
+    Loading,
+    Playing,
+    // everyone left, nothing more to do
+    Ended
+}
+
+// how often update wants to be called while nothing time critical is going on
+const UPDATE_INTERVAL: u64 = 100;
+// lobby chat kept for the replay, older messages make room for new ones
+const MAX_LOBBY_CHAT: usize = 100;
+
+pub struct Lobby {
+    config: LobbyConfig,
+    phase: Phase,
+    slots: SlotTable,
+    downloads: Option<Downloads>,
+    game: Option<Game>,
+    replay: Option<ReplayWriter>,
+    // chat from before the game started, it goes into the replay once there is one
+    lobby_chat: Vec<(u8, BnetString)>,
+    countdown_started: Option<Instant>,
+    // commands for whoever runs the lobby, which it can't answer on its own. they're only kept
+    // once someone said they'd take them, otherwise nothing would ever empty the queue
+    commands: Vec<(u8, String)>,
+    forward_commands: bool,
+    // where the lobby is listed, only the LAN game info carries the entry key
+    listed_on_lan: bool,
+    listed_on_realm: bool,
+    connections: HashMap<ConnectionID, Connection>,
+    next_connection: ConnectionID,
+    created: Instant
+}
+
+fn unspecified_address() -> SocketAddrV4 {
+    SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0)
+}
+
+// the game talks utf-8. only for text we wrote ourselves, which never contains a NUL, anything
+// typed in by someone has to be encoded by whoever got it
+pub fn message(text: &str) -> BnetString {
+    BnetString::encode(text, w3gs::ENCODING).unwrap()
+}
+
+impl Lobby {
+    pub fn new(config: LobbyConfig) -> Lobby {
+        Lobby {
+            phase: Phase::Lobby,
+            slots: config.slots.clone(),
+            downloads: config.map_data.clone().map(|data| Downloads::new(config.downloads, data)),
+            game: None,
+            replay: None,
+            lobby_chat: Vec::new(),
+            countdown_started: None,
+            commands: Vec::new(),
+            forward_commands: false,
+            listed_on_lan: false,
+            listed_on_realm: false,
+            config,
+            connections: HashMap::new(),
+            next_connection: 0,
+            created: Instant::now()
+        }
+    }
+
+    pub fn config(&self) -> &LobbyConfig {
+        &self.config
+    }
+
+    pub fn slots(&self) -> &SlotTable {
+        &self.slots
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.phase == Phase::Ended
+    }
+
+    pub fn players(&self) -> Vec<&Player> {
+        let mut players: Vec<_> = self.connections.values().filter_map(|x| x.player.as_ref()).collect();
+        players.sort_by_key(|x| x.pid);
+        players
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.created.elapsed()
+    }
+
+    pub fn is_connected(&self, connection: ConnectionID) -> bool {
+        self.connections.contains_key(&connection)
+    }
+
+    // milliseconds since the lobby was created, wraps after ~49 days which the client doesn't care about
+    fn ticks(&self) -> u32 {
+        let elapsed = self.created.elapsed();
+        (elapsed.as_secs() * 1000 + elapsed.subsec_nanos() as u64 / 1_000_000) as u32
+    }
+
+    pub fn connect(&mut self, address: SocketAddrV4, sender: UnboundedSender<W3GSPacket>) -> ConnectionID {
+        let id = self.next_connection;
+        self.next_connection += 1;
+
+        self.connections.insert(id, Connection {
+            address,
+            sender,
+            player: None
+        });
+
+        id
+    }
+
+    pub fn disconnect(&mut self, connection: ConnectionID) {
+        self.remove(connection, LeaveReason::Disconnect as u32);
+    }
+
+    pub fn receive(&mut self, connection: ConnectionID, packet: W3GSPacket) {
+        let pid = match self.connections.get(&connection) {
+            Some(x) => x.player.as_ref().map(|x| x.pid),
+            None => return
+        };
+
+        match (pid, packet) {
+            (None, W3GSPacket::ReqJoin(request)) => self.join(connection, request),
+            // nothing but a join request is allowed before joining
+            (None, _) => self.drop_connection(connection),
+            (Some(pid), W3GSPacket::ChatToHost(chat)) => self.chat(pid, chat),
+            (Some(pid), W3GSPacket::MapSize(map_size)) => self.map_size(pid, map_size),
+            (Some(pid), W3GSPacket::MapPartOk(ok)) => self.map_part_ok(pid, ok),
+            (Some(pid), W3GSPacket::PongToHost(value)) => self.pong(pid, value),
+            (Some(pid), W3GSPacket::GameLoadedSelf) => self.game_loaded(pid),
+            (Some(pid), W3GSPacket::OutgoingAction(action)) => self.action(pid, action),
+            (Some(pid), W3GSPacket::OutgoingKeepalive(keepalive)) => self.keepalive(pid, keepalive),
+            (Some(pid), W3GSPacket::DropReq) => self.drop_request(pid),
+            (Some(_), W3GSPacket::LeaveGame(reason)) => {
+                let reason = if self.phase == Phase::Lobby { LeaveReason::Lobby as u32 } else { reason };
+                self.remove(connection, reason);
+            }
+            _ => {}
+        }
+    }
+
+    fn send(&self, connection: ConnectionID, packet: W3GSPacket) {
+        if let Some(x) = self.connections.get(&connection) {
+            // the receiving end only goes away together with the connection
+            let _ = x.sender.unbounded_send(packet);
+        }
+    }
+
+    pub fn send_to(&self, pid: u8, packet: W3GSPacket) {
+        if let Some(connection) = self.connection_of(pid) {
+            self.send(connection, packet);
+        }
+    }
+
+    pub fn broadcast(&self, packet: W3GSPacket) {
+        for connection in self.connections.values() {
+            if connection.player.is_some() {
+                let _ = connection.sender.unbounded_send(packet.clone());
+            }
+        }
+    }
+
+    fn broadcast_except(&self, except: u8, packet: W3GSPacket) {
+        for connection in self.connections.values() {
+            if let Some(ref player) = connection.player {
+                if player.pid != except {
+                    let _ = connection.sender.unbounded_send(packet.clone());
+                }
+            }
+        }
+    }
+
+    fn connection_of(&self, pid: u8) -> Option<ConnectionID> {
+        self.connections.iter()
+            .find(|&(_, x)| x.player.as_ref().map(|x| x.pid) == Some(pid))
+            .map(|(&id, _)| id)
+    }
+
+    pub fn send_slot_info(&self) {
+        self.broadcast(W3GSPacket::SlotInfo(self.slots.encode()));
+    }
+
+    // how the game shows up in the LAN screen, port is where we listen for players
+    pub fn game_info(&self, product: u32, version: u32, port: u16) -> GameInfo {
+        GameInfo {
+            product,
+            version,
+            host_counter: self.config.host_counter,
+            entry_key: self.config.entry_key,
+            game_name: self.config.game_name.clone(),
+            stat: self.config.game_stat.encode(),
+            slots_total: self.slots.slots.len() as u32,
+            game_type: GAMEINFO_TYPE_CUSTOM,
+            slots_open: self.slots.open_slots() as u32,
+            uptime: self.uptime().as_secs() as u32,
+            port
+        }
+    }
+
+    pub fn set_listed_on_lan(&mut self) {
+        self.listed_on_lan = true;
+    }
+
+    pub fn set_listed_on_realm(&mut self) {
+        self.listed_on_realm = true;
+    }
+
+    // realm players never get to see the entry key, and once the lobby is on a realm as well
+    // there's no telling them apart from LAN players
+    fn checks_entry_key(&self) -> bool {
+        self.listed_on_lan && !self.listed_on_realm
+    }
+
+    pub fn refresh_game(&self) -> RefreshGame {
+        RefreshGame {
+            host_counter: self.config.host_counter,
+            players: self.slots.players() as u32,
+            slots: self.slots.slots.len() as u32
+        }
+    }
+
+    // the fake host player is gone once the game starts, from then on we talk as whoever has
+    // the lowest pid
+    fn host_pid(&self) -> u8 {
+        match self.phase {
+            Phase::Lobby => HOST_PID,
+            _ => self.players().first().map_or(HOST_PID, |x| x.pid)
+        }
+    }
+
+    fn chat_command(&self, message: BnetString) -> ChatCommand {
+        match self.phase {
+            Phase::Lobby | Phase::Countdown => ChatCommand::Message(message),
+            // sent to everyone
+            _ => ChatCommand::MessageExtra(0, message)
+        }
+    }
+
+    pub fn send_chat(&self, to_pid: u8, message: BnetString) {
+        self.send_to(to_pid, W3GSPacket::ChatFromHost(Chat {
+            to_pids: vec![to_pid],
+            from_pid: self.host_pid(),
+            command: self.chat_command(message)
+        }));
+    }
+
+    pub fn send_all_chat(&self, message: BnetString) {
+        let to_pids: Vec<_> = self.players().iter().map(|x| x.pid).collect();
+
+        self.broadcast(W3GSPacket::ChatFromHost(Chat {
+            to_pids,
+            from_pid: self.host_pid(),
+            command: self.chat_command(message)
+        }));
+    }
+
+    pub fn ping_all(&self) {
+        self.broadcast(W3GSPacket::PingFromHost(self.ticks()));
+    }
+
+    fn reject(&mut self, connection: ConnectionID, reason: RejectReason) {
+        self.send(connection, W3GSPacket::RejectJoin(reason as u32));
+        self.drop_connection(connection);
+    }
+
+    // forgets the connection, which also ends its outgoing queue and closes the socket
+    fn drop_connection(&mut self, connection: ConnectionID) {
+        self.connections.remove(&connection);
+    }
+
+    fn free_pid(&self) -> Option<u8> {
+        (HOST_PID + 1..MAX_PID + 1).find(|&pid| self.connection_of(pid).is_none())
+    }
+
+    fn host_info(&self) -> PlayerInfo {
+        PlayerInfo {
+            join_counter: 2,
+            pid: HOST_PID,
+            name: self.config.host_name.clone(),
+            external_address: unspecified_address(),
+            internal_address: unspecified_address()
+        }
+    }
+
+    fn player_info(&self, player: &Player, address: SocketAddrV4) -> PlayerInfo {
+        PlayerInfo {
+            join_counter: 2,
+            pid: player.pid,
+            name: player.name.clone(),
+            external_address: address,
+            internal_address: player.internal_address
+        }
+    }
+
+    fn join(&mut self, connection: ConnectionID, request: ReqJoin) {
+        // a different host counter means they're after a game that's gone by now
+        if request.host_counter != self.config.host_counter || self.phase != Phase::Lobby {
+            return self.reject(connection, RejectReason::Started);
+        }
+
+        if self.checks_entry_key() && request.entry_key != self.config.entry_key {
+            info!("game {}: {} joined with the wrong entry key", self.config.host_counter, self.connections[&connection].address);
+            return self.reject(connection, RejectReason::WrongPassword);
+        }
+
+        let taken = Some(&self.config.host_name).into_iter()
+            .chain(self.players().iter().map(|x| &x.name))
+            .any(|x| x.as_bytes().eq_ignore_ascii_case(request.name.as_bytes()));
+        if taken || request.name.is_empty() {
+            return self.reject(connection, RejectReason::Full);
+        }
+
+        let pid = match self.free_pid() {
+            Some(pid) => pid,
+            None => return self.reject(connection, RejectReason::Full)
+        };
+
+        if self.slots.occupy(pid).is_err() {
+            return self.reject(connection, RejectReason::Full);
+        }
+
+        let player = Player {
+            pid,
+            name: request.name,
+            internal_address: request.internal_address,
+            ping: None
+        };
+
+        let address = self.connections[&connection].address;
+        let new_info = self.player_info(&player, address);
+
+        self.send(connection, W3GSPacket::SlotInfoJoin(SlotInfoJoin {
+            slot_info: self.slots.encode(),
+            pid,
+            address
+        }));
+        self.send(connection, W3GSPacket::PlayerInfo(self.host_info()));
+        for other in self.connections.values() {
+            if let Some(ref other_player) = other.player {
+                self.send(connection, W3GSPacket::PlayerInfo(self.player_info(other_player, other.address)));
+            }
+        }
+
+        self.broadcast(W3GSPacket::PlayerInfo(new_info));
+        self.connections.get_mut(&connection).unwrap().player = Some(player);
+
+        self.send(connection, W3GSPacket::MapCheck(self.config.map_check.clone()));
+        self.send_slot_info();
+    }
+
+    fn remove(&mut self, connection: ConnectionID, reason: u32) {
+        let player = match self.connections.remove(&connection) {
+            Some(Connection { player: Some(player), .. }) => player,
+            _ => return
+        };
+
+        if let Some(ref mut downloads) = self.downloads {
+            downloads.stop(player.pid);
+        }
+
+        self.broadcast(W3GSPacket::PlayerLeaveOthers(PlayerLeave {
+            pid: player.pid,
+            reason
+        }));
+
+        // gone before the replay began, and the pid may go to someone else in the lobby
+        self.lobby_chat.retain(|x| x.0 != player.pid);
+
+        // once the game started slots stay as they are
+        if self.phase == Phase::Lobby {
+            self.slots.free(player.pid);
+            self.send_slot_info();
+            return;
+        }
+
+        if let Some(ref mut replay) = self.replay {
+            replay.write(&ReplayRecord::Leave {
+                reason: LEAVE_REMOTE,
+                pid: player.pid,
+                result: reason
+            });
+        }
+
+        let lag_time = self.game.as_mut().and_then(|x| x.remove(player.pid));
+        if let Some(lag_time) = lag_time {
+            self.broadcast(W3GSPacket::StopLag(LagPlayer {
+                pid: player.pid,
+                lag_time
+            }));
+        }
+
+        if self.players().is_empty() {
+            self.end();
+        }
+    }
+
+    fn chat(&mut self, pid: u8, chat: Chat) {
+        if chat.from_pid != pid {
+            return;
+        }
+
+        // rejected requests are dropped silently, the slot info goes out either way since the
+        // client already shows the change it asked for and has to be put back
+        let _ = match chat.command {
+            ChatCommand::Message(ref message) if self.phase == Phase::Lobby || self.phase == Phase::Countdown => {
+                self.relay_chat(chat.clone());
+                if self.config.replays.is_some() {
+                    if self.lobby_chat.len() >= MAX_LOBBY_CHAT {
+                        self.lobby_chat.remove(0);
+                    }
+                    self.lobby_chat.push((pid, message.clone()));
+                }
+                if message.as_bytes().starts_with(b"!") {
+                    self.forward_command(pid, message.decode(w3gs::ENCODING).into_owned());
+                }
+                return;
+            }
+            ChatCommand::MessageExtra(mode, ref message) if self.phase == Phase::Playing => {
+                self.relay_chat(chat.clone());
+                if let Some(ref mut replay) = self.replay {
+                    replay.write(&ReplayRecord::Chat {
+                        pid,
+                        mode: Some(mode),
+                        message: message.clone()
+                    });
+                }
+                if message.as_bytes().starts_with(b"!") {
+                    self.game_command(pid, &message.decode(w3gs::ENCODING));
+                }
+                return;
+            }
+            // lobby chat has no business in the game and the other way around
+            ChatCommand::Message(_) | ChatCommand::MessageExtra(..) => return,
+            _ if self.phase != Phase::Lobby => return,
+            ChatCommand::TeamChange(team) => self.slots.request_team(pid, team),
+            ChatCommand::ColorChange(color) => self.slots.request_color(pid, color),
+            ChatCommand::RaceChange(race) => self.slots.request_race(pid, race),
+            ChatCommand::HandicapChange(handicap) => self.slots.request_handicap(pid, handicap)
+        };
+
+        self.send_slot_info();
+    }
+
+    fn relay_chat(&self, chat: Chat) {
+        for &to_pid in chat.to_pids.iter().filter(|&&x| x != HOST_PID) {
+            self.send_to(to_pid, W3GSPacket::ChatFromHost(chat.clone()));
+        }
+    }
+
+    pub fn is_admin(&self, pid: u8) -> bool {
+        self.players().iter()
+            .find(|x| x.pid == pid)
+            .map_or(false, |player| self.config.admins.iter().any(|x| x.as_bytes().eq_ignore_ascii_case(player.name.as_bytes())))
+    }
+
+    fn map_size(&mut self, pid: u8, map_size: MapSize) {
+        let has_map = map_size.size_flag == MapSizeFlag::Check as u8 && map_size.map_size == self.config.map_check.map_size;
+
+        if has_map {
+            if let Some(ref mut downloads) = self.downloads {
+                downloads.stop(pid);
+            }
+            self.slots.set_download_status(pid, 100);
+            self.send_slot_info();
+            return;
+        }
+
+        // the client keeps telling us how much it has while downloading
+        if map_size.size_flag == MapSizeFlag::Downloading as u8 {
+            return self.map_progress(pid, map_size.map_size);
+        }
+
+        let admin = self.is_admin(pid);
+        let allowed = match self.downloads {
+            Some(ref downloads) => downloads.is_allowed(admin),
+            None => false
+        };
+
+        if !allowed {
+            self.send_chat(pid, message("You don't have the map and it can't be downloaded here."));
+            if let Some(connection) = self.connection_of(pid) {
+                self.remove(connection, LeaveReason::Lobby as u32);
+            }
+            return;
+        }
+
+        let downloads = self.downloads.as_mut().unwrap();
+        if !downloads.is_downloading(pid) {
+            downloads.start(pid);
+            self.send_to(pid, W3GSPacket::StartDownload(StartDownload {
+                from_pid: HOST_PID
+            }));
+        }
+    }
+
+    fn map_part_ok(&mut self, pid: u8, ok: MapPartOk) {
+        if ok.from_pid == pid && ok.to_pid == HOST_PID {
+            self.map_progress(pid, ok.position);
+        }
+    }
+
+    fn map_progress(&mut self, pid: u8, position: u32) {
+        let percent = match self.downloads {
+            Some(ref mut downloads) => downloads.ack(pid, position),
+            None => None
+        };
+
+        if let Some(percent) = percent {
+            self.slots.set_download_status(pid, percent);
+            self.send_slot_info();
+        }
+    }
+
+    // sends whatever map parts are due, called at a fixed interval
+    pub fn send_map_parts(&mut self, elapsed: Duration) {
+        let parts = match self.downloads {
+            Some(ref mut downloads) => downloads.poll(elapsed),
+            None => return
+        };
+
+        for part in parts {
+            self.send_to(part.to_pid, W3GSPacket::MapPart(part));
+        }
+    }
+
+    fn pong(&mut self, pid: u8, value: u32) {
+        let ping = self.ticks().wrapping_sub(value);
+
+        if let Some(connection) = self.connection_of(pid) {
+            if let Some(ref mut player) = self.connections.get_mut(&connection).unwrap().player {
+                player.ping = Some(ping);
+            }
+        }
+    }
+
+    // everyone is in and has the map
+    fn is_ready(&self) -> bool {
+        self.slots.is_full() && self.slots.players() > 0 && self.slots.slots.iter()
+            .filter(|x| x.is_player())
+            .all(|x| x.download_status == 100)
+    }
+
+    // starts the countdown, the game itself begins once it's over
+    pub fn start(&mut self) {
+        if self.phase != Phase::Lobby || self.players().is_empty() {
+            return;
+        }
+
+        // the fake host player has no place in the game
+        self.broadcast(W3GSPacket::PlayerLeaveOthers(PlayerLeave {
+            pid: HOST_PID,
+            reason: LeaveReason::Lobby as u32
+        }));
+        self.broadcast(W3GSPacket::CountdownStart);
+
+        self.phase = Phase::Countdown;
+        self.countdown_started = Some(Instant::now());
+        self.downloads = None;
+    }
+
+    // closes every connection, whatever phase we're in
+    pub fn end(&mut self) {
+        self.phase = Phase::Ended;
+        self.game = None;
+        self.connections.clear();
+
+        if let (Some(mut replay), Some(config)) = (self.replay.take(), self.config.replays.as_ref()) {
+            // the replay is saved from the host's point of view, so it's the host leaving last
+            replay.write(&ReplayRecord::Leave {
+                reason: LEAVE_LOCAL,
+                pid: HOST_PID,
+                result: LeaveReason::Lost as u32
+            });
+
+            // the last block and the file are left, the other games shouldn't wait on the disk for them
+            let game_name = self.config.game_name.decode(w3gs::ENCODING).into_owned();
+            let (config, host_counter) = (config.clone(), self.config.host_counter);
+            thread::spawn(move || {
+                if let Err(e) = config.save(&game_name, replay) {
+                    warn!("game {}: failed to save replay: {}", host_counter, e);
+                }
+            });
+        }
+    }
+
+    // everything up to the first time slot is known once loading starts
+    fn start_replay(&mut self) {
+        let config = match self.config.replays {
+            Some(ref config) => config,
+            None => return
+        };
+
+        let game = ReplayGame {
+            host: ReplayPlayer::custom(HOST_PID, self.config.host_name.clone()),
+            game_name: self.config.game_name.clone(),
+            stat: self.config.game_stat.clone(),
+            game_type: GAME_TYPE_CUSTOM,
+            language: 0,
+            players: self.players().iter().map(|x| ReplayPlayer::custom(x.pid, x.name.clone())).collect(),
+            slots: self.slots.clone()
+        };
+
+        let mut replay = ReplayWriter::new(PRODUCT_TFT, config.version, config.build, &game);
+        for record in ReplayRecord::start_records() {
+            replay.write(&record);
+        }
+
+        for (pid, message) in self.lobby_chat.drain(..) {
+            replay.write(&ReplayRecord::Chat {
+                pid,
+                mode: None,
+                message
+            });
+        }
+        self.replay = Some(replay);
+    }
+
+    fn game_loaded(&mut self, pid: u8) {
+        let loaded = match self.game {
+            Some(ref mut game) => game.loaded(pid),
+            None => false
+        };
+
+        if loaded {
+            self.broadcast(W3GSPacket::GameLoadedOthers(pid));
+        }
+    }
+
+    fn action(&mut self, pid: u8, action: OutgoingAction) {
+        if let Some(ref mut game) = self.game {
+            game.queue_action(pid, action.data);
+        }
+    }
+
+    // in-game commands, anyone may look at the settings but only admins change them
+    fn game_command(&mut self, pid: u8, text: &str) {
+        let mut words = text.split_whitespace();
+        let command = words.next().unwrap_or("").to_ascii_lowercase();
+        let argument = words.next();
+        let admin = self.is_admin(pid);
+
+        let (latency, auto_latency, sync_limit) = match self.game {
+            Some(ref game) => (game.latency(), game.is_auto_latency(), game.sync_limit()),
+            None => return
+        };
+
+        match (command.as_str(), argument) {
+            ("!drop", _) => self.drop_request(pid),
+            ("!latency", Some("auto")) if admin => {
+                self.game.as_mut().unwrap().set_auto_latency(true);
+                self.send_all_chat(message("Latency now follows the players' pings."));
+            }
+            ("!latency", Some(argument)) if admin => match argument.parse::<u32>() {
+                Ok(value) => {
+                    let game = self.game.as_mut().unwrap();
+                    game.set_auto_latency(false);
+                    let latency = game.set_latency(value);
+                    self.send_all_chat(message(&format!("Latency set to {} ms.", latency)));
+                }
+                Err(_) => self.send_chat(pid, message("Usage: !latency <ms> or !latency auto"))
+            },
+            ("!latency", _) => {
+                let mode = if auto_latency { " (automatic)" } else { "" };
+                self.send_chat(pid, message(&format!("Latency is {} ms{}.", latency, mode)));
+            }
+            ("!synclimit", Some(argument)) if admin => match argument.parse::<u32>() {
+                Ok(value) if value > 0 => {
+                    self.game.as_mut().unwrap().set_sync_limit(value);
+                    self.send_all_chat(message(&format!("Sync limit set to {} batches.", value)));
+                }
+                _ => self.send_chat(pid, message("Usage: !synclimit <batches>"))
+            },
+            ("!synclimit", _) => self.send_chat(pid, message(&format!("Sync limit is {} batches.", sync_limit))),
+            _ => self.forward_command(pid, text.to_string())
+        }
+    }
+
+    fn forward_command(&mut self, pid: u8, text: String) {
+        if self.forward_commands {
+            self.commands.push((pid, text));
+        }
+    }
+
+    // from now on commands the lobby can't answer are kept for take_commands
+    pub fn forward_commands(&mut self) {
+        self.forward_commands = true;
+    }
+
+    // the commands players sent since the last call, along with who sent them
+    pub fn take_commands(&mut self) -> Vec<(u8, String)> {
+        self.commands.drain(..).collect()
+    }
+
+    fn drop_request(&mut self, pid: u8) {
+        let drop = match self.game {
+            Some(ref mut game) => game.vote_drop(pid),
+            None => false
+        };
+
+        if drop {
+            self.drop_laggers("dropped by vote");
+        }
+    }
+
+    // kicks everyone we're waiting for so the game can go on without them
+    fn drop_laggers(&mut self, reason: &str) {
+        let laggers = self.game.as_ref().map_or(Vec::new(), |x| x.laggers());
+
+        for pid in laggers {
+            self.send_all_chat(message(&format!("{} was {}.", self.player_name(pid), reason)));
+
+            if let Some(connection) = self.connection_of(pid) {
+                self.remove(connection, LeaveReason::Disconnect as u32);
+            }
+        }
+    }
+
+    fn keepalive(&mut self, pid: u8, keepalive: OutgoingKeepalive) {
+        let desyncs = match self.game {
+            Some(ref mut game) => {
+                game.keepalive(pid, keepalive.checksum);
+                game.check_desync()
+            }
+            None => return
+        };
+
+        for desync in desyncs {
+            self.desync(desync);
+        }
+    }
+
+    pub fn find_player(&self, name: &str) -> Option<u8> {
+        self.players().iter()
+            .find(|x| x.name.as_bytes().eq_ignore_ascii_case(name.as_bytes()))
+            .map(|x| x.pid)
+    }
+
+    // false if there's nobody with that pid
+    pub fn kick(&mut self, pid: u8) -> bool {
+        let reason = match self.phase {
+            Phase::Lobby => LeaveReason::Lobby,
+            _ => LeaveReason::Disconnect
+        };
+
+        match self.connection_of(pid) {
+            Some(connection) => {
+                self.remove(connection, reason as u32);
+                true
+            }
+            None => false
+        }
+    }
+
+    pub fn player_name(&self, pid: u8) -> String {
+        match self.players().iter().find(|x| x.pid == pid) {
+            Some(player) => player.name.decode(w3gs::ENCODING).into_owned(),
+            None => format!("#{}", pid)
+        }
+    }
+
+    fn desync(&mut self, desync: Desync) {
+        let groups: Vec<String> = desync.groups.iter()
+            .map(|x| x.iter().map(|&pid| self.player_name(pid)).collect::<Vec<_>>().join(", "))
+            .collect();
+        let description = format!("[{}]", groups.join("] vs ["));
+
+        warn!("game {}: desync at tick {}: {}", self.config.host_counter, desync.tick, description);
+        self.send_all_chat(message(&format!("Desync detected: {}", description)));
+
+        // only with a clear majority, otherwise there's no telling who's right
+        let majority = desync.groups[0].len() > desync.groups[1].len();
+        if !self.config.game.kick_desynced || !majority {
+            return;
+        }
+
+        for &pid in desync.groups[1..].iter().flat_map(|x| x) {
+            self.send_all_chat(message(&format!("{} was kicked for desyncing.", self.player_name(pid))));
+            if let Some(connection) = self.connection_of(pid) {
+                self.remove(connection, LeaveReason::Disconnect as u32);
+            }
+        }
+    }
+
+    // drives everything that happens on a timer, returns how long until it should be called
+    // again or None once the lobby is over
+    pub fn update(&mut self) -> Option<Duration> {
+        let interval = Duration::from_millis(UPDATE_INTERVAL);
+
+        match self.phase {
+            Phase::Lobby => {
+                if self.is_ready() {
+                    self.start();
+                }
+                Some(interval)
+            }
+            Phase::Countdown => {
+                let done = self.countdown_started.map_or(true, |x| x.elapsed() >= Duration::from_secs(COUNTDOWN_DURATION));
+                if done {
+                    self.broadcast(W3GSPacket::CountdownEnd);
+
+                    let pids: Vec<_> = self.players().iter().map(|x| x.pid).collect();
+                    self.game = Some(Game::new(&pids, self.config.game));
+                    self.phase = Phase::Loading;
+                    self.start_replay();
+                }
+                Some(interval)
+            }
+            Phase::Loading => {
+                if !self.game.as_ref().map_or(false, |x| x.is_loading()) {
+                    self.phase = Phase::Playing;
+                    return Some(Duration::from_millis(0));
+                }
+                Some(interval)
+            }
+            Phase::Playing => {
+                let (lag_packets, auto_drop) = match self.game {
+                    Some(ref mut game) => (game.update_lag(), game.should_auto_drop()),
+                    None => return None
+                };
+                for packet in lag_packets {
+                    self.broadcast(packet);
+                }
+
+                if auto_drop {
+                    self.drop_laggers("dropped after lagging for too long");
+                }
+
+                let max_ping = self.players().iter().filter_map(|x| x.ping).max();
+                if let Some(ref mut game) = self.game {
+                    game.adjust_latency(max_ping);
+                }
+
+                let packets = self.game.as_mut().and_then(|x| x.take_actions());
+                for packet in packets.into_iter().flat_map(|x| x) {
+                    if let Some(ref mut replay) = self.replay {
+                        match packet {
+                            W3GSPacket::IncomingAction(ref x) => replay.write(&ReplayRecord::TimeSlot {
+                                interval: x.send_interval,
+                                actions: x.actions.clone()
+                            }),
+                            W3GSPacket::IncomingAction2(ref x) => replay.write(&ReplayRecord::TimeSlot2 {
+                                interval: x.send_interval,
+                                actions: x.actions.clone()
+                            }),
+                            _ => {}
+                        }
+                    }
+                    self.broadcast(packet);
+                }
+
+                // nothing to send while we wait for someone, only check on them now and then
+                match self.game {
+                    Some(ref game) if game.is_lagging() => Some(interval),
+                    Some(ref game) => Some(game.until_next_action()),
+                    None => None
+                }
+            }
+            Phase::Ended => None
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use futures::{Future, Stream};
+    use futures::sync::mpsc::{self, UnboundedReceiver};
+    use jekuthiel::packets::statstring::GameFlags;
+
+    fn string(text: &str) -> BnetString {
+        BnetString::encode(text, w3gs::ENCODING).unwrap()
+    }
+
+    pub fn config() -> LobbyConfig {
+        let map_path = Bytes::from(&b"Maps\\Download\\test.w3x"[..]);
+
+        LobbyConfig {
+            game_name: string("test game"),
+            game_stat: GameStat {
+                flags: GameFlags::default(),
+                map_width: 116,
+                map_height: 84,
+                map_crc: 0x1122_3344,
+                map_path: map_path.clone(),
+                host_name: Bytes::from(&b"host"[..]),
+                map_sha1: None
+            },
+            host_name: string("host"),
+            host_counter: 7,
+            entry_key: 0x1234_5678,
+            map_check: MapCheck {
+                map_path,
+                map_size: 1000,
+                map_info: 0x5566_7788,
+                map_crc: 0x1122_3344,
+                map_sha1: None
+            },
+            map_data: None,
+            downloads: DownloadConfig::default(),
+            slots: SlotTable::melee(4),
+            admins: vec![string("admin")],
+            game: GameConfig::default(),
+            replays: None
+        }
+    }
+
+    fn request(name: &str, host_counter: u32, entry_key: u32) -> ReqJoin {
+        ReqJoin {
+            host_counter,
+            entry_key,
+            unknown: 0,
+            listen_port: 6112,
+            peer_key: 0,
+            name: string(name),
+            unknown2: 0,
+            internal_address: SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 2), 6112)
+        }
+    }
+
+    fn connect(lobby: &mut Lobby) -> (ConnectionID, UnboundedReceiver<W3GSPacket>) {
+        let (sender, receiver) = mpsc::unbounded();
+        let connection = lobby.connect(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 50000), sender);
+        (connection, receiver)
+    }
+
+    // a player with the right host counter and entry key, the receiver has to be kept for them to stay
+    pub fn join(lobby: &mut Lobby, name: &str) -> UnboundedReceiver<W3GSPacket> {
+        let (connection, receiver) = connect(lobby);
+        let (host_counter, entry_key) = (lobby.config().host_counter, lobby.config().entry_key);
+        lobby.receive(connection, W3GSPacket::ReqJoin(request(name, host_counter, entry_key)));
+        receiver
+    }
+
+    // as if they told us they have the map
+    pub fn got_map(lobby: &mut Lobby, pid: u8) {
+        lobby.slots.set_download_status(pid, 100);
+    }
+
+    // everything a connection got, only for connections the lobby already dropped
+    fn sent(receiver: UnboundedReceiver<W3GSPacket>) -> Vec<W3GSPacket> {
+        receiver.collect().wait().unwrap()
+    }
+
+    #[test]
+    fn joins() {
+        let mut lobby = Lobby::new(config());
+        let _receiver = join(&mut lobby, "someone");
+
+        assert_eq!(lobby.players().len(), 1);
+        assert_eq!(lobby.find_player("SOMEONE"), Some(HOST_PID + 1));
+        assert_eq!(lobby.slots().players(), 1);
+    }
+
+    #[test]
+    fn rejects_other_host_counters() {
+        let mut lobby = Lobby::new(config());
+        let (connection, receiver) = connect(&mut lobby);
+        lobby.receive(connection, W3GSPacket::ReqJoin(request("someone", 6, 0x1234_5678)));
+
+        assert!(!lobby.is_connected(connection));
+        assert!(lobby.players().is_empty());
+        assert_eq!(sent(receiver), vec![W3GSPacket::RejectJoin(RejectReason::Started as u32)]);
+    }
+
+    #[test]
+    fn rejects_wrong_entry_keys_on_lan() {
+        let mut lobby = Lobby::new(config());
+        lobby.set_listed_on_lan();
+        let (connection, receiver) = connect(&mut lobby);
+        lobby.receive(connection, W3GSPacket::ReqJoin(request("someone", 7, 0x1234_5679)));
+
+        assert!(!lobby.is_connected(connection));
+        assert_eq!(sent(receiver), vec![W3GSPacket::RejectJoin(RejectReason::WrongPassword as u32)]);
+
+        let (connection, _receiver) = connect(&mut lobby);
+        lobby.receive(connection, W3GSPacket::ReqJoin(request("someone", 7, 0x1234_5678)));
+        assert_eq!(lobby.players().len(), 1);
+    }
+
+    #[test]
+    fn ignores_entry_keys_on_realms() {
+        let mut lobby = Lobby::new(config());
+        let (connection, _receiver) = connect(&mut lobby);
+        lobby.receive(connection, W3GSPacket::ReqJoin(request("someone", 7, 0)));
+        assert_eq!(lobby.players().len(), 1);
+
+        lobby.set_listed_on_lan();
+        lobby.set_listed_on_realm();
+        let (connection, _receiver) = connect(&mut lobby);
+        lobby.receive(connection, W3GSPacket::ReqJoin(request("someone else", 7, 0)));
+        assert_eq!(lobby.players().len(), 2);
+    }
+
+    // what the host said to a connection, only for connections the lobby already dropped
+    fn chat(receiver: UnboundedReceiver<W3GSPacket>) -> Vec<String> {
+        sent(receiver).into_iter()
+            .filter_map(|x| match x {
+                W3GSPacket::ChatFromHost(Chat { command: ChatCommand::MessageExtra(_, message), .. }) => Some(message.decode(w3gs::ENCODING).into_owned()),
+                _ => None
+            })
+            .collect()
+    }
+
+    // an admin and someone else, past loading
+    fn playing() -> (Lobby, UnboundedReceiver<W3GSPacket>, UnboundedReceiver<W3GSPacket>) {
+        let mut lobby = Lobby::new(config());
+        let admin = join(&mut lobby, "admin");
+        let other = join(&mut lobby, "someone");
+
+        let pids: Vec<_> = lobby.players().iter().map(|x| x.pid).collect();
+        lobby.game = Some(Game::new(&pids, lobby.config.game));
+        lobby.phase = Phase::Playing;
+        (lobby, admin, other)
+    }
+
+    fn settings(lobby: &Lobby) -> (u32, bool, u32) {
+        let game = lobby.game.as_ref().unwrap();
+        (game.latency(), game.is_auto_latency(), game.sync_limit())
+    }
+
+    #[test]
+    fn latency_command() {
+        let (mut lobby, admin, other) = playing();
+        let (admin_pid, other_pid) = (lobby.find_player("admin").unwrap(), lobby.find_player("someone").unwrap());
+        lobby.game.as_mut().unwrap().set_auto_latency(true);
+
+        lobby.game_command(other_pid, "!latency 150");
+        assert_eq!(settings(&lobby), (100, true, 50));
+
+        // setting it by hand turns the automatic latency off
+        lobby.game_command(admin_pid, "!LATENCY 150 ms");
+        assert_eq!(settings(&lobby), (150, false, 50));
+        lobby.game_command(admin_pid, "!latency 5000");
+        assert_eq!(settings(&lobby), (500, false, 50));
+        lobby.game_command(admin_pid, "!latency fast");
+        lobby.game_command(admin_pid, "!latency auto");
+        assert_eq!(settings(&lobby), (500, true, 50));
+        lobby.game_command(other_pid, "!latency");
+
+        lobby.end();
+        assert_eq!(chat(admin), vec![
+            "Latency set to 150 ms.",
+            "Latency set to 500 ms.",
+            "Usage: !latency <ms> or !latency auto",
+            "Latency now follows the players' pings."
+        ]);
+        assert_eq!(chat(other), vec![
+            "Latency is 100 ms (automatic).",
+            "Latency set to 150 ms.",
+            "Latency set to 500 ms.",
+            "Latency now follows the players' pings.",
+            "Latency is 500 ms (automatic)."
+        ]);
+    }
+
+    #[test]
+    fn synclimit_command() {
+        let (mut lobby, admin, other) = playing();
+        let (admin_pid, other_pid) = (lobby.find_player("admin").unwrap(), lobby.find_player("someone").unwrap());
+
+        lobby.game_command(other_pid, "!synclimit 10");
+        lobby.game_command(admin_pid, "!synclimit 0");
+        lobby.game_command(admin_pid, "!synclimit -1");
+        assert_eq!(settings(&lobby), (100, false, 50));
+        lobby.game_command(admin_pid, "!synclimit 10");
+        assert_eq!(settings(&lobby), (100, false, 10));
+
+        lobby.end();
+        assert_eq!(chat(admin), vec![
+            "Usage: !synclimit <batches>",
+            "Usage: !synclimit <batches>",
+            "Sync limit set to 10 batches."
+        ]);
+        assert_eq!(chat(other), vec![
+            "Sync limit is 50 batches.",
+            "Sync limit set to 10 batches."
+        ]);
+    }
+
+    #[test]
+    fn other_commands_are_forwarded() {
+        let (mut lobby, _admin, _other) = playing();
+        let pid = lobby.find_player("someone").unwrap();
+
+        // nobody took them
+        lobby.game_command(pid, "!games");
+        assert!(lobby.take_commands().is_empty());
+
+        lobby.forward_commands();
+        lobby.game_command(pid, "!games now");
+        assert_eq!(lobby.take_commands(), vec![(pid, "!games now".to_string())]);
+    }
+}
@@ -0,0 +1,124 @@
+// accepts WC3 clients on the port we advertise on the realm and hands their packets to the lobby
+#![allow(dead_code)]
+
+pub mod advertiser;
+pub mod autohost;
+pub mod console;
+pub mod counter;
+pub mod download;
+pub mod game;
+pub mod lan;
+pub mod lobby;
+pub mod replay;
+pub mod server;
+
+use futures::{Future, IntoFuture, Sink, Stream};
+use futures::future::{self, Either, Loop};
+use futures::sync::mpsc;
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::{Handle, Interval, Timeout};
+use tokio_io::AsyncRead;
+
+use jekuthiel::w3gs::W3GSCodec;
+
+use std::cell::RefCell;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::rc::Rc;
+use std::time::Duration;
+
+use self::lobby::Lobby;
+
+const PING_INTERVAL: u64 = 5;
+const DOWNLOAD_INTERVAL: u64 = 100;
+
+pub fn listen(handle: &Handle, port: u16, lobby: Rc<RefCell<Lobby>>) -> io::Result<impl Future<Item=(), Error=io::Error>> {
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
+    let listener = TcpListener::bind(&address, handle)?;
+    let timers = run_lobby(handle, lobby.clone())?;
+    let handle = handle.clone();
+
+    let accept = listener.incoming().for_each(move |(socket, address)| {
+        accept(&handle, socket, address, lobby.clone());
+        Ok(())
+    });
+
+    // everything stops once the lobby is over
+    Ok(accept.select(timers).map(|_| ()).map_err(|(e, _)| e))
+}
+
+// everything a lobby does on its own time, until it's over
+fn run_lobby(handle: &Handle, lobby: Rc<RefCell<Lobby>>) -> io::Result<impl Future<Item=(), Error=io::Error>> {
+    let handle = handle.clone();
+
+    let pings = {
+        let lobby = lobby.clone();
+        Interval::new(Duration::from_secs(PING_INTERVAL), &handle)?
+            .for_each(move |_| {
+                lobby.borrow().ping_all();
+                Ok(())
+            })
+    };
+
+    let downloads = {
+        let lobby = lobby.clone();
+        let interval = Duration::from_millis(DOWNLOAD_INTERVAL);
+        Interval::new(interval, &handle)?
+            .for_each(move |_| {
+                lobby.borrow_mut().send_map_parts(interval);
+                Ok(())
+            })
+    };
+    let timers = pings.select(downloads).map(|_| ()).map_err(|(e, _)| e);
+
+    // the lobby decides when it wants to be updated next, the game's action batches need more
+    // precise timing than a fixed interval gives us
+    let updates = {
+        let lobby = lobby.clone();
+        let handle = handle.clone();
+        future::loop_fn((), move |_| {
+            match lobby.borrow_mut().update() {
+                Some(delay) => Either::A(Timeout::new(delay, &handle).into_future().flatten().map(|_| Loop::Continue(()))),
+                None => Either::B(future::ok(Loop::Break(())))
+            }
+        })
+    };
+
+    Ok(timers.select(updates).map(|_| ()).map_err(|(e, _)| e))
+}
+
+fn accept(handle: &Handle, socket: TcpStream, address: SocketAddr, lobby: Rc<RefCell<Lobby>>) {
+    let address = match address {
+        SocketAddr::V4(address) => address,
+        // WC3 has no idea what ipv6 is
+        SocketAddr::V6(_) => SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), address.port())
+    };
+
+    let _ = socket.set_nodelay(true);
+    let (sink, stream) = socket.framed(W3GSCodec::new()).split();
+    let (sender, receiver) = mpsc::unbounded();
+    let connection = lobby.borrow_mut().connect(address, sender);
+
+    // the queue ends once the lobby drops the sender, which closes our half of the socket
+    let receiver = receiver.map_err(|_| io::Error::new(io::ErrorKind::Other, "connection queue failed"));
+    handle.spawn(sink.send_all(receiver).map(|_| ()).map_err(|_| ()));
+
+    let disconnect = lobby.clone();
+    let reader = stream
+        .for_each(move |packet| {
+            let mut lobby = lobby.borrow_mut();
+            lobby.receive(connection, packet);
+
+            if lobby.is_connected(connection) {
+                Ok(())
+            } else {
+                Err(io::Error::new(io::ErrorKind::ConnectionAborted, "dropped by lobby"))
+            }
+        })
+        .then(move |_| {
+            disconnect.borrow_mut().disconnect(connection);
+            Ok(())
+        });
+
+    handle.spawn(reader);
+}
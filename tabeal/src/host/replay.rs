@@ -0,0 +1,59 @@
+// where and how replays of hosted games are saved
+
+use jekuthiel::replay::ReplayWriter;
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug)]
+pub struct ReplayConfig {
+    pub directory: PathBuf,
+    // the game version players are on, e.g. 26 and 6059 for 1.26a
+    pub version: u32,
+    pub build: u16
+}
+
+// days since the epoch to year, month and day, from Howard Hinnant's date algorithms
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// e.g. "2018-03-14 21-05-33", in UTC and without anything a file system might choke on
+fn timestamp() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0) as i64;
+    let (year, month, day) = civil_from_days(seconds / 86_400);
+    let time = seconds % 86_400;
+
+    format!("{:04}-{:02}-{:02} {:02}-{:02}-{:02}", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+impl ReplayConfig {
+    pub fn path(&self, game_name: &str) -> PathBuf {
+        let name: String = game_name.chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c if c.is_control() => '_',
+                c => c
+            })
+            .collect();
+
+        self.directory.join(format!("{} {}.w3g", name.trim(), timestamp()))
+    }
+
+    pub fn save(&self, game_name: &str, replay: ReplayWriter) -> io::Result<PathBuf> {
+        let path = self.path(game_name);
+        File::create(&path)?.write_all(&replay.finish()?)?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,298 @@
+// runs several games side by side: at most one open lobby, which gets the new connections and
+// the realm advertisement, and however many games that already started
+#![allow(dead_code)]
+
+use futures::{Future, Stream};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::{Handle, Interval};
+
+use jekuthiel::packets::BNetIncomingPacket;
+use jekuthiel::w3gs;
+
+use std::cell::RefCell;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::rc::Rc;
+use std::time::Duration;
+
+use realm::RealmConnection;
+
+use super::{accept, run_lobby};
+use super::advertiser::{self, AdvertiseConfig, GameAdvertiser};
+use super::lobby::{message, Lobby, LobbyConfig, Phase};
+
+// how often finished games are cleaned up and commands answered, in milliseconds
+const UPDATE_INTERVAL: u64 = 250;
+
+#[derive(Clone, Copy, Debug)]
+pub struct HostConfig {
+    // games in progress at once, the open lobby not included
+    pub max_games: usize,
+    pub advertise: AdvertiseConfig
+}
+
+impl Default for HostConfig {
+    fn default() -> Self {
+        HostConfig {
+            max_games: 5,
+            advertise: AdvertiseConfig::default()
+        }
+    }
+}
+
+struct OpenLobby {
+    lobby: Rc<RefCell<Lobby>>,
+    // None without a realm to advertise on
+    advertiser: Option<Rc<RefCell<GameAdvertiser>>>
+}
+
+pub struct Host {
+    handle: Handle,
+    config: HostConfig,
+    // shared by every lobby we advertise
+    realm: Option<RealmConnection>,
+    lobby: Option<OpenLobby>,
+    games: Vec<Rc<RefCell<Lobby>>>
+}
+
+impl Host {
+    pub fn new(handle: &Handle, config: HostConfig, realm: Option<RealmConnection>) -> Host {
+        Host {
+            handle: handle.clone(),
+            config,
+            realm,
+            lobby: None,
+            games: Vec::new()
+        }
+    }
+
+    pub fn config(&self) -> &HostConfig {
+        &self.config
+    }
+
+    pub fn lobby(&self) -> Option<Rc<RefCell<Lobby>>> {
+        self.lobby.as_ref().map(|x| x.lobby.clone())
+    }
+
+    pub fn games(&self) -> &[Rc<RefCell<Lobby>>] {
+        &self.games
+    }
+
+    // whether another lobby may be opened right now
+    pub fn can_create(&self) -> bool {
+        self.lobby.is_none() && self.games.len() < self.config.max_games
+    }
+
+    // opens a lobby and advertises it on the realm, if we have one
+    pub fn create(&mut self, config: LobbyConfig) -> io::Result<Rc<RefCell<Lobby>>> {
+        if self.lobby.is_some() {
+            return Err(io::Error::new(io::ErrorKind::Other, "a lobby is already open"));
+        }
+        if self.games.len() >= self.config.max_games {
+            return Err(io::Error::new(io::ErrorKind::Other, "too many games running"));
+        }
+
+        let lobby = Rc::new(RefCell::new(Lobby::new(config)));
+        lobby.borrow_mut().forward_commands();
+        let host_counter = lobby.borrow().config().host_counter;
+
+        let running = run_lobby(&self.handle, lobby.clone())?;
+        self.handle.spawn(running.map_err(move |e| warn!("game {}: {}", host_counter, e)));
+
+        let advertiser = match self.realm {
+            Some(ref realm) => {
+                let advertiser = Rc::new(RefCell::new(GameAdvertiser::new(self.config.advertise, &lobby.borrow(), realm.encoding())));
+                let advertising = advertiser::advertise(&self.handle, realm.sink(), advertiser.clone(), lobby.clone())?;
+                self.handle.spawn(advertising.map(|_| ()).map_err(move |e| warn!("game {}: advertising failed: {}", host_counter, e)));
+                Some(advertiser)
+            }
+            None => None
+        };
+
+        self.lobby = Some(OpenLobby {
+            lobby: lobby.clone(),
+            advertiser
+        });
+        Ok(lobby)
+    }
+
+    // realm packets the lobbies care about, the rest is ignored
+    pub fn receive_realm(&mut self, packet: &BNetIncomingPacket) {
+        if let BNetIncomingPacket::StartAdvEx3(ref result) = *packet {
+            if let Some(OpenLobby { advertiser: Some(ref advertiser), .. }) = self.lobby {
+                advertiser.borrow_mut().receive(result.status);
+            }
+        }
+    }
+
+    // one line per lobby and game
+    pub fn summary(&self) -> Vec<String> {
+        let lobbies = self.lobby.iter().map(|x| &x.lobby).chain(self.games.iter());
+
+        let lines: Vec<String> = lobbies
+            .map(|lobby| {
+                let lobby = lobby.borrow();
+                let state = match lobby.phase() {
+                    Phase::Lobby => format!("lobby, {}/{} players", lobby.slots().players(), lobby.slots().slots.len()),
+                    Phase::Countdown | Phase::Loading => format!("starting, {} players", lobby.players().len()),
+                    Phase::Playing => format!("playing, {} players left", lobby.players().len()),
+                    Phase::Ended => "over".to_string()
+                };
+                format!("#{} {}: {}", lobby.config().host_counter, lobby.config().game_name.decode(w3gs::ENCODING), state)
+            })
+            .collect();
+
+        if lines.is_empty() {
+            vec!["No games running.".to_string()]
+        } else {
+            lines
+        }
+    }
+
+    // moves a lobby that started over to the games, forgets finished games and answers the
+    // commands the lobbies couldn't
+    pub fn update(&mut self) {
+        let phase = self.lobby.as_ref().map(|x| x.lobby.borrow().phase());
+        match phase {
+            Some(Phase::Lobby) | None => {}
+            Some(Phase::Ended) => self.lobby = None,
+            Some(_) => {
+                let started = self.lobby.take().unwrap();
+                self.games.push(started.lobby);
+            }
+        }
+
+        self.games.retain(|x| !x.borrow().is_finished());
+
+        let lobbies: Vec<_> = self.lobby.iter().map(|x| x.lobby.clone()).chain(self.games.iter().cloned()).collect();
+        for lobby in lobbies {
+            let commands = lobby.borrow_mut().take_commands();
+            for (pid, text) in commands {
+                self.command(&lobby, pid, &text);
+            }
+        }
+    }
+
+    fn command(&self, lobby: &Rc<RefCell<Lobby>>, pid: u8, text: &str) {
+        let command = text.split_whitespace().next().unwrap_or("").to_ascii_lowercase();
+
+        if command == "!games" {
+            let lines = self.summary();
+            let lobby = lobby.borrow();
+            for line in lines {
+                lobby.send_chat(pid, message(&line));
+            }
+        }
+    }
+}
+
+// one port for every game, new connections go to whichever lobby is open at the time
+pub fn serve(handle: &Handle, port: u16, host: Rc<RefCell<Host>>) -> io::Result<impl Future<Item=(), Error=io::Error>> {
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
+    let listener = TcpListener::bind(&address, handle)?;
+    let handle = handle.clone();
+
+    let updates = {
+        let host = host.clone();
+        Interval::new(Duration::from_millis(UPDATE_INTERVAL), &handle)?
+            .for_each(move |_| {
+                host.borrow_mut().update();
+                Ok(())
+            })
+    };
+
+    let accept = listener.incoming().for_each(move |(socket, address)| {
+        // with no lobby open there's nothing to join, dropping the socket turns them away
+        if let Some(lobby) = host.borrow().lobby() {
+            accept(&handle, socket, address, lobby);
+        }
+        Ok(())
+    });
+
+    Ok(accept.select(updates).map(|_| ()).map_err(|(e, _)| e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Stream;
+    use tokio_core::reactor::Core;
+
+    use jekuthiel::w3gs::W3GSPacket;
+    use jekuthiel::w3gs::packets::{Chat, ChatCommand};
+
+    use host::lobby::tests::{config, join};
+
+    fn host(core: &Core, max_games: usize) -> Host {
+        Host::new(&core.handle(), HostConfig { max_games, ..HostConfig::default() }, None)
+    }
+
+    #[test]
+    fn started_lobbies_become_games() {
+        let core = Core::new().unwrap();
+        let mut host = host(&core, 1);
+
+        let lobby = host.create(config()).unwrap();
+        assert!(!host.can_create());
+        assert!(host.create(config()).is_err());
+
+        host.update();
+        assert!(host.lobby().is_some());
+
+        let _receiver = join(&mut lobby.borrow_mut(), "someone");
+        lobby.borrow_mut().start();
+        host.update();
+        assert!(host.lobby().is_none());
+        assert_eq!(host.games().len(), 1);
+
+        // the one game we may run is going
+        assert!(!host.can_create());
+        assert!(host.create(config()).is_err());
+
+        lobby.borrow_mut().end();
+        host.update();
+        assert!(host.games().is_empty());
+        assert!(host.can_create());
+        assert!(host.create(config()).is_ok());
+    }
+
+    #[test]
+    fn closed_lobbies_are_forgotten() {
+        let core = Core::new().unwrap();
+        let mut host = host(&core, 1);
+
+        host.create(config()).unwrap().borrow_mut().end();
+        host.update();
+        assert!(host.lobby().is_none());
+        assert!(host.games().is_empty());
+        assert_eq!(host.summary(), vec!["No games running."]);
+    }
+
+    #[test]
+    fn games_command() {
+        let core = Core::new().unwrap();
+        let mut host = host(&core, 2);
+        let lobby = host.create(config()).unwrap();
+        let receiver = join(&mut lobby.borrow_mut(), "someone");
+        assert_eq!(host.summary(), vec!["#7 test game: lobby, 1/4 players"]);
+
+        // theirs is the first connection
+        let pid = lobby.borrow().find_player("someone").unwrap();
+        lobby.borrow_mut().receive(0, W3GSPacket::ChatToHost(Chat {
+            to_pids: vec![1],
+            from_pid: pid,
+            command: ChatCommand::Message(message("!games"))
+        }));
+        host.update();
+        assert!(lobby.borrow_mut().take_commands().is_empty());
+
+        lobby.borrow_mut().end();
+        let answers: Vec<_> = receiver.collect().wait().unwrap().into_iter()
+            .filter_map(|x| match x {
+                W3GSPacket::ChatFromHost(Chat { command: ChatCommand::Message(text), .. }) => Some(text.decode(w3gs::ENCODING).into_owned()),
+                _ => None
+            })
+            .collect();
+        assert_eq!(answers, vec!["#7 test game: lobby, 1/4 players"]);
+    }
+}
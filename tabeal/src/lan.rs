@@ -0,0 +1,103 @@
+// finds games on the local network the way the LAN screen does: broadcast a search and collect
+// the GAMEINFOs that come back
+#![allow(dead_code)]
+
+use futures::{Future, Sink, Stream};
+use tokio_core::net::UdpSocket;
+use tokio_core::reactor::{Handle, Timeout};
+
+use jekuthiel::packets::statstring::GameStat;
+use jekuthiel::packets::string::Encoding;
+use jekuthiel::w3gs::{W3GSPacket, W3GSUdpCodec};
+use jekuthiel::w3gs::packets::{GameInfo, SearchGame, PRODUCT_TFT};
+
+use serde_json;
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+
+use host::lan::LAN_PORT;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ScanConfig {
+    pub broadcast: SocketAddrV4,
+    // the minor version to search for, 26 for 1.26
+    pub version: u32,
+    // how long to wait for answers, in milliseconds
+    pub timeout: u64
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        ScanConfig {
+            broadcast: SocketAddrV4::new(Ipv4Addr::new(255, 255, 255, 255), LAN_PORT),
+            version: 26,
+            timeout: 1000
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LanGame {
+    // where to join, the host's address with the port from its GAMEINFO
+    pub address: SocketAddrV4,
+    pub info: GameInfo,
+    // None if the host sent a statstring we can't decode
+    pub stat: Option<GameStat>
+}
+
+impl LanGame {
+    pub fn players(&self) -> u32 {
+        self.info.slots_total.saturating_sub(self.info.slots_open)
+    }
+
+    pub fn to_json(&self, encoding: Encoding) -> serde_json::Value {
+        json!({
+            "name": self.info.game_name.decode(encoding),
+            "address": self.address.to_string(),
+            "host_counter": self.info.host_counter,
+            "host_name": self.stat.as_ref().map(|x| String::from_utf8_lossy(&x.host_name).into_owned()),
+            "map_path": self.stat.as_ref().map(|x| String::from_utf8_lossy(&x.map_path).into_owned()),
+            "players": self.players(),
+            "slots": self.info.slots_total,
+            "uptime": self.info.uptime
+        })
+    }
+}
+
+// every game that answered within the timeout, once each
+pub fn scan(handle: &Handle, config: ScanConfig) -> io::Result<impl Future<Item=Vec<LanGame>, Error=io::Error>> {
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0);
+    let socket = UdpSocket::bind(&address, handle)?;
+    socket.set_broadcast(true)?;
+
+    let (sink, stream) = socket.framed(W3GSUdpCodec).split();
+    let search = W3GSPacket::SearchGame(SearchGame {
+        product: PRODUCT_TFT,
+        version: config.version
+    });
+
+    let answers = stream.filter_map(|(address, packet)| match (address, packet) {
+        (SocketAddr::V4(address), Some(W3GSPacket::GameInfo(info))) => Some(Some(LanGame {
+            address: SocketAddrV4::new(*address.ip(), info.port),
+            stat: GameStat::decode(&info.stat),
+            info
+        })),
+        _ => None
+    });
+
+    // the timeout shows up as a None in the stream, which is where we stop listening
+    let timeout = Timeout::new(Duration::from_millis(config.timeout), handle)?.into_stream().map(|_| None);
+    let games = answers.select(timeout)
+        .take_while(|x| Ok(x.is_some()))
+        .filter_map(|x| x)
+        .fold(Vec::new(), |mut games: Vec<LanGame>, game| {
+            if !games.iter().any(|x| x.address == game.address && x.info.host_counter == game.info.host_counter) {
+                games.push(game);
+            }
+            Ok::<_, io::Error>(games)
+        });
+
+    Ok(sink.send((SocketAddr::V4(config.broadcast), search)).and_then(|_| games))
+}
@@ -1,7 +1,26 @@
+#![feature(conservative_impl_trait)]
+
 extern crate jekuthiel;
 extern crate tokio_core;
 extern crate tokio_io;
 extern crate futures;
+#[macro_use]
+extern crate serde_json;
+extern crate byteorder;
+extern crate bzip2;
+extern crate flate2;
+extern crate bytes;
+extern crate crc;
+extern crate sha1;
+#[macro_use]
+extern crate log;
+
+pub mod gamelist;
+pub mod host;
+pub mod lan;
+pub mod map;
+pub mod mpq;
+pub mod realm;
 
 use futures::{Future, Stream};
 use tokio_io::AsyncRead;
@@ -0,0 +1,288 @@
+// everything the host needs to know about a map file to advertise it and let players join
+#![allow(dead_code)]
+
+pub mod w3i;
+
+use bytes::Bytes;
+use crc::crc32;
+use sha1::Sha1;
+
+use jekuthiel::w3gs::packets::MapCheck;
+
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek};
+use std::path::Path;
+
+use mpq::Archive;
+
+use self::w3i::W3i;
+
+// mixed into the xoro crc after the two scripts, its bytes go into the sha1 as well
+const MAP_CRC_MAGIC: u32 = 0x03F1_379E;
+
+const COMMON_J: &str = "Scripts\\common.j";
+const BLIZZARD_J: &str = "Scripts\\blizzard.j";
+
+// files that go into the map crc and sha1, in this order
+const MAP_CRC_FILES: [&str; 10] = [
+    "war3map.j",
+    "scripts\\war3map.j",
+    "war3map.w3e",
+    "war3map.wpm",
+    "war3map.doo",
+    "war3map.w3u",
+    "war3map.w3b",
+    "war3map.w3d",
+    "war3map.w3a",
+    "war3map.w3q"
+];
+
+#[derive(Clone, Debug)]
+pub struct MapInfo {
+    pub size: u32,
+    // crc32 of the whole file
+    pub info: u32,
+    // the "xoro" crc
+    pub crc: u32,
+    pub sha1: [u8; 20],
+    pub w3i: W3i,
+    // the whole file, for players who have to download it
+    pub data: Bytes
+}
+
+fn read_all<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+// xors the data in dword by dword, rotating after each one, trailing bytes go one at a time
+fn xor_rotate_left(data: &[u8]) -> u32 {
+    let mut value: u32 = 0;
+
+    for chunk in data.chunks(4) {
+        if chunk.len() < 4 {
+            for &x in chunk {
+                value = (value ^ x as u32).rotate_left(3);
+            }
+            continue;
+        }
+
+        let dword = (chunk[0] as u32) | (chunk[1] as u32) << 8 | (chunk[2] as u32) << 16 | (chunk[3] as u32) << 24;
+        value = (value ^ dword).rotate_left(3);
+    }
+
+    value
+}
+
+// maps may ship their own common.j or blizzard.j, the game uses those instead of its own
+fn read_script<R: Read + Seek>(archive: &mut Archive<R>, name: &str, default: &[u8]) -> io::Result<Vec<u8>> {
+    match archive.read_file(name) {
+        Ok(file) => Ok(file),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(default.to_vec()),
+        Err(e) => Err(e)
+    }
+}
+
+impl MapInfo {
+    // common.j and blizzard.j have to be the ones from the patch the players are on
+    pub fn load<P: AsRef<Path>, C: AsRef<Path>, B: AsRef<Path>>(path: P, common_j: C, blizzard_j: B) -> io::Result<MapInfo> {
+        MapInfo::from_data(read_all(path)?, &read_all(common_j)?, &read_all(blizzard_j)?)
+    }
+
+    pub fn from_data(data: Vec<u8>, common_j: &[u8], blizzard_j: &[u8]) -> io::Result<MapInfo> {
+        let mut archive = Archive::new(Cursor::new(&data[..]))?;
+        let common_j = read_script(&mut archive, COMMON_J, common_j)?;
+        let blizzard_j = read_script(&mut archive, BLIZZARD_J, blizzard_j)?;
+
+        let mut crc = xor_rotate_left(&common_j) ^ xor_rotate_left(&blizzard_j);
+        crc = crc.rotate_left(3);
+        crc = (crc ^ MAP_CRC_MAGIC).rotate_left(3);
+
+        let mut sha1 = Sha1::new();
+        sha1.update(&common_j);
+        sha1.update(&blizzard_j);
+        sha1.update(&[0x9E, 0x37, 0xF1, 0x03]);
+
+        let mut found_script = false;
+        for &name in MAP_CRC_FILES.iter() {
+            // some maps have both scripts, the game only uses the first one
+            if found_script && name == "scripts\\war3map.j" {
+                continue;
+            }
+
+            let file = match archive.read_file(name) {
+                Ok(file) => file,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e)
+            };
+
+            if name.ends_with("war3map.j") {
+                found_script = true;
+            }
+
+            crc = (crc ^ xor_rotate_left(&file)).rotate_left(3);
+            sha1.update(&file);
+        }
+
+        if !found_script {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "map has no war3map.j"));
+        }
+
+        Ok(MapInfo {
+            size: data.len() as u32,
+            info: crc32::checksum_ieee(&data),
+            crc,
+            sha1: sha1.digest().bytes(),
+            w3i: W3i::load(&mut archive)?,
+            data: Bytes::from(data)
+        })
+    }
+
+    // map_path is where the game finds the map, e.g. Maps\Download\name.w3x
+    pub fn map_check(&self, map_path: Bytes) -> MapCheck {
+        MapCheck {
+            map_path,
+            map_size: self.size,
+            map_info: self.info,
+            map_crc: self.crc,
+            map_sha1: Some(self.sha1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpq::tests::build_plain;
+
+    use std::collections::HashMap;
+    use std::env;
+
+    const COMMON: &[u8] = b"native DoNothing takes nothing returns nothing\r\n";
+    const BLIZZARD: &[u8] = b"function DoNothingElse takes nothing returns nothing\r\nendfunction\r\n";
+    const SCRIPT: &[u8] = b"function main takes nothing returns nothing\r\nendfunction\r\n";
+    // worked out on their own from the files above and the archive build_plain makes of them
+    const MAP_SIZE: u32 = 1020;
+    const MAP_INFO: u32 = 0x771B_25EE;
+    const MAP_CRC: u32 = 0x0CD7_D8DF;
+    const MAP_SHA1: [u8; 20] = [
+        0x02, 0x89, 0x9e, 0xdf, 0xf8, 0xe1, 0xd0, 0xc8, 0x7c, 0x62,
+        0x71, 0xae, 0xdb, 0x01, 0x8c, 0x1f, 0x33, 0x9a, 0x49, 0x77
+    ];
+    const TERRAIN: &[u8] = b"W3E!\x0b\x00\x00\x00L";
+
+    // a ROC w3i without any players
+    fn w3i() -> Vec<u8> {
+        let mut data = vec![18, 0, 0, 0, 1, 0, 0, 0, 0x10, 0x27, 0, 0];
+        data.extend_from_slice(b"Test\0Me\0\0\0");
+        data.extend(vec![0; 48]);
+        data.extend_from_slice(&[64, 0, 0, 0, 64, 0, 0, 0, 4, 0, 0, 0, b'L']);
+        for _ in 0..2 {
+            data.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0]);
+        }
+        data.extend(vec![0; 8]);
+        data
+    }
+
+    #[test]
+    fn xor_rotate_left_known_values() {
+        assert_eq!(xor_rotate_left(&[]), 0);
+        assert_eq!(xor_rotate_left(&[1, 0, 0, 0]), 8);
+        assert_eq!(xor_rotate_left(&[1, 0, 0, 0, 1, 0, 0, 0]), 0x48);
+        assert_eq!(xor_rotate_left(&[0, 0, 0, 0x80]), 4);
+        // trailing bytes go in one at a time
+        assert_eq!(xor_rotate_left(&[1, 0, 0, 0, 1, 1]), 0x248);
+    }
+
+    #[test]
+    fn known_map() {
+        let w3i = w3i();
+        let data = build_plain(&[("war3map.j", SCRIPT), ("war3map.w3e", TERRAIN), ("war3map.w3i", &w3i)]);
+        let map = MapInfo::from_data(data, COMMON, BLIZZARD).unwrap();
+
+        assert_eq!(map.size, MAP_SIZE);
+        assert_eq!(map.info, MAP_INFO);
+        assert_eq!(map.crc, MAP_CRC);
+        assert_eq!(map.sha1, MAP_SHA1);
+        assert_eq!(map.w3i.name, "Test");
+        assert_eq!(map.data.len() as u32, map.size);
+
+        let check = map.map_check(Bytes::from_static(b"Maps\\Download\\test.w3x"));
+        assert_eq!((check.map_size, check.map_info, check.map_crc, check.map_sha1), (MAP_SIZE, MAP_INFO, MAP_CRC, Some(MAP_SHA1)));
+    }
+
+    #[test]
+    fn only_the_first_script_counts() {
+        let w3i = w3i();
+        let both = build_plain(&[("war3map.j", SCRIPT), ("Scripts\\war3map.j", b"ignored"), ("war3map.w3e", TERRAIN), ("war3map.w3i", &w3i)]);
+        let map = MapInfo::from_data(both, COMMON, BLIZZARD).unwrap();
+        assert_eq!((map.crc, map.sha1), (MAP_CRC, MAP_SHA1));
+
+        let nested = build_plain(&[("Scripts\\war3map.j", SCRIPT), ("war3map.w3e", TERRAIN), ("war3map.w3i", &w3i)]);
+        let map = MapInfo::from_data(nested, COMMON, BLIZZARD).unwrap();
+        assert_eq!((map.crc, map.sha1), (MAP_CRC, MAP_SHA1));
+
+        let none = build_plain(&[("war3map.w3e", TERRAIN), ("war3map.w3i", &w3i)]);
+        assert!(MapInfo::from_data(none, COMMON, BLIZZARD).is_err());
+    }
+
+    #[test]
+    fn scripts_in_the_map_win() {
+        let w3i = w3i();
+        let data = build_plain(&[
+            ("war3map.j", SCRIPT),
+            ("war3map.w3e", TERRAIN),
+            ("war3map.w3i", &w3i),
+            ("Scripts\\common.j", COMMON),
+            ("Scripts\\blizzard.j", BLIZZARD)
+        ]);
+        let map = MapInfo::from_data(data, b"patch common.j", b"patch blizzard.j").unwrap();
+
+        assert_eq!((map.crc, map.sha1), (MAP_CRC, MAP_SHA1));
+    }
+
+    // GHost++ map configs give every value as its bytes in decimal, little endian
+    fn mapcfg(text: &str) -> HashMap<String, String> {
+        text.lines()
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty() && !x.starts_with('#'))
+            .filter_map(|x| x.find('=').map(|index| (x[..index].trim().to_string(), x[index + 1..].trim().to_string())))
+            .collect()
+    }
+
+    fn mapcfg_bytes(value: &str) -> Vec<u8> {
+        value.split_whitespace().map(|x| x.parse().unwrap()).collect()
+    }
+
+    fn mapcfg_u32(value: &str) -> u32 {
+        mapcfg_bytes(value).iter().rev().fold(0, |acc, &x| acc << 8 | x as u32)
+    }
+
+    // checks a real map against what GHost++ worked out for it. none of the files can be shipped
+    // here, so TABEAL_MAPCFG has to point at a GHost++ map config with the map it names in
+    // map_localpath, and the patch's common.j and blizzard.j, next to it
+    #[test]
+    #[ignore]
+    fn ghost_mapcfg() {
+        let path = env::var("TABEAL_MAPCFG").expect("TABEAL_MAPCFG isn't set");
+        let path = Path::new(&path);
+        let dir = path.parent().unwrap();
+        let config = mapcfg(&String::from_utf8_lossy(&read_all(path).unwrap()));
+
+        let map = MapInfo::load(dir.join(&config["map_localpath"]), dir.join("common.j"), dir.join("blizzard.j")).unwrap();
+
+        assert_eq!(map.size, mapcfg_u32(&config["map_size"]));
+        assert_eq!(map.info, mapcfg_u32(&config["map_info"]));
+        assert_eq!(map.crc, mapcfg_u32(&config["map_crc"]));
+        assert_eq!(&map.sha1[..], &mapcfg_bytes(&config["map_sha1"])[..]);
+    }
+
+    #[test]
+    fn mapcfg_values() {
+        let config = mapcfg("# comment\nmap_size = 236 3 0 0\nmap_crc=1 2 3 4\n\nmap_localpath = (2)Test.w3m\n");
+        assert_eq!(mapcfg_u32(&config["map_size"]), 1004);
+        assert_eq!(mapcfg_u32(&config["map_crc"]), 0x0403_0201);
+        assert_eq!(config["map_localpath"], "(2)Test.w3m");
+    }
+}
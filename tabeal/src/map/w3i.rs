@@ -0,0 +1,545 @@
+// war3map.w3i, the map properties set in the editor: players, forces and options
+// reference: https://github.com/stijnherfst/HiveWE/wiki/war3map.w3i-Map-Info
+#![allow(dead_code)]
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use jekuthiel::w3gs::slots::*;
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Cursor, Read, Seek};
+
+use mpq::Archive;
+
+type E = LittleEndian;
+
+pub const W3I_FILE: &str = "war3map.w3i";
+pub const WTS_FILE: &str = "war3map.wts";
+
+// ROC, TFT, 1.31 and 1.32 respectively
+const VERSION_ROC: u32 = 18;
+const VERSION_TFT: u32 = 25;
+const VERSION_131: u32 = 28;
+const VERSION_132: u32 = 31;
+
+pub const MAP_FLAG_HIDE_MINIMAP: u32 = 0x0001;
+pub const MAP_FLAG_MODIFY_ALLY_PRIORITIES: u32 = 0x0002;
+pub const MAP_FLAG_MELEE: u32 = 0x0004;
+pub const MAP_FLAG_FIXED_PLAYER_SETTINGS: u32 = 0x0020;
+pub const MAP_FLAG_CUSTOM_FORCES: u32 = 0x0040;
+pub const MAP_FLAG_CUSTOM_TECHTREE: u32 = 0x0080;
+pub const MAP_FLAG_CUSTOM_ABILITIES: u32 = 0x0100;
+pub const MAP_FLAG_CUSTOM_UPGRADES: u32 = 0x0200;
+
+pub const FORCE_FLAG_ALLIED: u32 = 0x0001;
+pub const FORCE_FLAG_ALLIED_VICTORY: u32 = 0x0002;
+pub const FORCE_FLAG_SHARED_VISION: u32 = 0x0004;
+pub const FORCE_FLAG_SHARED_UNIT_CONTROL: u32 = 0x0010;
+pub const FORCE_FLAG_SHARED_ADVANCED_UNIT_CONTROL: u32 = 0x0020;
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Controller {
+    Human = 1,
+    Computer = 2,
+    Neutral = 3,
+    Rescuable = 4
+}
+
+impl Controller {
+    pub fn from_id(id: u32) -> Option<Self> {
+        match id {
+            1 => Some(Controller::Human),
+            2 => Some(Controller::Computer),
+            3 => Some(Controller::Neutral),
+            4 => Some(Controller::Rescuable),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Player {
+    pub number: u32,
+    pub controller: Controller,
+    // None if the player gets to pick
+    pub race: Option<Race>,
+    pub fixed_start_position: bool,
+    pub name: String,
+    pub start_position: (f32, f32)
+}
+
+#[derive(Clone, Debug)]
+pub struct Force {
+    pub flags: u32,
+    // bit n is set if player number n is in this force
+    pub players: u32,
+    pub name: String
+}
+
+impl Force {
+    pub fn contains(&self, player: u32) -> bool {
+        player < 32 && self.players & (1 << player) != 0
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct W3i {
+    pub version: u32,
+    pub map_version: u32,
+    pub editor_version: u32,
+    pub name: String,
+    pub author: String,
+    pub description: String,
+    pub players_recommended: String,
+    pub playable_width: u32,
+    pub playable_height: u32,
+    pub flags: u32,
+    pub players: Vec<Player>,
+    pub forces: Vec<Force>
+}
+
+// the editor moves most strings out into war3map.wts and leaves TRIGSTR_xxx behind
+fn parse_wts(data: &[u8]) -> HashMap<u32, String> {
+    let text = String::from_utf8_lossy(data);
+    let mut strings = HashMap::new();
+    let mut lines = text.lines();
+
+    while let Some(line) = lines.next() {
+        let mut words = line.trim_left_matches('\u{feff}').split_whitespace();
+        let id = match (words.next(), words.next()) {
+            (Some("STRING"), Some(id)) => id.parse::<u32>().ok(),
+            _ => None
+        };
+
+        if let Some(id) = id {
+            // there may be a comment between the header and the opening brace
+            if lines.by_ref().find(|x| x.trim() == "{").is_none() {
+                break;
+            }
+
+            let body: Vec<&str> = lines.by_ref().take_while(|x| x.trim_right() != "}").collect();
+            strings.insert(id, body.join("\n"));
+        }
+    }
+
+    strings
+}
+
+fn resolve(value: String, strings: &HashMap<u32, String>) -> String {
+    if !value.starts_with("TRIGSTR_") {
+        return value;
+    }
+
+    let digits: String = value["TRIGSTR_".len()..].chars().take_while(|x| x.is_digit(10)).collect();
+    match digits.parse::<u32>().ok().and_then(|x| strings.get(&x)) {
+        Some(resolved) => resolved.clone(),
+        None => value
+    }
+}
+
+fn read_string(buf: &mut Cursor<&[u8]>) -> io::Result<String> {
+    let mut bytes = Vec::new();
+    buf.read_until(0, &mut bytes)?;
+
+    if bytes.pop() != Some(0) {
+        return Err(invalid("unterminated string in w3i"));
+    }
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn skip(buf: &mut Cursor<&[u8]>, length: u64) -> io::Result<()> {
+    let position = buf.position() + length;
+    if position > buf.get_ref().len() as u64 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "w3i ended early"));
+    }
+
+    buf.set_position(position);
+    Ok(())
+}
+
+fn read_race(id: u32) -> Option<Race> {
+    match id {
+        1 => Some(Race::Human),
+        2 => Some(Race::Orc),
+        3 => Some(Race::Undead),
+        4 => Some(Race::NightElf),
+        _ => None
+    }
+}
+
+impl W3i {
+    pub fn load<R: Read + Seek>(archive: &mut Archive<R>) -> io::Result<W3i> {
+        let data = archive.read_file(W3I_FILE)?;
+        let strings = match archive.read_file(WTS_FILE) {
+            Ok(wts) => parse_wts(&wts),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e)
+        };
+
+        W3i::parse(&data, &strings)
+    }
+
+    pub fn parse(data: &[u8], strings: &HashMap<u32, String>) -> io::Result<W3i> {
+        let mut buf = Cursor::new(data);
+
+        let version = buf.read_u32::<E>()?;
+        match version {
+            VERSION_ROC | VERSION_TFT | VERSION_131 | VERSION_132 => {}
+            _ => return Err(invalid("unsupported w3i version"))
+        }
+
+        let map_version = buf.read_u32::<E>()?;
+        let editor_version = buf.read_u32::<E>()?;
+        if version >= VERSION_131 {
+            // game version the map was saved with
+            skip(&mut buf, 4 * 4)?;
+        }
+
+        let name = resolve(read_string(&mut buf)?, strings);
+        let author = resolve(read_string(&mut buf)?, strings);
+        let description = resolve(read_string(&mut buf)?, strings);
+        let players_recommended = resolve(read_string(&mut buf)?, strings);
+
+        // camera bounds and their complements
+        skip(&mut buf, 8 * 4 + 4 * 4)?;
+        let playable_width = buf.read_u32::<E>()?;
+        let playable_height = buf.read_u32::<E>()?;
+        let flags = buf.read_u32::<E>()?;
+        // main ground type
+        skip(&mut buf, 1)?;
+
+        // loading screen and prologue, nothing the host cares about
+        if version == VERSION_ROC {
+            skip(&mut buf, 4)?;
+        } else {
+            skip(&mut buf, 4)?;
+            read_string(&mut buf)?;
+        }
+        for _ in 0..3 {
+            read_string(&mut buf)?;
+        }
+        if version == VERSION_ROC {
+            skip(&mut buf, 4)?;
+        } else {
+            skip(&mut buf, 4)?;
+            read_string(&mut buf)?;
+        }
+        for _ in 0..3 {
+            read_string(&mut buf)?;
+        }
+
+        if version >= VERSION_TFT {
+            // fog, weather, sound environment, light environment and water tint
+            skip(&mut buf, 4 + 4 * 3 + 4 + 4)?;
+            read_string(&mut buf)?;
+            skip(&mut buf, 1 + 4)?;
+        }
+        if version >= VERSION_131 {
+            // script language
+            skip(&mut buf, 4)?;
+        }
+        if version >= VERSION_132 {
+            // supported graphics modes and game data version
+            skip(&mut buf, 4 + 4)?;
+        }
+
+        let player_count = buf.read_u32::<E>()?;
+        let mut players = Vec::new();
+        for _ in 0..player_count {
+            let number = buf.read_u32::<E>()?;
+            let controller = Controller::from_id(buf.read_u32::<E>()?).ok_or_else(|| invalid("invalid player controller in w3i"))?;
+            let race = read_race(buf.read_u32::<E>()?);
+            let fixed_start_position = buf.read_u32::<E>()? != 0;
+            let name = resolve(read_string(&mut buf)?, strings);
+            let start_position = (buf.read_f32::<E>()?, buf.read_f32::<E>()?);
+
+            // ally priorities, and enemy priorities since 1.32
+            skip(&mut buf, if version >= VERSION_132 { 4 * 4 } else { 2 * 4 })?;
+
+            players.push(Player {
+                number,
+                controller,
+                race,
+                fixed_start_position,
+                name,
+                start_position
+            });
+        }
+
+        let force_count = buf.read_u32::<E>()?;
+        let mut forces = Vec::new();
+        for _ in 0..force_count {
+            forces.push(Force {
+                flags: buf.read_u32::<E>()?,
+                players: buf.read_u32::<E>()?,
+                name: resolve(read_string(&mut buf)?, strings)
+            });
+        }
+
+        Ok(W3i {
+            version,
+            map_version,
+            editor_version,
+            name,
+            author,
+            description,
+            players_recommended,
+            playable_width,
+            playable_height,
+            flags,
+            players,
+            forces
+        })
+    }
+
+    pub fn layout_style(&self) -> LayoutStyle {
+        if self.flags & MAP_FLAG_MELEE != 0 {
+            LayoutStyle::Melee
+        } else if self.flags & MAP_FLAG_FIXED_PLAYER_SETTINGS != 0 {
+            LayoutStyle::FixedPlayerSettings
+        } else {
+            LayoutStyle::CustomForces
+        }
+    }
+
+    fn force_of(&self, player: u32) -> u8 {
+        self.forces.iter().position(|x| x.contains(player)).unwrap_or(0) as u8
+    }
+
+    // one slot for every human or computer player, teams come from the forces unless it's melee
+    pub fn slot_table(&self) -> SlotTable {
+        let layout_style = self.layout_style();
+
+        let slots: Vec<Slot> = self.players.iter()
+            .filter(|x| x.controller == Controller::Human || x.controller == Controller::Computer)
+            .take(MAX_SLOTS)
+            .enumerate()
+            .map(|(i, player)| {
+                let team = match layout_style {
+                    LayoutStyle::Melee => i as u8,
+                    _ => self.force_of(player.number)
+                };
+
+                // the map's races only stick if players can't change them anyway
+                let race = match player.race {
+                    Some(race) if layout_style == LayoutStyle::FixedPlayerSettings => race as u8,
+                    _ => Race::Random as u8 | RACE_SELECTABLE
+                };
+
+                let mut slot = Slot::open(team, player.number as u8, race);
+                if player.controller == Controller::Computer {
+                    slot.status = SlotStatus::Occupied;
+                    slot.computer = true;
+                    slot.download_status = 100;
+                }
+                slot
+            })
+            .collect();
+
+        SlotTable {
+            player_slots: slots.len() as u8,
+            slots,
+            random_seed: 0,
+            layout_style,
+            observers: false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::WriteBytesExt;
+
+    fn string(data: &mut Vec<u8>, value: &str) {
+        data.extend_from_slice(value.as_bytes());
+        data.push(0);
+    }
+
+    // a w3i the way the editor of the given version saves it, players are (number, controller, race, name)
+    fn build(version: u32, flags: u32, players: &[(u32, u32, u32, &str)], forces: &[(u32, u32, &str)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.write_u32::<E>(version).unwrap();
+        data.write_u32::<E>(7).unwrap();
+        data.write_u32::<E>(6059).unwrap();
+        if version >= VERSION_131 {
+            data.extend(vec![0; 16]);
+        }
+        for value in ["TRIGSTR_001", "Author", "TRIGSTR_003", "1-4"].iter() {
+            string(&mut data, value);
+        }
+        data.extend(vec![0; 48]);
+        data.write_u32::<E>(96).unwrap();
+        data.write_u32::<E>(128).unwrap();
+        data.write_u32::<E>(flags).unwrap();
+        data.push(b'L');
+
+        for _ in 0..2 {
+            data.write_i32::<E>(-1).unwrap();
+            if version != VERSION_ROC {
+                string(&mut data, "");
+            }
+            for _ in 0..3 {
+                string(&mut data, "");
+            }
+        }
+        if version >= VERSION_TFT {
+            data.extend(vec![0; 24]);
+            string(&mut data, "");
+            data.extend(vec![0; 5]);
+        }
+        if version >= VERSION_131 {
+            data.extend(vec![0; 4]);
+        }
+        if version >= VERSION_132 {
+            data.extend(vec![0; 8]);
+        }
+
+        data.write_u32::<E>(players.len() as u32).unwrap();
+        for &(number, controller, race, name) in players {
+            data.write_u32::<E>(number).unwrap();
+            data.write_u32::<E>(controller).unwrap();
+            data.write_u32::<E>(race).unwrap();
+            data.write_u32::<E>(1).unwrap();
+            string(&mut data, name);
+            data.write_f32::<E>(-512.0).unwrap();
+            data.write_f32::<E>(1024.0).unwrap();
+            data.extend(vec![0; if version >= VERSION_132 { 16 } else { 8 }]);
+        }
+
+        data.write_u32::<E>(forces.len() as u32).unwrap();
+        for &(flags, players, name) in forces {
+            data.write_u32::<E>(flags).unwrap();
+            data.write_u32::<E>(players).unwrap();
+            string(&mut data, name);
+        }
+        data
+    }
+
+    fn strings() -> HashMap<u32, String> {
+        parse_wts(b"\xef\xbb\xbfSTRING 1\r\n{\r\nTest Map\r\n}\r\n\r\nSTRING 3 // comment\r\n{\r\nfirst line\r\nsecond line\r\n}\r\n")
+    }
+
+    const PLAYERS: [(u32, u32, u32, &str); 4] = [
+        (0, 1, 1, "Player 1"),
+        (1, 1, 2, "Player 2"),
+        (2, 2, 3, "Computer"),
+        (3, 3, 0, "Neutral")
+    ];
+    const FORCES: [(u32, u32, &str); 2] = [
+        (FORCE_FLAG_ALLIED | FORCE_FLAG_SHARED_VISION, 0b0001, "Force 1"),
+        (FORCE_FLAG_ALLIED, 0b0110, "TRIGSTR_001")
+    ];
+
+    #[test]
+    fn parses_wts() {
+        let strings = strings();
+        assert_eq!(strings.len(), 2);
+        assert_eq!(strings[&1], "Test Map");
+        assert_eq!(strings[&3], "first line\nsecond line");
+    }
+
+    #[test]
+    fn resolves_strings() {
+        let strings = strings();
+        assert_eq!(resolve("TRIGSTR_001".to_string(), &strings), "Test Map");
+        assert_eq!(resolve("TRIGSTR_3".to_string(), &strings), "first line\nsecond line");
+        assert_eq!(resolve("TRIGSTR_042".to_string(), &strings), "TRIGSTR_042");
+        assert_eq!(resolve("plain".to_string(), &strings), "plain");
+    }
+
+    #[test]
+    fn parses_every_version() {
+        for &version in [VERSION_ROC, VERSION_TFT, VERSION_131, VERSION_132].iter() {
+            let data = build(version, MAP_FLAG_CUSTOM_FORCES, &PLAYERS, &FORCES);
+            let w3i = W3i::parse(&data, &strings()).unwrap();
+
+            assert_eq!((w3i.version, w3i.map_version, w3i.editor_version), (version, 7, 6059));
+            assert_eq!(w3i.name, "Test Map");
+            assert_eq!(w3i.author, "Author");
+            assert_eq!(w3i.description, "first line\nsecond line");
+            assert_eq!(w3i.players_recommended, "1-4");
+            assert_eq!((w3i.playable_width, w3i.playable_height), (96, 128));
+            assert_eq!(w3i.flags, MAP_FLAG_CUSTOM_FORCES);
+
+            assert_eq!(w3i.players.len(), 4);
+            assert_eq!(w3i.players[1].number, 1);
+            assert_eq!(w3i.players[1].controller, Controller::Human);
+            assert_eq!(w3i.players[1].race, Some(Race::Orc));
+            assert_eq!(w3i.players[1].name, "Player 2");
+            assert_eq!(w3i.players[1].start_position, (-512.0, 1024.0));
+            assert!(w3i.players[1].fixed_start_position);
+            assert_eq!(w3i.players[2].controller, Controller::Computer);
+            assert_eq!(w3i.players[3].race, None);
+
+            assert_eq!(w3i.forces.len(), 2);
+            assert_eq!(w3i.forces[0].flags, FORCE_FLAG_ALLIED | FORCE_FLAG_SHARED_VISION);
+            assert_eq!(w3i.forces[1].name, "Test Map");
+            assert!(w3i.forces[1].contains(2));
+            assert!(!w3i.forces[1].contains(0));
+            assert!(!w3i.forces[1].contains(40));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_data() {
+        let data = build(VERSION_TFT, 0, &PLAYERS, &FORCES);
+        for length in 0..data.len() {
+            assert!(W3i::parse(&data[..length], &HashMap::new()).is_err(), "{}", length);
+        }
+
+        let mut unknown_version = data.clone();
+        unknown_version[0] = 26;
+        assert!(W3i::parse(&unknown_version, &HashMap::new()).is_err());
+
+        let bad_controller = build(VERSION_TFT, 0, &[(0, 9, 1, "Player 1")], &[]);
+        assert!(W3i::parse(&bad_controller, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn layout_styles() {
+        let layout_style = |flags| W3i::parse(&build(VERSION_TFT, flags, &[], &[]), &HashMap::new()).unwrap().layout_style();
+        assert_eq!(layout_style(MAP_FLAG_MELEE), LayoutStyle::Melee);
+        assert_eq!(layout_style(MAP_FLAG_CUSTOM_FORCES | MAP_FLAG_FIXED_PLAYER_SETTINGS), LayoutStyle::FixedPlayerSettings);
+        assert_eq!(layout_style(MAP_FLAG_CUSTOM_FORCES), LayoutStyle::CustomForces);
+    }
+
+    #[test]
+    fn custom_forces_slots() {
+        let w3i = W3i::parse(&build(VERSION_TFT, MAP_FLAG_CUSTOM_FORCES, &PLAYERS, &FORCES), &HashMap::new()).unwrap();
+        let table = w3i.slot_table();
+
+        // the neutral player gets no slot
+        assert_eq!(table.player_slots, 3);
+        assert_eq!(table.slots.len(), 3);
+        assert_eq!(table.layout_style, LayoutStyle::CustomForces);
+
+        let teams: Vec<u8> = table.slots.iter().map(|x| x.team).collect();
+        assert_eq!(teams, vec![0, 1, 1]);
+        let colors: Vec<u8> = table.slots.iter().map(|x| x.color).collect();
+        assert_eq!(colors, vec![0, 1, 2]);
+
+        assert_eq!(table.slots[0].status, SlotStatus::Open);
+        assert_eq!(table.slots[0].race, Race::Random as u8 | RACE_SELECTABLE);
+        assert!(table.slots[2].computer);
+        assert_eq!(table.slots[2].status, SlotStatus::Occupied);
+        assert_eq!(table.slots[2].download_status, 100);
+    }
+
+    #[test]
+    fn melee_and_fixed_slots() {
+        let melee = W3i::parse(&build(VERSION_TFT, MAP_FLAG_MELEE, &PLAYERS, &FORCES), &HashMap::new()).unwrap().slot_table();
+        let teams: Vec<u8> = melee.slots.iter().map(|x| x.team).collect();
+        assert_eq!(teams, vec![0, 1, 2]);
+
+        let flags = MAP_FLAG_CUSTOM_FORCES | MAP_FLAG_FIXED_PLAYER_SETTINGS;
+        let fixed = W3i::parse(&build(VERSION_TFT, flags, &PLAYERS, &FORCES), &HashMap::new()).unwrap().slot_table();
+        let races: Vec<u8> = fixed.slots.iter().map(|x| x.race).collect();
+        assert_eq!(races, vec![Race::Human as u8, Race::Orc as u8, Race::Undead as u8]);
+    }
+}
@@ -0,0 +1,161 @@
+// hashing and encryption shared by the hash table, block table and file data
+
+pub const HASH_TABLE_OFFSET: u32 = 0;
+pub const HASH_NAME_A: u32 = 1;
+pub const HASH_NAME_B: u32 = 2;
+pub const HASH_FILE_KEY: u32 = 3;
+
+pub struct CryptTable {
+    table: Vec<u32>
+}
+
+impl CryptTable {
+    pub fn new() -> CryptTable {
+        let mut table = vec![0u32; 0x500];
+        let mut seed: u32 = 0x0010_0001;
+
+        for index1 in 0..0x100 {
+            let mut index2 = index1;
+            for _ in 0..5 {
+                seed = (seed * 125 + 3) % 0x002A_AAAB;
+                let temp1 = (seed & 0xffff) << 0x10;
+                seed = (seed * 125 + 3) % 0x002A_AAAB;
+                let temp2 = seed & 0xffff;
+
+                table[index2] = temp1 | temp2;
+                index2 += 0x100;
+            }
+        }
+
+        CryptTable { table }
+    }
+
+    // file names are case insensitive and use backslashes
+    pub fn hash_string(&self, name: &str, hash_type: u32) -> u32 {
+        let mut seed1: u32 = 0x7FED_7FED;
+        let mut seed2: u32 = 0xEEEE_EEEE;
+
+        for c in name.bytes() {
+            let c = match c {
+                b'/' => b'\\',
+                _ => c.to_ascii_uppercase()
+            } as u32;
+
+            seed1 = self.table[(hash_type * 0x100 + c) as usize] ^ seed1.wrapping_add(seed2);
+            seed2 = c.wrapping_add(seed1).wrapping_add(seed2).wrapping_add(seed2 << 5).wrapping_add(3);
+        }
+
+        seed1
+    }
+
+    // decrypts in place, a trailing partial dword is left as-is
+    pub fn decrypt(&self, data: &mut [u8], mut key: u32) {
+        let mut seed: u32 = 0xEEEE_EEEE;
+
+        for chunk in data.chunks_mut(4) {
+            if chunk.len() < 4 {
+                break;
+            }
+
+            let value = (chunk[0] as u32) | (chunk[1] as u32) << 8 | (chunk[2] as u32) << 16 | (chunk[3] as u32) << 24;
+
+            seed = seed.wrapping_add(self.table[0x400 + (key & 0xff) as usize]);
+            let plain = value ^ key.wrapping_add(seed);
+            key = ((!key << 0x15).wrapping_add(0x1111_1111)) | (key >> 0x0B);
+            seed = plain.wrapping_add(seed).wrapping_add(seed << 5).wrapping_add(3);
+
+            chunk[0] = plain as u8;
+            chunk[1] = (plain >> 8) as u8;
+            chunk[2] = (plain >> 16) as u8;
+            chunk[3] = (plain >> 24) as u8;
+        }
+    }
+
+    // only needed to build archives for the tests, we never write any
+    #[cfg(test)]
+    pub fn encrypt(&self, data: &mut [u8], mut key: u32) {
+        let mut seed: u32 = 0xEEEE_EEEE;
+
+        for chunk in data.chunks_mut(4) {
+            if chunk.len() < 4 {
+                break;
+            }
+
+            let plain = (chunk[0] as u32) | (chunk[1] as u32) << 8 | (chunk[2] as u32) << 16 | (chunk[3] as u32) << 24;
+
+            seed = seed.wrapping_add(self.table[0x400 + (key & 0xff) as usize]);
+            let value = plain ^ key.wrapping_add(seed);
+            key = ((!key << 0x15).wrapping_add(0x1111_1111)) | (key >> 0x0B);
+            seed = plain.wrapping_add(seed).wrapping_add(seed << 5).wrapping_add(3);
+
+            chunk[0] = value as u8;
+            chunk[1] = (value >> 8) as u8;
+            chunk[2] = (value >> 16) as u8;
+            chunk[3] = (value >> 24) as u8;
+        }
+    }
+
+    // the key of a file only depends on its name without the path
+    pub fn file_key(&self, name: &str, offset: u32, size: u32, fix_key: bool) -> u32 {
+        let base_name = name.rsplit(|c| c == '\\' || c == '/').next().unwrap_or(name);
+        let key = self.hash_string(base_name, HASH_FILE_KEY);
+
+        if fix_key {
+            key.wrapping_add(offset) ^ size
+        } else {
+            key
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_known_values() {
+        let crypt = CryptTable::new();
+        assert_eq!(crypt.table[0], 0x55C6_36E2);
+        assert_eq!(crypt.table[1], 0x02BE_0170);
+    }
+
+    #[test]
+    fn table_keys() {
+        let crypt = CryptTable::new();
+        assert_eq!(crypt.hash_string("(hash table)", HASH_FILE_KEY), 0xC3AF_3770);
+        assert_eq!(crypt.hash_string("(block table)", HASH_FILE_KEY), 0xEC83_B3A3);
+    }
+
+    #[test]
+    fn names_are_case_and_slash_insensitive() {
+        let crypt = CryptTable::new();
+        for &hash_type in [HASH_TABLE_OFFSET, HASH_NAME_A, HASH_NAME_B, HASH_FILE_KEY].iter() {
+            assert_eq!(crypt.hash_string("Scripts\\war3map.j", hash_type), crypt.hash_string("scripts/WAR3MAP.J", hash_type));
+        }
+        assert!(crypt.hash_string("war3map.j", HASH_NAME_A) != crypt.hash_string("war3map.j", HASH_NAME_B));
+    }
+
+    #[test]
+    fn decrypt_undoes_encrypt() {
+        let crypt = CryptTable::new();
+        let plain: Vec<u8> = (0..27u8).collect();
+        let mut data = plain.clone();
+
+        crypt.encrypt(&mut data, 0x1234_5678);
+        assert!(data[..24] != plain[..24]);
+        // the trailing partial dword stays as it is
+        assert_eq!(data[24..], plain[24..]);
+
+        crypt.decrypt(&mut data, 0x1234_5678);
+        assert_eq!(data, plain);
+    }
+
+    #[test]
+    fn file_keys() {
+        let crypt = CryptTable::new();
+        let key = crypt.hash_string("war3map.j", HASH_FILE_KEY);
+
+        assert_eq!(crypt.file_key("Scripts\\war3map.j", 0x200, 0x1000, false), key);
+        assert_eq!(crypt.file_key("war3map.j", 0x200, 0x1000, true), key.wrapping_add(0x200) ^ 0x1000);
+    }
+}
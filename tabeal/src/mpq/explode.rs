@@ -0,0 +1,185 @@
+// PKWARE Data Compression Library "implode" decompressor, ported from Mark Adler's blast.c
+
+use std::io;
+
+const MAX_BITS: usize = 13;
+
+// compact code length tables: low nibble is the length, high nibble the repeat count - 1
+const LITERAL_LENGTHS: [u8; 98] = [
+    11, 124, 8, 7, 28, 7, 188, 13, 76, 4, 10, 8, 12, 10, 12, 10, 8, 23, 8,
+    9, 7, 6, 7, 8, 7, 6, 55, 8, 23, 24, 12, 11, 7, 9, 11, 12, 6, 7, 22, 5,
+    7, 24, 6, 11, 9, 6, 7, 22, 7, 11, 38, 7, 9, 8, 25, 11, 8, 11, 9, 12,
+    8, 12, 5, 38, 5, 38, 5, 11, 7, 5, 6, 21, 6, 10, 53, 8, 7, 24, 10, 27,
+    44, 253, 253, 253, 252, 252, 252, 13, 12, 45, 12, 45, 12, 61, 12, 45,
+    44, 173
+];
+const LENGTH_LENGTHS: [u8; 6] = [2, 35, 36, 53, 38, 23];
+const DISTANCE_LENGTHS: [u8; 7] = [2, 20, 53, 230, 247, 151, 248];
+
+const LENGTH_BASE: [u16; 16] = [3, 2, 4, 5, 6, 7, 8, 9, 10, 12, 16, 24, 40, 72, 136, 264];
+const LENGTH_EXTRA: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8];
+
+// length value that marks the end of the stream
+const END_OF_STREAM: u16 = 519;
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+struct Huffman {
+    count: [u16; MAX_BITS + 1],
+    symbol: Vec<u16>
+}
+
+impl Huffman {
+    fn new(compact: &[u8]) -> Huffman {
+        let mut lengths = Vec::new();
+        for &x in compact {
+            for _ in 0..(x >> 4) + 1 {
+                lengths.push((x & 15) as usize);
+            }
+        }
+
+        let mut count = [0u16; MAX_BITS + 1];
+        for &length in &lengths {
+            count[length] += 1;
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + count[length];
+        }
+
+        let mut symbol = vec![0u16; lengths.len()];
+        for (i, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbol[offsets[length] as usize] = i as u16;
+                offsets[length] += 1;
+            }
+        }
+
+        Huffman { count, symbol }
+    }
+}
+
+struct BitReader<'a> {
+    input: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u32
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, need: u32) -> io::Result<u32> {
+        while self.count < need {
+            let byte = *self.input.get(self.position).ok_or_else(|| invalid("implode stream ended early"))?;
+            self.position += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+
+        let value = self.buffer & ((1 << need) - 1);
+        self.buffer >>= need;
+        self.count -= need;
+        Ok(value)
+    }
+
+    // codes are stored bit-inverted
+    fn decode(&mut self, huffman: &Huffman) -> io::Result<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+
+        for length in 1..MAX_BITS + 1 {
+            code |= (self.bits(1)? ^ 1) as i32;
+            let count = huffman.count[length] as i32;
+            if code < first + count {
+                return Ok(huffman.symbol[(index + code - first) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+
+        Err(invalid("invalid implode code"))
+    }
+}
+
+pub fn explode(input: &[u8], expected_size: usize) -> io::Result<Vec<u8>> {
+    let literals = Huffman::new(&LITERAL_LENGTHS);
+    let lengths = Huffman::new(&LENGTH_LENGTHS);
+    let distances = Huffman::new(&DISTANCE_LENGTHS);
+
+    let mut reader = BitReader {
+        input,
+        position: 0,
+        buffer: 0,
+        count: 0
+    };
+    let mut output = Vec::with_capacity(expected_size);
+
+    let coded_literals = reader.bits(8)?;
+    if coded_literals > 1 {
+        return Err(invalid("invalid implode literal mode"));
+    }
+
+    let dictionary_bits = reader.bits(8)?;
+    if dictionary_bits < 4 || dictionary_bits > 6 {
+        return Err(invalid("invalid implode dictionary size"));
+    }
+
+    loop {
+        if reader.bits(1)? != 0 {
+            let symbol = reader.decode(&lengths)? as usize;
+            let length = LENGTH_BASE[symbol] + reader.bits(LENGTH_EXTRA[symbol] as u32)? as u16;
+            if length == END_OF_STREAM {
+                break;
+            }
+
+            let shift = if length == 2 { 2 } else { dictionary_bits };
+            let distance = ((reader.decode(&distances)? as usize) << shift) + reader.bits(shift)? as usize + 1;
+            if distance > output.len() {
+                return Err(invalid("implode distance too far back"));
+            }
+
+            // the copy may overlap what it's producing, so it has to go byte by byte
+            let start = output.len() - distance;
+            for i in 0..length as usize {
+                let byte = output[start + i];
+                output.push(byte);
+            }
+        } else {
+            let literal = if coded_literals != 0 {
+                reader.decode(&literals)? as u8
+            } else {
+                reader.bits(8)? as u8
+            };
+            output.push(literal);
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blast_example() {
+        // the example stream from blast.c
+        let input = [0x00, 0x04, 0x82, 0x24, 0x25, 0x8f, 0x80, 0x7f];
+        assert_eq!(explode(&input, 13).unwrap(), b"AIAIAIAIAIAIA");
+    }
+
+    #[test]
+    fn rejects_bad_streams() {
+        // coded literals must be 0 or 1
+        assert!(explode(&[0x02, 0x04, 0x00], 0).is_err());
+        // dictionary size must be 4 to 6 bits
+        assert!(explode(&[0x00, 0x07, 0x00], 0).is_err());
+        // ends before the end of stream code
+        assert!(explode(&[0x00, 0x04, 0x82, 0x24], 13).is_err());
+    }
+}
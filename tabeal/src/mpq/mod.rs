@@ -0,0 +1,550 @@
+// reader for MPQ v1 archives, which is all WC3 maps ever use
+// reference: http://www.zezula.net/en/mpq/mpqformat.html
+#![allow(dead_code)]
+
+mod crypt;
+mod explode;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use bzip2::read::BzDecoder;
+use flate2::read::ZlibDecoder;
+
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use self::crypt::*;
+
+type E = LittleEndian;
+
+const ARCHIVE_MAGIC: u32 = 0x1A51_504D; // MPQ\x1A
+const USER_DATA_MAGIC: u32 = 0x1B51_504D; // MPQ\x1B
+// headers are always aligned to this
+const HEADER_ALIGNMENT: u64 = 0x200;
+
+const BLOCK_IMPLODE: u32 = 0x0000_0100;
+const BLOCK_COMPRESS: u32 = 0x0000_0200;
+const BLOCK_ENCRYPTED: u32 = 0x0001_0000;
+const BLOCK_FIX_KEY: u32 = 0x0002_0000;
+const BLOCK_SINGLE_UNIT: u32 = 0x0100_0000;
+const BLOCK_DELETE_MARKER: u32 = 0x0200_0000;
+const BLOCK_SECTOR_CRC: u32 = 0x0400_0000;
+const BLOCK_EXISTS: u32 = 0x8000_0000;
+
+const COMPRESSION_ZLIB: u8 = 0x02;
+const COMPRESSION_IMPLODE: u8 = 0x08;
+const COMPRESSION_BZIP2: u8 = 0x10;
+
+const HASH_ENTRY_EMPTY: u32 = 0xFFFF_FFFF;
+const HASH_ENTRY_DELETED: u32 = 0xFFFF_FFFE;
+
+// sizes in the archive can't be trusted, never reserve more than this up front
+const MAX_PREALLOCATION: usize = 0x0100_0000;
+
+pub const LISTFILE: &str = "(listfile)";
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Header {
+    archive_size: u32,
+    format_version: u16,
+    sector_size: u32,
+    hash_table_position: u32,
+    block_table_position: u32,
+    hash_table_size: u32,
+    block_table_size: u32
+}
+
+#[derive(Clone, Copy, Debug)]
+struct HashEntry {
+    name_a: u32,
+    name_b: u32,
+    locale: u16,
+    platform: u16,
+    block_index: u32
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BlockEntry {
+    pub offset: u32,
+    pub compressed_size: u32,
+    pub file_size: u32,
+    pub flags: u32
+}
+
+impl BlockEntry {
+    pub fn exists(&self) -> bool {
+        self.flags & BLOCK_EXISTS != 0 && self.flags & BLOCK_DELETE_MARKER == 0
+    }
+}
+
+pub struct Archive<R> {
+    reader: R,
+    // where the archive starts in the underlying file, maps put a 512 byte header in front
+    offset: u64,
+    // of the underlying file, nothing can be stored past it
+    length: u64,
+    header: Header,
+    hash_table: Vec<HashEntry>,
+    block_table: Vec<BlockEntry>,
+    crypt: CryptTable
+}
+
+impl Archive<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Archive::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> Archive<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let crypt = CryptTable::new();
+        let length = reader.seek(SeekFrom::End(0))?;
+        let (offset, header) = Self::find_header(&mut reader)?;
+
+        let hash_table = Self::read_table(&mut reader, &crypt, offset + header.hash_table_position as u64, header.hash_table_size, length, "(hash table)")?
+            .chunks(4)
+            .map(|x| HashEntry {
+                name_a: x[0],
+                name_b: x[1],
+                locale: x[2] as u16,
+                platform: (x[2] >> 16) as u16,
+                block_index: x[3]
+            })
+            .collect();
+
+        let block_table = Self::read_table(&mut reader, &crypt, offset + header.block_table_position as u64, header.block_table_size, length, "(block table)")?
+            .chunks(4)
+            .map(|x| BlockEntry {
+                offset: x[0],
+                compressed_size: x[1],
+                file_size: x[2],
+                flags: x[3]
+            })
+            .collect();
+
+        Ok(Archive {
+            reader,
+            offset,
+            length,
+            header,
+            hash_table,
+            block_table,
+            crypt
+        })
+    }
+
+    fn find_header(reader: &mut R) -> io::Result<(u64, Header)> {
+        let length = reader.seek(SeekFrom::End(0))?;
+        let mut position = 0;
+
+        while position + 32 <= length {
+            reader.seek(SeekFrom::Start(position))?;
+            match reader.read_u32::<E>()? {
+                ARCHIVE_MAGIC => return Ok((position, Self::read_header(reader)?)),
+                USER_DATA_MAGIC => {
+                    let _user_data_size = reader.read_u32::<E>()?;
+                    let header_offset = reader.read_u32::<E>()? as u64;
+                    reader.seek(SeekFrom::Start(position + header_offset))?;
+                    if reader.read_u32::<E>()? == ARCHIVE_MAGIC {
+                        return Ok((position + header_offset, Self::read_header(reader)?));
+                    }
+                }
+                _ => {}
+            }
+
+            position += HEADER_ALIGNMENT;
+        }
+
+        Err(invalid("no MPQ header found"))
+    }
+
+    fn read_header(reader: &mut R) -> io::Result<Header> {
+        let _header_size = reader.read_u32::<E>()?;
+        let archive_size = reader.read_u32::<E>()?;
+        let format_version = reader.read_u16::<E>()?;
+        let sector_size_shift = reader.read_u16::<E>()?;
+
+        // some map protectors put garbage here, nothing sane goes beyond this
+        if sector_size_shift > 16 {
+            return Err(invalid("invalid MPQ sector size"));
+        }
+
+        Ok(Header {
+            archive_size,
+            format_version,
+            sector_size: 512 << sector_size_shift,
+            hash_table_position: reader.read_u32::<E>()?,
+            block_table_position: reader.read_u32::<E>()?,
+            hash_table_size: reader.read_u32::<E>()?,
+            block_table_size: reader.read_u32::<E>()?
+        })
+    }
+
+    // both tables are encrypted with a key derived from a fixed name. protectors put bogus
+    // sizes in the header, so only what the archive actually holds is read
+    fn read_table(reader: &mut R, crypt: &CryptTable, position: u64, entries: u32, length: u64, key_name: &str) -> io::Result<Vec<u32>> {
+        let entries = ::std::cmp::min(entries as u64, length.saturating_sub(position) / 16) as usize;
+        let mut data = vec![0u8; entries * 16];
+        reader.seek(SeekFrom::Start(position))?;
+        reader.read_exact(&mut data)?;
+        crypt.decrypt(&mut data, crypt.hash_string(key_name, HASH_FILE_KEY));
+
+        let mut cursor = Cursor::new(data);
+        let mut values = Vec::with_capacity(entries * 4);
+        for _ in 0..entries * 4 {
+            values.push(cursor.read_u32::<E>()?);
+        }
+
+        Ok(values)
+    }
+
+    fn find_block(&self, name: &str) -> Option<&BlockEntry> {
+        // positions depend on the size the header claims, even if less of the table was there
+        let size = self.header.hash_table_size as usize;
+        if size == 0 {
+            return None;
+        }
+
+        let start = self.crypt.hash_string(name, HASH_TABLE_OFFSET) as usize % size;
+        let name_a = self.crypt.hash_string(name, HASH_NAME_A);
+        let name_b = self.crypt.hash_string(name, HASH_NAME_B);
+
+        for i in 0..size {
+            let entry = match self.hash_table.get((start + i) % size) {
+                Some(entry) if entry.block_index != HASH_ENTRY_EMPTY => entry,
+                _ => break
+            };
+
+            if entry.name_a == name_a && entry.name_b == name_b && entry.block_index != HASH_ENTRY_DELETED {
+                return match self.block_table.get(entry.block_index as usize) {
+                    Some(block) if block.exists() => Some(block),
+                    _ => None
+                };
+            }
+        }
+
+        None
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.find_block(name).is_some()
+    }
+
+    pub fn blocks(&self) -> &[BlockEntry] {
+        &self.block_table
+    }
+
+    // names of the files in the archive, only available if it has a listfile
+    pub fn list(&mut self) -> io::Result<Vec<String>> {
+        let listfile = self.read_file(LISTFILE)?;
+
+        Ok(String::from_utf8_lossy(&listfile)
+            .split(|c| c == '\r' || c == '\n' || c == ';')
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_owned())
+            .collect())
+    }
+
+    pub fn read_file(&mut self, name: &str) -> io::Result<Vec<u8>> {
+        let block = *self.find_block(name).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} not found in archive", name)))?;
+
+        let key = if block.flags & BLOCK_ENCRYPTED != 0 {
+            Some(self.crypt.file_key(name, block.offset, block.file_size, block.flags & BLOCK_FIX_KEY != 0))
+        } else {
+            None
+        };
+
+        let position = self.offset + block.offset as u64;
+        let size = ::std::cmp::min(block.compressed_size as u64, self.length.saturating_sub(position));
+        let mut data = vec![0u8; size as usize];
+        self.reader.seek(SeekFrom::Start(position))?;
+        self.reader.read_exact(&mut data)?;
+
+        if block.flags & BLOCK_SINGLE_UNIT != 0 {
+            if let Some(key) = key {
+                self.crypt.decrypt(&mut data, key);
+            }
+
+            return if block.flags & (BLOCK_COMPRESS | BLOCK_IMPLODE) != 0 && (data.len() as u32) < block.file_size {
+                decompress(&data, block.flags, block.file_size as usize)
+            } else {
+                data.truncate(block.file_size as usize);
+                Ok(data)
+            };
+        }
+
+        self.read_sectors(&mut data, &block, key)
+    }
+
+    fn read_sectors(&self, data: &mut [u8], block: &BlockEntry, key: Option<u32>) -> io::Result<Vec<u8>> {
+        let sector_size = self.header.sector_size as usize;
+        let file_size = block.file_size as usize;
+        let sector_count = (file_size + sector_size - 1) / sector_size;
+        let compressed = block.flags & (BLOCK_COMPRESS | BLOCK_IMPLODE) != 0;
+
+        // uncompressed files have no offset table, their sectors simply follow each other
+        let offsets: Vec<usize> = if compressed {
+            let table_length = (sector_count + 1) * 4;
+            if data.len() < table_length {
+                return Err(invalid("truncated MPQ sector table"));
+            }
+
+            let mut table = data[..table_length].to_vec();
+            if let Some(key) = key {
+                self.crypt.decrypt(&mut table, key.wrapping_sub(1));
+            }
+
+            let mut cursor = Cursor::new(table);
+            (0..sector_count + 1).map(|_| cursor.read_u32::<E>().unwrap() as usize).collect()
+        } else {
+            if data.len() < file_size {
+                return Err(invalid("truncated MPQ file"));
+            }
+            (0..sector_count + 1).map(|i| ::std::cmp::min(i * sector_size, file_size)).collect()
+        };
+
+        let mut output = Vec::with_capacity(::std::cmp::min(file_size, MAX_PREALLOCATION));
+        for i in 0..sector_count {
+            let (start, end) = (offsets[i], offsets[i + 1]);
+            if start > end || end > data.len() {
+                return Err(invalid("invalid MPQ sector offset"));
+            }
+
+            let expected = ::std::cmp::min(sector_size, file_size - i * sector_size);
+            let sector = &mut data[start..end];
+
+            if let Some(key) = key {
+                self.crypt.decrypt(sector, key.wrapping_add(i as u32));
+            }
+
+            if compressed && sector.len() < expected {
+                output.extend(decompress(sector, block.flags, expected)?);
+            } else {
+                output.extend_from_slice(sector);
+            }
+        }
+
+        output.truncate(file_size);
+        Ok(output)
+    }
+}
+
+fn decompress(data: &[u8], flags: u32, expected_size: usize) -> io::Result<Vec<u8>> {
+    let expected_size = ::std::cmp::min(expected_size, MAX_PREALLOCATION);
+
+    // old style implode has no compression mask in front
+    if flags & BLOCK_IMPLODE != 0 {
+        return explode::explode(data, expected_size);
+    }
+
+    let (&mask, data) = data.split_first().ok_or_else(|| invalid("empty MPQ sector"))?;
+    let mut data = data.to_vec();
+
+    if mask & !(COMPRESSION_ZLIB | COMPRESSION_IMPLODE | COMPRESSION_BZIP2) != 0 {
+        return Err(invalid("unsupported MPQ compression"));
+    }
+
+    // undone in the reverse order of how they were applied
+    if mask & COMPRESSION_BZIP2 != 0 {
+        let mut output = Vec::with_capacity(expected_size);
+        BzDecoder::new(&data[..]).read_to_end(&mut output)?;
+        data = output;
+    }
+
+    if mask & COMPRESSION_IMPLODE != 0 {
+        data = explode::explode(&data, expected_size)?;
+    }
+
+    if mask & COMPRESSION_ZLIB != 0 {
+        let mut output = Vec::with_capacity(expected_size);
+        ZlibDecoder::new(&data[..]).read_to_end(&mut output)?;
+        data = output;
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use byteorder::WriteBytesExt;
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
+
+    const HASH_TABLE_SIZE: u32 = 16;
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(vec![COMPRESSION_ZLIB], Compression::best());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    // how a file is stored, the way the MPQ editors write it
+    fn store(crypt: &CryptTable, name: &str, contents: &[u8], offset: u32, flags: u32) -> Vec<u8> {
+        let key = crypt.file_key(name, offset, contents.len() as u32, flags & BLOCK_FIX_KEY != 0);
+
+        if flags & BLOCK_SINGLE_UNIT != 0 {
+            let mut data = if flags & BLOCK_COMPRESS != 0 { zlib(contents) } else { contents.to_vec() };
+            if flags & BLOCK_ENCRYPTED != 0 {
+                crypt.encrypt(&mut data, key);
+            }
+            return data;
+        }
+
+        // 512 byte sectors, each compressed on its own behind an offset table
+        let sectors: Vec<&[u8]> = contents.chunks(512).collect();
+        let mut table = Vec::new();
+        let mut data = Vec::new();
+        let mut position = (sectors.len() + 1) * 4;
+
+        for (i, sector) in sectors.iter().enumerate() {
+            table.write_u32::<E>(position as u32).unwrap();
+            let mut sector = zlib(sector);
+            if flags & BLOCK_ENCRYPTED != 0 {
+                crypt.encrypt(&mut sector, key.wrapping_add(i as u32));
+            }
+            position += sector.len();
+            data.extend(sector);
+        }
+        table.write_u32::<E>(position as u32).unwrap();
+
+        if flags & BLOCK_ENCRYPTED != 0 {
+            crypt.encrypt(&mut table, key.wrapping_sub(1));
+        }
+        table.extend(data);
+        table
+    }
+
+    // an archive behind a 512 byte map header, holding the given files
+    fn build(files: &[(&str, &[u8], u32)]) -> Vec<u8> {
+        let crypt = CryptTable::new();
+        let mut body = Vec::new();
+        let mut blocks = Vec::new();
+        let mut hashes = vec![[HASH_ENTRY_EMPTY; 4]; HASH_TABLE_SIZE as usize];
+
+        for (index, &(name, contents, flags)) in files.iter().enumerate() {
+            let offset = 32 + body.len() as u32;
+            let data = store(&crypt, name, contents, offset, flags | BLOCK_EXISTS);
+            blocks.push([offset, data.len() as u32, contents.len() as u32, flags | BLOCK_EXISTS]);
+            body.extend(data);
+
+            let mut slot = crypt.hash_string(name, HASH_TABLE_OFFSET) % HASH_TABLE_SIZE;
+            while hashes[slot as usize][3] != HASH_ENTRY_EMPTY {
+                slot = (slot + 1) % HASH_TABLE_SIZE;
+            }
+            hashes[slot as usize] = [crypt.hash_string(name, HASH_NAME_A), crypt.hash_string(name, HASH_NAME_B), 0, index as u32];
+        }
+
+        let table = |entries: &[[u32; 4]], key_name: &str| {
+            let mut data = Vec::new();
+            for value in entries.iter().flat_map(|x| x.iter()) {
+                data.write_u32::<E>(*value).unwrap();
+            }
+            crypt.encrypt(&mut data, crypt.hash_string(key_name, HASH_FILE_KEY));
+            data
+        };
+        let hash_table = table(&hashes, "(hash table)");
+        let block_table = table(&blocks, "(block table)");
+
+        let hash_table_position = 32 + body.len() as u32;
+        let block_table_position = hash_table_position + hash_table.len() as u32;
+
+        let mut archive = vec![0u8; HEADER_ALIGNMENT as usize];
+        archive.write_u32::<E>(ARCHIVE_MAGIC).unwrap();
+        archive.write_u32::<E>(32).unwrap();
+        archive.write_u32::<E>(block_table_position + block_table.len() as u32).unwrap();
+        archive.write_u16::<E>(0).unwrap();
+        archive.write_u16::<E>(0).unwrap();
+        archive.write_u32::<E>(hash_table_position).unwrap();
+        archive.write_u32::<E>(block_table_position).unwrap();
+        archive.write_u32::<E>(HASH_TABLE_SIZE).unwrap();
+        archive.write_u32::<E>(files.len() as u32).unwrap();
+        archive.extend(body);
+        archive.extend(hash_table);
+        archive.extend(block_table);
+        archive
+    }
+
+    // for tests of what's in the archives, the files are stored as they are
+    pub fn build_plain(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let files: Vec<_> = files.iter().map(|&(name, contents)| (name, contents, BLOCK_SINGLE_UNIT)).collect();
+        build(&files)
+    }
+
+    fn script() -> Vec<u8> {
+        (0..2000).flat_map(|i| format!("call DoNothing() // {}\r\n", i).into_bytes()).collect()
+    }
+
+    #[test]
+    fn reads_files() {
+        let script = script();
+        let files: &[(&str, &[u8], u32)] = &[
+            ("war3map.j", &script, BLOCK_COMPRESS),
+            ("war3map.w3i", b"plain single unit", BLOCK_SINGLE_UNIT),
+            ("Scripts\\common.j", &script, BLOCK_COMPRESS | BLOCK_SINGLE_UNIT),
+            ("war3map.wts", &script, BLOCK_COMPRESS | BLOCK_ENCRYPTED | BLOCK_FIX_KEY),
+            ("(listfile)", b"war3map.j\r\nwar3map.w3i;war3map.wts\r\n", BLOCK_ENCRYPTED | BLOCK_SINGLE_UNIT)
+        ];
+        let mut archive = Archive::new(Cursor::new(build(files))).unwrap();
+
+        for &(name, contents, _) in files {
+            assert_eq!(archive.read_file(name).unwrap(), contents, "{}", name);
+        }
+        assert_eq!(archive.list().unwrap(), vec!["war3map.j", "war3map.w3i", "war3map.wts"]);
+    }
+
+    #[test]
+    fn names_are_case_and_slash_insensitive() {
+        let mut archive = Archive::new(Cursor::new(build(&[("Scripts\\common.j", b"native", BLOCK_SINGLE_UNIT)]))).unwrap();
+
+        assert!(archive.contains("scripts/COMMON.J"));
+        assert_eq!(archive.read_file("SCRIPTS\\Common.j").unwrap(), b"native");
+        assert!(!archive.contains("Scripts\\blizzard.j"));
+        assert_eq!(archive.read_file("Scripts\\blizzard.j").unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn rejects_missing_header() {
+        assert!(Archive::new(Cursor::new(vec![0u8; 4096])).is_err());
+    }
+
+    #[test]
+    fn bogus_table_sizes_are_clamped() {
+        let mut data = build(&[("war3map.j", b"function main takes nothing returns nothing", BLOCK_SINGLE_UNIT)]);
+        // a protector claiming billions of blocks
+        Cursor::new(&mut data[HEADER_ALIGNMENT as usize + 28..]).write_u32::<E>(0xFFFF_FFFF).unwrap();
+
+        let mut archive = Archive::new(Cursor::new(data)).unwrap();
+        assert!(archive.blocks().len() < 16);
+        assert!(archive.read_file("war3map.j").is_ok());
+    }
+
+    #[test]
+    fn bogus_file_sizes_are_clamped() {
+        let script = script();
+        let mut archive = Archive::new(Cursor::new(build(&[("war3map.j", &script, BLOCK_COMPRESS)]))).unwrap();
+        archive.block_table[0].compressed_size = 0xFFFF_FFF0;
+        assert_eq!(archive.read_file("war3map.j").unwrap(), script);
+
+        // claims far more than the sectors hold, which only shows once they're decompressed
+        archive.block_table[0].file_size = 0xFFFF_FFF0;
+        assert!(archive.read_file("war3map.j").is_err());
+
+        let mut archive = Archive::new(Cursor::new(build(&[("war3map.w3i", b"plain", BLOCK_SINGLE_UNIT)]))).unwrap();
+        archive.block_table[0].file_size = 0xFFFF_FFF0;
+        archive.block_table[0].compressed_size = 0xFFFF_FFF0;
+        assert!(archive.read_file("war3map.w3i").is_ok());
+    }
+
+    #[test]
+    fn decompress_rejects_unknown_compression() {
+        assert!(decompress(&[0x01, 0x00], BLOCK_COMPRESS, 16).is_err());
+        assert!(decompress(&[], BLOCK_COMPRESS, 16).is_err());
+        assert_eq!(decompress(&zlib(b"sector"), BLOCK_COMPRESS, 6).unwrap(), b"sector");
+    }
+}
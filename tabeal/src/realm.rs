@@ -0,0 +1,48 @@
+// our end of a realm connection, shared by everything that talks to the realm: packets go out
+// through it and text is read and written in the encoding its codec was set up with
+#![allow(dead_code)]
+
+use futures::{Future, Sink, Stream};
+use futures::sync::mpsc::{self, UnboundedSender};
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+use tokio_io::AsyncRead;
+
+use jekuthiel::BNetPCodec;
+use jekuthiel::packets::{BNetIncomingPacket, BNetOutgoingPacket};
+use jekuthiel::packets::string::Encoding;
+
+use std::io;
+
+#[derive(Clone)]
+pub struct RealmConnection {
+    sender: UnboundedSender<BNetOutgoingPacket>,
+    encoding: Encoding
+}
+
+impl RealmConnection {
+    // frames the socket with the realm's encoding, the returned stream is everything the realm
+    // sends us; whatever goes through the connection is written by a task of its own
+    pub fn new(handle: &Handle, socket: TcpStream, encoding: Encoding) -> (RealmConnection, impl Stream<Item=BNetIncomingPacket, Error=io::Error>) {
+        let codec = BNetPCodec::new(encoding);
+        let (sender, receiver) = mpsc::unbounded();
+        let connection = RealmConnection {
+            sender,
+            encoding: codec.encoding()
+        };
+
+        let (sink, stream) = socket.framed(codec).split();
+        let receiver = receiver.map_err(|_| io::Error::new(io::ErrorKind::Other, "realm queue failed"));
+        handle.spawn(sink.send_all(receiver).map(|_| ()).map_err(|e| warn!("realm: {}", e)));
+
+        (connection, stream)
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn sink(&self) -> impl Sink<SinkItem=BNetOutgoingPacket, SinkError=io::Error> {
+        self.sender.clone().sink_map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "realm connection closed"))
+    }
+}